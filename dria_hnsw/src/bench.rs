@@ -0,0 +1,166 @@
+//! Recall and latency of searches over an index, see `dria_hnsw-cli bench`.
+use crate::db::store::VectorStore;
+use crate::errors::errors::DeserializeError;
+use crate::filter::projection::Projection;
+use crate::hnsw::index::HNSW;
+use crate::hnsw::sync_map::SynchronizedNodes;
+use crate::proto::index_buffer::Point;
+use mini_moka::sync::Cache;
+use rayon::prelude::*;
+use simsimd::SimSIMD;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of points read from the store at once to compute the ground truth.
+const BATCH_SIZE: usize = 10_000;
+
+/// Outcome of searching a set of queries with the same `ef`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub ef: usize,
+    /// Mean fraction of the true nearest neighbors found by a query.
+    pub recall: f64,
+    /// Queries per second, searching one query at a time.
+    pub qps: f64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Exact `k` nearest neighbors of each query by the cosine distance, among the points that are not deleted.
+pub fn ground_truth(
+    store: &dyn VectorStore,
+    queries: &[Vec<f32>],
+    k: usize,
+) -> Result<Vec<Vec<u32>>, DeserializeError> {
+    let datasize = store.get_datasize()?;
+    let mut points = Vec::with_capacity(datasize);
+    for start in (0..datasize).step_by(BATCH_SIZE) {
+        let indices = (start..datasize.min(start + BATCH_SIZE))
+            .map(|idx| idx as u32)
+            .collect::<Vec<u32>>();
+        let nodes = store.get_neighbors(0, indices.clone())?;
+        let batch = store.get_points(&indices)?;
+        points.extend(
+            nodes
+                .iter()
+                .zip(batch)
+                .filter(|(node, _)| node.visible)
+                .map(|(_, point)| (point.idx, point.vector().into_owned())),
+        );
+    }
+
+    Ok(queries
+        .par_iter()
+        .map(|q| {
+            let mut distances = points
+                .iter()
+                .map(|(idx, v)| (SimSIMD::cosine(q, v).unwrap_or(f32::MAX), *idx))
+                .collect::<Vec<(f32, u32)>>();
+            if distances.len() > k {
+                distances.select_nth_unstable_by(k, |a, b| a.0.total_cmp(&b.0));
+                distances.truncate(k);
+            }
+            distances.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+            distances.into_iter().map(|(_, idx)| idx).collect()
+        })
+        .collect())
+}
+
+/// Searches the `k` nearest neighbors of each query one after the other, and compares them to the ground truth.
+///
+/// The caches are shared between runs, as they are between the queries of a server.
+pub fn run(
+    ind: &HNSW,
+    queries: &[Vec<f32>],
+    truth: &[Vec<u32>],
+    k: usize,
+    node_map: Arc<SynchronizedNodes>,
+    point_map: Cache<String, Point>,
+) -> Result<BenchResult, DeserializeError> {
+    let projection = Projection::new(None, None, None, None);
+
+    let mut latencies = Vec::with_capacity(queries.len());
+    let mut recall = 0.0;
+    let start = Instant::now();
+    for (q, truth) in queries.iter().zip(truth) {
+        let query_start = Instant::now();
+        let res = ind.knn_search(q, k, node_map.clone(), point_map.clone(), &projection)?;
+        latencies.push(query_start.elapsed());
+
+        let ids = res
+            .results
+            .iter()
+            .filter_map(|r| r["id"].as_u64().map(|id| id as u32))
+            .collect::<Vec<u32>>();
+        recall += recall_at(&ids, truth, k);
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort();
+    Ok(BenchResult {
+        ef: ind.ef,
+        recall: recall / queries.len().max(1) as f64,
+        qps: queries.len() as f64 / elapsed.as_secs_f64(),
+        p50: percentile(&latencies, 0.50),
+        p95: percentile(&latencies, 0.95),
+        p99: percentile(&latencies, 0.99),
+    })
+}
+
+/// Fraction of the first `k` true neighbors among the results, out of those that exist.
+fn recall_at(results: &[u32], truth: &[u32], k: usize) -> f64 {
+    let truth = &truth[..truth.len().min(k)];
+    if truth.is_empty() {
+        return 1.0;
+    }
+    let found = results.iter().filter(|id| truth.contains(id)).count();
+    found as f64 / truth.len() as f64
+}
+
+/// Value at the given fraction of sorted values, the nearest one if it falls in between.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[i]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory_client::MemoryClient;
+    use crate::proto::index_buffer::{LayerNode, Precision};
+
+    #[test]
+    fn test_recall_and_percentile() {
+        assert_eq!(recall_at(&[1, 2, 3], &[3, 4, 1, 9], 3), 2.0 / 3.0);
+        assert_eq!(recall_at(&[1], &[1], 10), 1.0);
+
+        let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&latencies, 0.5), Duration::from_millis(51));
+        assert_eq!(percentile(&latencies, 0.99), Duration::from_millis(99));
+    }
+
+    #[test]
+    fn test_ground_truth() {
+        let store = MemoryClient::new();
+        let vectors = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![1.0, 0.1],
+            vec![1.0, 0.2],
+        ];
+        store.add_points_batch(&vectors, 0, Precision::F32).unwrap();
+        store.set_datasize(vectors.len()).unwrap();
+        let mut nodes = (0..vectors.len())
+            .map(|idx| LayerNode::new(0, idx))
+            .collect::<Vec<LayerNode>>();
+        nodes[0].visible = false;
+        store.upsert_neighbors(nodes).unwrap();
+
+        let truth = ground_truth(&store, &[vec![1.0, 0.0], vec![0.0, 1.0]], 2).unwrap();
+        assert_eq!(truth, vec![vec![2, 3], vec![1, 3]]);
+    }
+}
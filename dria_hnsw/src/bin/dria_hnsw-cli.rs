@@ -0,0 +1,480 @@
+//! Offline tools for the RocksDB index at `ROCKSDB_PATH`, configured with the same environment variables as the server.
+use actix_web::web::Data;
+use dria_hnsw::bench;
+use dria_hnsw::db::env::Config;
+use dria_hnsw::db::rocksdb_client::RocksdbClient;
+use dria_hnsw::db::store::{new_snapshot_name, VectorStore};
+use dria_hnsw::formats::{fvecs, hnswlib, read_metadata, read_records, Format, Records};
+use dria_hnsw::hnsw::index::HNSW;
+use dria_hnsw::hnsw::options::IndexOptions;
+use dria_hnsw::hnsw::sync_map::SynchronizedNodes;
+use dria_hnsw::middlewares::cache::{NodeCache, PointCache};
+use dria_hnsw::worker::{ef_helper, prepare_quantizer, train_quantizer, train_worker};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage:
+  dria_hnsw-cli build <file> [--format fvecs|npy|jsonl|parquet] [--metadata <file>] [--batch-size <n>]
+  dria_hnsw-cli import <hnswlib-index> [--metadata <file>]
+  dria_hnsw-cli export <hnswlib-index>
+  dria_hnsw-cli bench <queries> [--truth <ivecs>] [--base <file>] [--k <n>] [--levels <l,...> | --ef <ef,...>]
+  dria_hnsw-cli migrate
+  dria_hnsw-cli snapshot [name]
+  dria_hnsw-cli snapshots
+  dria_hnsw-cli restore <name>";
+
+/// Number of vectors inserted at once, the graph is written to the store after each batch.
+const DEFAULT_BATCH_SIZE: usize = 50_000;
+
+/// Number of nearest neighbors searched by a benchmark.
+const DEFAULT_K: usize = 10;
+
+/// Parameters of the graphs built by the server, see `train_worker`.
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 128;
+
+/// A command, run with its parsed arguments.
+type Command = fn(Args) -> io::Result<()>;
+
+struct Args {
+    /// The positional argument, a file or the name of a snapshot.
+    argument: Option<String>,
+    format: Option<Format>,
+    metadata: Option<PathBuf>,
+    batch_size: usize,
+    truth: Option<PathBuf>,
+    base: Option<PathBuf>,
+    k: usize,
+    levels: Option<Vec<usize>>,
+    ef: Option<Vec<usize>>,
+}
+
+impl Args {
+    /// Parses the arguments of a command, which accepts the given options.
+    fn parse(args: &[String], options: &[&str]) -> Result<Args, String> {
+        let mut argument = None;
+        let mut format = None;
+        let mut metadata = None;
+        let mut batch_size = DEFAULT_BATCH_SIZE;
+        let mut truth = None;
+        let mut base = None;
+        let mut k = DEFAULT_K;
+        let mut levels = None;
+        let mut ef = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg.starts_with("--") && !options.contains(&arg.as_str()) {
+                return Err(format!("Unexpected option {}", arg));
+            }
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value of {}", arg))
+            };
+            match arg.as_str() {
+                "--format" => {
+                    let name = value()?;
+                    format = Some(
+                        Format::from_name(name)
+                            .ok_or_else(|| format!("Unsupported format {}", name))?,
+                    );
+                }
+                "--metadata" => metadata = Some(PathBuf::from(value()?)),
+                "--batch-size" => {
+                    batch_size = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or("Batch size should be a positive integer")?;
+                }
+                "--truth" => truth = Some(PathBuf::from(value()?)),
+                "--base" => base = Some(PathBuf::from(value()?)),
+                "--k" => {
+                    k = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or("k should be a positive integer")?;
+                }
+                "--levels" => levels = Some(parse_list(value()?)?),
+                "--ef" => ef = Some(parse_list(value()?)?),
+                _ if argument.is_none() && !arg.starts_with("--") => argument = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument {}", arg)),
+            }
+        }
+
+        Ok(Args {
+            argument,
+            format,
+            metadata,
+            batch_size,
+            truth,
+            base,
+            k,
+            levels,
+            ef,
+        })
+    }
+
+    /// The file given to a command.
+    fn file(&self) -> io::Result<PathBuf> {
+        self.argument
+            .as_ref()
+            .map(PathBuf::from)
+            .ok_or_else(|| error("Missing file"))
+    }
+}
+
+/// Parses a comma-separated list of integers, e.g. `1,2,4`.
+fn parse_list(value: &str) -> Result<Vec<usize>, String> {
+    value
+        .split(',')
+        .map(|n| n.trim().parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| format!("Invalid list of integers {}", value))
+}
+
+fn error(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message.into())
+}
+
+/// Opens the index of the environment, failing if it is written to but the index is a read-only replica.
+fn open_store(cfg: &Config, write: bool) -> io::Result<RocksdbClient> {
+    let rocksdb_client = RocksdbClient::new(cfg.contract_id.clone())
+        .map_err(|e| error(format!("Rocksdb client failed to initialize: {}", e)))?;
+    if write && rocksdb_client.is_read_only() {
+        return Err(error("Can not write to a read-only replica"));
+    }
+    Ok(rocksdb_client)
+}
+
+/// Format of a file, inferred from its extension unless it is given.
+fn file_format(path: &Path, format: Option<Format>) -> io::Result<Format> {
+    match format {
+        Some(format) => Ok(format),
+        None => Format::from_path(path).ok_or_else(|| {
+            error(format!(
+                "Unknown format of {}, it should be one of fvecs, npy, jsonl or parquet",
+                path.display()
+            ))
+        }),
+    }
+}
+
+/// Inserts records into the index in batches, like `insert_vector` does without the HTTP layer.
+fn insert_records(
+    cfg: &Config,
+    store: &Data<dyn VectorStore>,
+    mut records: Records,
+    batch_size: usize,
+) -> io::Result<()> {
+    let point_cache = Data::new(PointCache::new());
+    let start = Instant::now();
+    let mut inserted = 0;
+    loop {
+        let mut vectors = Vec::new();
+        let mut metadata_batch = Vec::new();
+        let mut external_ids = Vec::new();
+        for record in records.by_ref().take(batch_size) {
+            let record = record?;
+            vectors.push(record.vector);
+            metadata_batch.push(record.metadata);
+            external_ids.push(record.external_id);
+        }
+        if vectors.is_empty() {
+            break;
+        }
+
+        let count = vectors.len();
+        let res = train_worker(
+            cfg,
+            vectors,
+            metadata_batch,
+            external_ids,
+            &NodeCache::new(),
+            point_cache.clone(),
+            store.clone(),
+            10_000,
+        )
+        .map_err(|e| error(e.to_string()))?;
+
+        inserted += count;
+        println!(
+            "Inserted {} vectors in {:.1}s, the index has {}",
+            inserted,
+            start.elapsed().as_secs_f64(),
+            res.datasize
+        );
+    }
+
+    Ok(())
+}
+
+/// Inserts the records of a file into the index.
+fn build(args: Args) -> io::Result<()> {
+    let file = args.file()?;
+    let format = file_format(&file, args.format)?;
+    let cfg = Config::new();
+    let rocksdb_client = open_store(&cfg, true)?;
+    let store: Data<dyn VectorStore> = Data::from(Arc::new(rocksdb_client) as Arc<dyn VectorStore>);
+
+    let records = read_records(&file, format, args.metadata.as_deref())?;
+    insert_records(&cfg, &store, records, args.batch_size)
+}
+
+/// Imports an index saved by hnswlib into an empty index, without rebuilding its graph.
+fn import(args: Args) -> io::Result<()> {
+    let file = args.file()?;
+    let cfg = Config::new();
+    let store = open_store(&cfg, true)?;
+    if store.get_num_layers().is_ok() {
+        return Err(error(
+            "Can not import into an index that already has vectors",
+        ));
+    }
+
+    let start = Instant::now();
+    let index = hnswlib::read(File::open(file)?)?;
+    let metadata: Box<dyn Iterator<Item = io::Result<Value>>> = match &args.metadata {
+        Some(path) => Box::new(read_metadata(path)?),
+        None => Box::new(std::iter::repeat_with(|| Ok(json!({})))),
+    };
+    let options = IndexOptions::from_config(&cfg).map_err(|e| error(e.to_string()))?;
+    hnswlib::import(&index, &store, &options, metadata)?;
+
+    // the quantizer is trained as soon as there are enough vectors, as it is on insertion
+    let datasize = index.elements.len();
+    if options.quantization.is_some() && datasize >= options.train_size {
+        train_quantizer(&store, &options, datasize, 10_000).map_err(|e| error(e.to_string()))?;
+    }
+
+    println!(
+        "Imported {} vectors in {:.1}s",
+        datasize,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Exports the index to a file that hnswlib can load.
+fn export(args: Args) -> io::Result<()> {
+    let file = args.file()?;
+    let store = open_store(&Config::new(), false)?;
+
+    let start = Instant::now();
+    let index = hnswlib::export(&store, M, EF_CONSTRUCTION)?;
+    hnswlib::write(&index, File::create(file)?)?;
+
+    println!(
+        "Exported {} vectors in {:.1}s",
+        index.elements.len(),
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Measures the recall & latency of queries over the index, for each `ef` of a sweep.
+///
+/// The index is built from the base vectors first if they are given, otherwise it is the existing index.
+fn bench(args: Args) -> io::Result<()> {
+    let file = args.file()?;
+    let cfg = Config::new();
+    let rocksdb_client = open_store(&cfg, args.base.is_some())?;
+    let store: Data<dyn VectorStore> = Data::from(Arc::new(rocksdb_client) as Arc<dyn VectorStore>);
+
+    if let Some(base) = &args.base {
+        if store.get_num_layers().is_ok() {
+            return Err(error(
+                "Can not build the base vectors into an index that already has vectors, omit --base to benchmark it",
+            ));
+        }
+        let records = read_records(base, file_format(base, None)?, None)?;
+        insert_records(&cfg, &store, records, args.batch_size)?;
+    }
+
+    let queries = read_records(&file, file_format(&file, args.format)?, None)?
+        .map(|record| record.map(|record| record.vector))
+        .collect::<io::Result<Vec<Vec<f32>>>>()?;
+    let truth = match &args.truth {
+        Some(path) => {
+            fvecs::read_ivecs(File::open(path)?).collect::<io::Result<Vec<Vec<u32>>>>()?
+        }
+        None => {
+            let start = Instant::now();
+            let truth = bench::ground_truth(store.get_ref(), &queries, args.k)
+                .map_err(|e| error(e.to_string()))?;
+            println!(
+                "Computed the ground truth of {} queries in {:.1}s",
+                queries.len(),
+                start.elapsed().as_secs_f64()
+            );
+            truth
+        }
+    };
+    if truth.len() < queries.len() {
+        return Err(error(
+            "The ground truth has fewer vectors than there are queries",
+        ));
+    }
+
+    let settings = match (args.ef, args.levels) {
+        (Some(_), Some(_)) => return Err(error("Give either --ef or --levels, not both")),
+        (Some(efs), None) => efs
+            .into_iter()
+            .map(|ef| (format!("ef {}", ef), ef))
+            .collect(),
+        (None, levels) => levels
+            .unwrap_or_else(|| (1..=4).collect())
+            .into_iter()
+            .map(|level| (format!("level {}", level), ef_helper(Some(level))))
+            .collect::<Vec<(String, usize)>>(),
+    };
+
+    let point_cache = PointCache::new();
+    let mut ind = HNSW::new(M, EF_CONSTRUCTION, settings[0].1, None, store.clone());
+    prepare_quantizer(&mut ind, &point_cache, &cfg.contract_id)
+        .map_err(|e| error(e.to_string()))?;
+    let options = store
+        .get_options()
+        .map_err(|e| error(e.to_string()))?
+        .unwrap_or_default();
+    ind.set_rescore(options.rescore);
+    let node_map = Arc::new(SynchronizedNodes::new());
+    let point_map = point_cache.get_cache(cfg.contract_id.clone());
+
+    // a first run warms up the caches, so that every setting is measured as in a long-running server
+    bench::run(
+        &ind,
+        &queries,
+        &truth,
+        args.k,
+        node_map.clone(),
+        point_map.clone(),
+    )
+    .map_err(|e| error(e.to_string()))?;
+
+    println!(
+        "{:<10} {:>6} {:>10} {:>10} {:>9} {:>9} {:>9}",
+        "setting",
+        "ef",
+        format!("recall@{}", args.k),
+        "qps",
+        "p50 ms",
+        "p95 ms",
+        "p99 ms"
+    );
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    for (setting, ef) in settings {
+        ind.set_ef(ef);
+        let res = bench::run(
+            &ind,
+            &queries,
+            &truth,
+            args.k,
+            node_map.clone(),
+            point_map.clone(),
+        )
+        .map_err(|e| error(e.to_string()))?;
+        println!(
+            "{:<10} {:>6} {:>10.4} {:>10.1} {:>9.3} {:>9.3} {:>9.3}",
+            setting,
+            res.ef,
+            res.recall,
+            res.qps,
+            ms(res.p50),
+            ms(res.p95),
+            ms(res.p99)
+        );
+    }
+
+    Ok(())
+}
+
+/// Converts the index to the latest on-disk format.
+fn migrate(_: Args) -> io::Result<()> {
+    let store = open_store(&Config::new(), true)?;
+    let count = store
+        .migrate()
+        .map_err(|e| error(format!("Migration failed: {}", e)))?;
+    println!("Migrated {} keys to the latest format", count);
+    Ok(())
+}
+
+/// Saves a snapshot of the index, named after the current time unless a name is given.
+fn snapshot(args: Args) -> io::Result<()> {
+    let store = open_store(&Config::new(), false)?;
+    let name = args.argument.unwrap_or_else(new_snapshot_name);
+    store
+        .create_snapshot(&name)
+        .map_err(|e| error(format!("Snapshot failed: {}", e)))?;
+    println!("Saved snapshot {}", name);
+    Ok(())
+}
+
+/// Lists the saved snapshots of the index.
+fn snapshots(_: Args) -> io::Result<()> {
+    let store = open_store(&Config::new(), false)?;
+    let names = store
+        .list_snapshots()
+        .map_err(|e| error(format!("Listing snapshots failed: {}", e)))?;
+    names.iter().for_each(|name| println!("{}", name));
+    Ok(())
+}
+
+/// Replaces the index with a saved snapshot.
+fn restore(args: Args) -> io::Result<()> {
+    let name = args
+        .argument
+        .ok_or_else(|| error("Missing snapshot name"))?;
+    let store = open_store(&Config::new(), true)?;
+    store
+        .restore_snapshot(&name)
+        .map_err(|e| error(format!("Restore failed: {}", e)))?;
+    println!("Restored snapshot {}", name);
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let (command, options): (Command, &[&str]) = match args.get(1).map(String::as_str) {
+        Some("build") => (build, &["--format", "--metadata", "--batch-size"]),
+        Some("import") => (import, &["--metadata"]),
+        Some("export") => (export, &[]),
+        Some("bench") => (
+            bench,
+            &[
+                "--truth",
+                "--base",
+                "--k",
+                "--levels",
+                "--ef",
+                "--batch-size",
+            ],
+        ),
+        Some("migrate") => (migrate, &[]),
+        Some("snapshot") => (snapshot, &[]),
+        Some("snapshots") => (snapshots, &[]),
+        Some("restore") => (restore, &[]),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let result = match Args::parse(&args[2..], options) {
+        Ok(args) => command(args),
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::proto::index_buffer::{LayerNode, Point};
+use crate::proto::index_buffer::{LayerNode, Point, PointQuant, Precision};
 use crate::proto::insert_buffer::{BatchStr, BatchVec, SingletonStr, SingletonVec};
 use prost::Message;
 
@@ -28,6 +28,179 @@ pub fn base64_to_node(e_node: &str) -> LayerNode {
     node
 }
 
+pub fn point_quant_to_base64(point: &PointQuant) -> String {
+    let mut bytes = Vec::new();
+    point.encode(&mut bytes).expect("Failed to encode message");
+    base64::encode(&bytes)
+}
+
+pub fn base64_to_point_quant(e_point: &str) -> PointQuant {
+    let bytes = base64::decode(e_point).unwrap();
+    PointQuant::decode(bytes.as_slice()).unwrap()
+}
+
+/// Encodes a vector as base64 of its little-endian `f32` bytes.
+pub fn vector_to_base64(v: &[f32]) -> String {
+    let bytes: Vec<u8> = v.iter().flat_map(|x| x.to_le_bytes()).collect();
+    base64::encode(bytes)
+}
+
+/// Decodes a vector from base64 of its little-endian `f32` bytes.
+pub fn base64_to_vector(e_vector: &str) -> Vec<f32> {
+    let bytes = base64::decode(e_vector).unwrap();
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Converts a vector to little-endian 16-bit floats of the given precision.
+pub fn vector_to_half(v: &[f32], precision: Precision) -> Vec<u8> {
+    v.iter()
+        .flat_map(|x| {
+            match precision {
+                Precision::Bf16 => f32_to_bf16(*x),
+                _ => f32_to_f16(*x),
+            }
+            .to_le_bytes()
+        })
+        .collect()
+}
+
+/// Converts little-endian 16-bit floats of the given precision back to a vector.
+pub fn half_to_vector(h: &[u8], precision: Precision) -> Vec<f32> {
+    h.chunks_exact(2)
+        .map(|b| {
+            let x = u16::from_le_bytes([b[0], b[1]]);
+            match precision {
+                Precision::Bf16 => bf16_to_f32(x),
+                _ => f16_to_f32(x),
+            }
+        })
+        .collect()
+}
+
+/// Converts to IEEE 754 half-precision, rounding to nearest even.
+fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+
+    // infinity & NaN
+    if exp == 0xff {
+        return sign | 0x7c00 | if man != 0 { 0x200 } else { 0 };
+    }
+
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        // too large, becomes infinity
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        // too small, becomes a subnormal or zero
+        if e < -10 {
+            return sign;
+        }
+        let man = man | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let half = man >> shift;
+        let rem = man & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rem > halfway || (rem == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+
+    // a carry from rounding moves into the exponent, which is still correct
+    let half = ((e as u32) << 10) | (man >> 13);
+    let rem = man & 0x1fff;
+    let round = rem > 0x1000 || (rem == 0x1000 && half & 1 == 1);
+    sign | (half + round as u32) as u16
+}
+
+fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let man = (h & 0x3ff) as u32;
+
+    let bits = match exp {
+        0 if man == 0 => sign,
+        0 => {
+            // subnormal, normalized for f32
+            let shift = man.leading_zeros() - 21;
+            let man = (man << shift) & 0x3ff;
+            sign | ((127 - 15 + 1 - shift) << 23) | (man << 13)
+        }
+        0x1f => sign | 0x7f80_0000 | (man << 13),
+        _ => sign | ((exp + 127 - 15) << 23) | (man << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// Converts to bfloat16, i.e. the upper half of `f32`, rounding to nearest even.
+fn f32_to_bf16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    if x.is_nan() {
+        return ((bits >> 16) | 0x40) as u16;
+    }
+    let round = ((bits >> 16) & 1) + 0x7fff;
+    (bits.wrapping_add(round) >> 16) as u16
+}
+
+fn bf16_to_f32(h: u16) -> f32 {
+    f32::from_bits((h as u32) << 16)
+}
+
+//*************** On-disk format ***************//
+
+/// Messages are stored as base64 strings and numbers as decimal strings, as written by HollowDB.
+pub const FORMAT_BASE64: u32 = 1;
+/// Messages are stored as raw protobuf bytes and numbers as little-endian `u64`.
+pub const FORMAT_BINARY: u32 = 2;
+/// Values are stored as in [`FORMAT_BINARY`], but in separate column families with integer-encoded keys.
+pub const FORMAT_COLUMN_FAMILIES: u32 = 3;
+
+/// Returns `true` if a stored message is a base64 string.
+///
+/// Raw protobuf messages start with a field tag, and none of the tags used in our messages is a base64 character,
+/// so both formats can be read regardless of the format of the database.
+pub fn is_base64(value: &[u8]) -> bool {
+    match value.first() {
+        Some(c) => c.is_ascii_alphanumeric() || *c == b'+' || *c == b'/' || *c == b'=',
+        None => false,
+    }
+}
+
+pub fn encode_message<M: Message>(message: &M, format: u32) -> Vec<u8> {
+    match format {
+        FORMAT_BASE64 => base64::encode(message.encode_to_vec()).into_bytes(),
+        _ => message.encode_to_vec(),
+    }
+}
+
+pub fn decode_message<M: Message + Default>(value: &[u8]) -> Option<M> {
+    if is_base64(value) {
+        let bytes = base64::decode(value).ok()?;
+        M::decode(bytes.as_slice()).ok()
+    } else {
+        M::decode(value).ok()
+    }
+}
+
+pub fn encode_number(number: usize, format: u32) -> Vec<u8> {
+    match format {
+        FORMAT_BASE64 => number.to_string().into_bytes(),
+        _ => (number as u64).to_le_bytes().to_vec(),
+    }
+}
+
+pub fn decode_number(value: &[u8], format: u32) -> Option<usize> {
+    match format {
+        FORMAT_BASE64 => std::str::from_utf8(value).ok()?.parse().ok(),
+        _ => Some(u64::from_le_bytes(value.try_into().ok()?) as usize),
+    }
+}
+
 //*************** Batch to Singleton ***************//
 
 pub fn base64_to_batch_vec(batch: &str) -> BatchVec {
@@ -60,13 +233,68 @@ mod tests {
 
     #[test]
     fn test_point_to_base64() {
-        let point = Point {
-            idx: 1,
-            v: vec![1.0, 2.0, 3.0],
-        };
+        let point = Point::new(vec![1.0, 2.0, 3.0], 1);
         let enc = point_to_base64(&point);
         let dec = base64_to_point(&enc);
         assert_eq!(point, dec);
+
+        let point = Point::new_with_precision(vec![1.0, 2.0, 3.0], 1, Precision::F16);
+        let dec = base64_to_point(&point_to_base64(&point));
+        assert_eq!(dec.vector().to_vec(), vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_formats() {
+        let node = LayerNode::new(1, 5);
+        for format in [FORMAT_BASE64, FORMAT_BINARY] {
+            let value = encode_message(&node, format);
+            assert_eq!(is_base64(&value), format == FORMAT_BASE64);
+            assert_eq!(decode_message::<LayerNode>(&value), Some(node.clone()));
+
+            let value = encode_number(12345678, format);
+            assert_eq!(decode_number(&value, format), Some(12345678));
+        }
+
+        // values written by HollowDB
+        let point = Point::new(vec![1.0, 2.0], 0);
+        let value = point_to_base64(&point).into_bytes();
+        assert_eq!(decode_message::<Point>(&value), Some(point));
+        assert_eq!(decode_number(b"42", FORMAT_BASE64), Some(42));
+    }
+
+    #[test]
+    fn test_half_precision() {
+        let v = vec![0.0, -0.0, 1.0, -2.5, 0.1, 65504.0, 1e-7, 1e6, 3.3];
+
+        let f16 = half_to_vector(&vector_to_half(&v, Precision::F16), Precision::F16);
+        assert_eq!(f16[..6], [0.0, -0.0, 1.0, -2.5, 0.099975586, 65504.0]);
+        assert_eq!(f16[6], 1.1920929e-7); // subnormal
+        assert!(f16[7].is_infinite());
+        assert_eq!(f16[8], 3.3007812);
+
+        let bf16 = half_to_vector(&vector_to_half(&v, Precision::Bf16), Precision::Bf16);
+        for (x, y) in v.iter().zip(bf16) {
+            assert!((x - y).abs() <= x.abs() / 128.0);
+        }
+    }
+
+    #[test]
+    fn test_point_quant_to_base64() {
+        let point = PointQuant {
+            idx: 1,
+            v: vec![0, 127, 255],
+        };
+        let enc = point_quant_to_base64(&point);
+        let dec = base64_to_point_quant(&enc);
+        assert_eq!(point, dec);
+    }
+
+    #[test]
+    fn test_vector_to_base64() {
+        let v = vec![1.0, -2.5, 3.25];
+        let enc = vector_to_base64(&v);
+        let dec = base64_to_vector(&enc);
+        assert_eq!(v, dec);
     }
 
     #[test]
@@ -75,7 +303,7 @@ mod tests {
             level: 1,
             idx: 1,
             visible: true,
-            neighbors: std::collections::HashMap::new(),
+            neighbors: std::collections::BTreeMap::new(),
         };
         let enc = node_to_base64(&node);
         let dec = base64_to_node(&enc);
@@ -88,7 +316,7 @@ mod tests {
             level: 1,
             idx: 1,
             visible: true,
-            neighbors: std::collections::HashMap::new(),
+            neighbors: std::collections::BTreeMap::new(),
         };
         let enc = "CAEQARgB".to_string();
         let dec = base64_to_node(&enc);
@@ -12,8 +12,23 @@ pub struct Config {
     logging_level: String,
     pub contract_id: String,
     pub redis_url: String,
+    pub redis_cluster: bool,
+    pub store: String,
     pub port: String,
     pub rocksdb_path: String,
+    pub read_only: bool,
+    pub rocksdb_secondary_path: String,
+    pub snapshot_path: String,
+    pub catch_up_interval: u64,
+    pub quantization: Option<String>,
+    pub quantization_train_size: Option<String>,
+    pub rescore: bool,
+    pub pq_subspaces: Option<String>,
+    pub storage_type: Option<String>,
+    pub extend_candidates: bool,
+    pub keep_pruned_connections: bool,
+    pub rng_seed: Option<u64>,
+    pub serial_build: bool,
 }
 
 impl Config {
@@ -23,6 +38,29 @@ impl Config {
             Err(_) => "/tmp/rocksdb".to_string(),
         };
 
+        let read_only = match env::var("READ_ONLY") {
+            Ok(val) => val == "true",
+            Err(_) => false,
+        };
+
+        let rocksdb_secondary_path = match env::var("ROCKSDB_SECONDARY_PATH") {
+            Ok(val) => val,
+            Err(_) => env::temp_dir()
+                .join(format!("rocksdb-secondary-{}", std::process::id()))
+                .to_string_lossy()
+                .to_string(),
+        };
+
+        let snapshot_path = match env::var("SNAPSHOT_PATH") {
+            Ok(val) => val,
+            Err(_) => format!("{}-snapshots", rocksdb_path),
+        };
+
+        let catch_up_interval = match env::var("CATCH_UP_INTERVAL") {
+            Ok(val) => val.parse().unwrap_or(5),
+            Err(_) => 5,
+        };
+
         let port = match env::var("PORT") {
             Ok(val) => val,
             Err(_) => "8080".to_string(),
@@ -36,6 +74,56 @@ impl Config {
             }
         };
 
+        let quantization = env::var("QUANTIZATION").ok();
+
+        let quantization_train_size = env::var("QUANTIZATION_TRAIN_SIZE").ok();
+
+        let rescore = match env::var("RESCORE") {
+            Ok(val) => val != "false",
+            Err(_) => true,
+        };
+
+        let pq_subspaces = env::var("PQ_SUBSPACES").ok();
+
+        let storage_type = env::var("STORAGE_TYPE").ok();
+
+        let extend_candidates = match env::var("EXTEND_CANDIDATES") {
+            Ok(val) => val == "true",
+            Err(_) => false,
+        };
+
+        let keep_pruned_connections = match env::var("KEEP_PRUNED_CONNECTIONS") {
+            Ok(val) => val != "false",
+            Err(_) => true,
+        };
+
+        // an invalid seed fails at startup, rather than giving a random build that is believed to be reproducible
+        let rng_seed = env::var("RNG_SEED").ok().map(|val| {
+            val.parse::<u64>().unwrap_or_else(|_| {
+                panic!("RNG_SEED should be an unsigned integer, found {:?}", val)
+            })
+        });
+
+        let serial_build = match env::var("SERIAL_BUILD") {
+            Ok(val) => val == "true",
+            Err(_) => false,
+        };
+
+        let store = match env::var("STORE") {
+            Ok(val) => val,
+            Err(_) => "rocksdb".to_string(),
+        };
+
+        let redis_url = match env::var("REDIS_URL") {
+            Ok(val) => val,
+            Err(_) => "redis://127.0.0.1/".to_string(),
+        };
+
+        let redis_cluster = match env::var("REDIS_CLUSTER") {
+            Ok(val) => val == "true",
+            Err(_) => false,
+        };
+
         Config {
             env: "development".to_string(),
             debug: true,
@@ -45,9 +133,24 @@ impl Config {
             global_rate_limit: "".to_string(),
             logging_level: "DEBUG".to_string(),
             contract_id,
-            redis_url: "redis://127.0.0.1/".to_string(),
+            redis_url,
+            redis_cluster,
+            store,
             port,
             rocksdb_path,
+            read_only,
+            rocksdb_secondary_path,
+            snapshot_path,
+            catch_up_interval,
+            quantization,
+            quantization_train_size,
+            rescore,
+            pq_subspaces,
+            storage_type,
+            extend_candidates,
+            keep_pruned_connections,
+            rng_seed,
+            serial_build,
         }
     }
 }
@@ -0,0 +1,241 @@
+use crate::db::store::{VectorStore, WriteLock};
+use crate::errors::errors::DeserializeError;
+use crate::hnsw::options::IndexOptions;
+use crate::hnsw::quantization::Quantizer;
+use crate::proto::index_buffer::{LayerNode, Point, PointQuant, Precision};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError, RwLock};
+
+#[derive(Debug, Default)]
+struct MemoryData {
+    points: HashMap<u32, Point>,
+    codes: HashMap<u32, PointQuant>,
+    nodes: HashMap<(u32, u32), LayerNode>,
+    metadata: BTreeMap<u32, Value>,
+    external_ids: HashMap<String, u32>,
+    external_ids_of: HashMap<u32, String>,
+    headers: HashMap<&'static str, usize>,
+    options: Option<IndexOptions>,
+    quantizer: Option<Quantizer>,
+}
+
+/// A store that keeps the whole index in memory, which is lost when the server stops.
+///
+/// Useful for tests and ephemeral indices.
+#[derive(Debug, Default)]
+pub struct MemoryClient {
+    data: RwLock<MemoryData>,
+    /// Held by writers, see [`VectorStore::lock_writes`].
+    writes: Mutex<()>,
+}
+
+impl MemoryClient {
+    pub fn new() -> MemoryClient {
+        MemoryClient::default()
+    }
+
+    fn get_header(&self, name: &str) -> Result<usize, DeserializeError> {
+        let data = self.data.read().unwrap();
+        data.headers
+            .get(name)
+            .copied()
+            .ok_or(DeserializeError::MissingKey)
+    }
+
+    fn set_header(&self, name: &'static str, value: usize) -> Result<(), DeserializeError> {
+        self.data.write().unwrap().headers.insert(name, value);
+        Ok(())
+    }
+}
+
+impl VectorStore for MemoryClient {
+    fn lock_writes(&self) -> Result<WriteLock<'_>, DeserializeError> {
+        let guard = self.writes.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(WriteLock::new(guard))
+    }
+
+    fn get_neighbors(
+        &self,
+        layer: usize,
+        indices: Vec<u32>,
+    ) -> Result<Vec<LayerNode>, DeserializeError> {
+        let data = self.data.read().unwrap();
+        indices
+            .iter()
+            .map(|&idx| {
+                data.nodes
+                    .get(&(layer as u32, idx))
+                    .cloned()
+                    .ok_or(DeserializeError::MissingKey)
+            })
+            .collect()
+    }
+
+    fn upsert_neighbors(&self, nodes: Vec<LayerNode>) -> Result<(), DeserializeError> {
+        let mut data = self.data.write().unwrap();
+        for node in nodes {
+            data.nodes.insert((node.level, node.idx), node);
+        }
+        Ok(())
+    }
+
+    fn get_points_partial(&self, indices: &[u32]) -> Vec<Result<Point, DeserializeError>> {
+        let data = self.data.read().unwrap();
+        indices
+            .iter()
+            .map(|idx| {
+                data.points
+                    .get(idx)
+                    .cloned()
+                    .ok_or(DeserializeError::MissingKey)
+            })
+            .collect()
+    }
+
+    fn add_points_batch(
+        &self,
+        v: &[Vec<f32>],
+        start_idx: usize,
+        precision: Precision,
+    ) -> Result<(), DeserializeError> {
+        let mut data = self.data.write().unwrap();
+        for (i, p) in v.iter().enumerate() {
+            let idx = start_idx + i;
+            let p = Point::new_with_precision(p.clone(), idx, precision);
+            data.points.insert(idx as u32, p);
+        }
+        Ok(())
+    }
+
+    fn get_codes(&self, indices: &[u32]) -> Result<Vec<PointQuant>, DeserializeError> {
+        let data = self.data.read().unwrap();
+        indices
+            .iter()
+            .map(|idx| {
+                data.codes
+                    .get(idx)
+                    .cloned()
+                    .ok_or(DeserializeError::MissingKey)
+            })
+            .collect()
+    }
+
+    fn add_codes_batch(
+        &self,
+        codes: Vec<Vec<u8>>,
+        start_idx: usize,
+    ) -> Result<(), DeserializeError> {
+        let mut data = self.data.write().unwrap();
+        for (i, code) in codes.into_iter().enumerate() {
+            let idx = start_idx + i;
+            data.codes.insert(idx as u32, PointQuant::new(code, idx));
+        }
+        Ok(())
+    }
+
+    fn get_datasize(&self) -> Result<usize, DeserializeError> {
+        self.get_header("datasize")
+    }
+
+    fn set_datasize(&self, datasize: usize) -> Result<(), DeserializeError> {
+        self.set_header("datasize", datasize)
+    }
+
+    fn get_num_layers(&self) -> Result<usize, DeserializeError> {
+        self.get_header("num_layers")
+    }
+
+    fn set_num_layers(&self, num_layers: usize) -> Result<(), DeserializeError> {
+        self.set_header("num_layers", num_layers)
+    }
+
+    fn get_ep(&self) -> Result<usize, DeserializeError> {
+        self.get_header("ep")
+    }
+
+    fn set_ep(&self, ep: usize) -> Result<(), DeserializeError> {
+        self.set_header("ep", ep)
+    }
+
+    fn get_options(&self) -> Result<Option<IndexOptions>, DeserializeError> {
+        Ok(self.data.read().unwrap().options.clone())
+    }
+
+    fn set_options(&self, options: &IndexOptions) -> Result<(), DeserializeError> {
+        self.data.write().unwrap().options = Some(options.clone());
+        Ok(())
+    }
+
+    fn get_quantizer(&self) -> Result<Option<Quantizer>, DeserializeError> {
+        Ok(self.data.read().unwrap().quantizer.clone())
+    }
+
+    fn set_quantizer(&self, quantizer: &Quantizer) -> Result<(), DeserializeError> {
+        self.data.write().unwrap().quantizer = Some(quantizer.clone());
+        Ok(())
+    }
+
+    fn get_metadatas_partial(&self, indices: &[u32]) -> Vec<Result<Value, DeserializeError>> {
+        let data = self.data.read().unwrap();
+        indices
+            .iter()
+            .map(|idx| {
+                data.metadata
+                    .get(idx)
+                    .cloned()
+                    .ok_or(DeserializeError::MissingKey)
+            })
+            .collect()
+    }
+
+    fn set_metadata_batch(&self, metadata: Vec<Value>, idx: usize) -> Result<(), DeserializeError> {
+        let mut data = self.data.write().unwrap();
+        for (i, m) in metadata.into_iter().enumerate() {
+            data.metadata.insert((idx + i) as u32, m);
+        }
+        Ok(())
+    }
+
+    fn get_indices_by_external_ids(
+        &self,
+        external_ids: &[String],
+    ) -> Result<Vec<Option<u32>>, DeserializeError> {
+        let data = self.data.read().unwrap();
+        Ok(external_ids
+            .iter()
+            .map(|x| data.external_ids.get(x).copied())
+            .collect())
+    }
+
+    fn get_external_ids(&self, indices: &[u32]) -> Result<Vec<Option<String>>, DeserializeError> {
+        let data = self.data.read().unwrap();
+        Ok(indices
+            .iter()
+            .map(|idx| data.external_ids_of.get(idx).cloned())
+            .collect())
+    }
+
+    fn set_external_ids_batch(
+        &self,
+        external_ids: &[(String, u32)],
+    ) -> Result<(), DeserializeError> {
+        let mut data = self.data.write().unwrap();
+        for (external_id, idx) in external_ids {
+            data.external_ids.insert(external_id.clone(), *idx);
+            data.external_ids_of.insert(*idx, external_id.clone());
+        }
+        Ok(())
+    }
+
+    fn delete_records(&self, indices: &[u32]) -> Result<(), DeserializeError> {
+        let mut data = self.data.write().unwrap();
+        for idx in indices {
+            data.metadata.remove(idx);
+            if let Some(external_id) = data.external_ids_of.remove(idx) {
+                data.external_ids.remove(&external_id);
+            }
+        }
+        Ok(())
+    }
+}
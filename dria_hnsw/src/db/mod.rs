@@ -1,4 +1,6 @@
 pub mod conversions;
 pub mod env;
+pub mod memory_client;
 pub mod redis_client;
 pub mod rocksdb_client;
+pub mod store;
@@ -1,285 +1,652 @@
 extern crate redis;
-use redis::{Client, Commands, Connection};
+use r2d2::Pool;
+use redis::cluster::{cluster_pipe, ClusterClient};
+use redis::{Client, ConnectionLike, Script};
 
-use crate::db::conversions::{base64_to_node, node_to_base64, point_to_base64};
+use crate::db::conversions::{
+    decode_message, decode_number, encode_message, encode_number, FORMAT_BASE64,
+};
 use crate::db::env::Config;
+use crate::db::store::{VectorStore, WriteLock};
 use crate::errors::errors::DeserializeError;
-use crate::proto::index_buffer::{LayerNode, Point};
-use prost::Message;
+use crate::hnsw::options::IndexOptions;
+use crate::hnsw::quantization::Quantizer;
+use crate::proto::index_buffer::{LayerNode, Point, PointQuant, Precision};
+use log::error;
 use serde_json::Value;
+use std::fmt::Display;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long the write lock of an index is held without being renewed, in case its holder stops without releasing it.
+const LOCK_TTL_MS: u64 = 30 * 1000;
+/// How often the holder of the write lock renews it, well within [`LOCK_TTL_MS`].
+const LOCK_RENEWAL: Duration = Duration::from_millis(LOCK_TTL_MS / 3);
+/// How long a writer waits before trying to take a held write lock again.
+const LOCK_RETRY: Duration = Duration::from_millis(50);
+
+/// Deletes the write lock only if it is still held with the given token, i.e. it has not expired and been taken by another writer.
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Extends the write lock only if it is still held with the given token.
+const RENEW_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+#[derive(Clone)]
+enum RedisPool {
+    Single(Pool<Client>),
+    Cluster(Pool<ClusterClient>),
+}
 
+/// Runs a pipeline built by `$build` over a connection of either kind of pool.
+///
+/// Multi-key commands such as `MGET` can not span the slots of a cluster, so each key is a command of its own.
+macro_rules! pipeline {
+    ($pool:expr, |$pipe:ident| $build:block) => {
+        match $pool {
+            RedisPool::Single(pool) => {
+                let mut connection = pool
+                    .get()
+                    .map_err(|_| DeserializeError::RedisConnectionError)?;
+                let mut $pipe = redis::pipe();
+                $build;
+                $pipe.query(&mut *connection)
+            }
+            RedisPool::Cluster(pool) => {
+                let mut connection = pool
+                    .get()
+                    .map_err(|_| DeserializeError::RedisConnectionError)?;
+                let mut $pipe = cluster_pipe();
+                $build;
+                $pipe.query(&mut *connection)
+            }
+        }
+    };
+}
+
+/// A store over Redis, or a Redis cluster, with the same keys & values as HollowDB,
+/// i.e. `{tag}.value.{key}` with base64 messages and decimal numbers.
+///
+/// Queries run concurrently over a pool of connections.
 pub struct RedisClient {
-    client: Client,
-    connection: Connection,
+    pool: RedisPool,
     tag: String,
+    /// Held by the writers of this server, before they take the lock shared with other servers.
+    writes: Mutex<()>,
+}
+
+/// The write lock of an index, shared by all servers of a store; see [`VectorStore::lock_writes`].
+struct RedisLock<'a> {
+    client: &'a RedisClient,
+    token: String,
+    /// Renews the lock for as long as it is held, see [`renew_lock`].
+    renewal: Option<(Sender<()>, JoinHandle<()>)>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl Drop for RedisLock<'_> {
+    fn drop(&mut self) {
+        if let Some((stop, renewal)) = self.renewal.take() {
+            drop(stop);
+            let _ = renewal.join();
+        }
+        let script = Script::new(UNLOCK_SCRIPT);
+        let res: Result<i64, DeserializeError> = self.client.with_connection(|c| {
+            script
+                .key(self.client.key("lock"))
+                .arg(&self.token)
+                .invoke(c)
+                .map_err(|_| DeserializeError::RedisConnectionError)
+        });
+        if let Err(e) = res {
+            error!(
+                "Error releasing the write lock, it expires in {}ms: {}",
+                LOCK_TTL_MS, e
+            );
+        }
+    }
+}
+
+/// Renews the write lock held with the given token, so that it does not expire however long the write takes,
+/// until the returned sender is dropped.
+fn renew_lock(pool: RedisPool, key: String, token: String) -> (Sender<()>, JoinHandle<()>) {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let renewal = thread::spawn(move || {
+        let script = Script::new(RENEW_SCRIPT);
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(LOCK_RENEWAL) {
+            let renewed: Result<i64, DeserializeError> = pool.with_connection(|c| {
+                script
+                    .key(&key)
+                    .arg(&token)
+                    .arg(LOCK_TTL_MS)
+                    .invoke(c)
+                    .map_err(|_| DeserializeError::RedisConnectionError)
+            });
+            match renewed {
+                Ok(1) => {}
+                Ok(_) => {
+                    error!("The write lock expired before it was renewed, another server may be writing");
+                    break;
+                }
+                Err(e) => error!("Error renewing the write lock: {}", e),
+            }
+        }
+    });
+    (stop, renewal)
+}
+
+impl RedisPool {
+    /// Runs single commands over a connection of the pool.
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&mut dyn ConnectionLike) -> Result<T, DeserializeError>,
+    ) -> Result<T, DeserializeError> {
+        match self {
+            RedisPool::Single(pool) => {
+                let mut connection = pool
+                    .get()
+                    .map_err(|_| DeserializeError::RedisConnectionError)?;
+                f(&mut *connection)
+            }
+            RedisPool::Cluster(pool) => {
+                let mut connection = pool
+                    .get()
+                    .map_err(|_| DeserializeError::RedisConnectionError)?;
+                f(&mut *connection)
+            }
+        }
+    }
 }
 
 impl RedisClient {
     pub fn new(contract_id: String) -> Result<RedisClient, DeserializeError> {
         let cfg = Config::new();
-        let client =
-            Client::open(cfg.redis_url).map_err(|_| DeserializeError::RedisConnectionError)?;
-        let connection = client
-            .get_connection()
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
+        RedisClient::open(&cfg.redis_url, cfg.redis_cluster, contract_id)
+    }
+
+    /// Connects to the Redis server at the given URL, or to a cluster through its comma-separated nodes.
+    pub fn open(
+        url: &str,
+        cluster: bool,
+        contract_id: String,
+    ) -> Result<RedisClient, DeserializeError> {
+        let pool = if cluster {
+            let nodes = url
+                .split(',')
+                .map(|node| node.trim())
+                .collect::<Vec<&str>>();
+            let client =
+                ClusterClient::new(nodes).map_err(|_| DeserializeError::RedisConnectionError)?;
+            RedisPool::Cluster(
+                Pool::builder()
+                    .build(client)
+                    .map_err(|_| DeserializeError::RedisConnectionError)?,
+            )
+        } else {
+            let client = Client::open(url).map_err(|_| DeserializeError::RedisConnectionError)?;
+            RedisPool::Single(
+                Pool::builder()
+                    .build(client)
+                    .map_err(|_| DeserializeError::RedisConnectionError)?,
+            )
+        };
 
         Ok(RedisClient {
-            client,
-            connection,
+            pool,
             tag: contract_id,
+            writes: Mutex::new(()),
         })
     }
 
-    pub fn set(&mut self, key: String, value: String) -> Result<(), DeserializeError> {
-        let keys_local = format!("{}.value.{}", self.tag, key);
-        let _: () = self
-            .connection
-            .set(&keys_local, &value)
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        Ok(())
+    /// Runs single commands over a connection of the pool.
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&mut dyn ConnectionLike) -> Result<T, DeserializeError>,
+    ) -> Result<T, DeserializeError> {
+        self.pool.with_connection(f)
     }
 
-    pub fn get_neighbor(
-        &mut self,
-        layer: usize,
-        idx: usize,
-    ) -> Result<LayerNode, DeserializeError> {
-        let key = format!(
-            "{}.value.{}:{}",
-            self.tag,
-            layer.to_string(),
-            idx.to_string()
-        );
+    fn key(&self, name: impl Display) -> String {
+        format!("{}.value.{}", self.tag, name)
+    }
+
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<Vec<u8>>>, DeserializeError> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
 
-        let node_str = match self
-            .connection
-            .get::<_, String>(key)
-            .map_err(|_| DeserializeError::RedisConnectionError)
-        {
-            Ok(node_str) => node_str,
-            Err(_) => {
-                return Err(DeserializeError::RedisConnectionError);
+        pipeline!(&self.pool, |pipe| {
+            for key in keys.iter() {
+                pipe.get(key);
             }
-        };
+        })
+        .map_err(|_| DeserializeError::RedisConnectionError)
+    }
 
-        //let node_:Value = serde_json::from_str(&node_str).unwrap();
-        Ok(base64_to_node(&node_str))
+    fn set_many(&self, pairs: Vec<(String, Vec<u8>)>) -> Result<(), DeserializeError> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+
+        pipeline!(&self.pool, |pipe| {
+            for (key, value) in pairs.iter() {
+                pipe.set(key, value).ignore();
+            }
+        })
+        .map_err(|_| DeserializeError::RedisConnectionError)
     }
 
-    pub fn get_neighbors(
-        &mut self,
-        layer: usize,
-        indices: Vec<u32>,
-    ) -> Result<Vec<LayerNode>, DeserializeError> {
-        let keys = indices
-            .iter()
-            .map(|x| format!("{}.value.{}:{}", self.tag, layer.to_string(), x.to_string()))
-            .collect::<Vec<String>>();
+    fn delete_many(&self, keys: Vec<String>) -> Result<(), DeserializeError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
 
-        let values_str: Vec<String> = self
-            .connection
-            .mget(keys)
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
+        pipeline!(&self.pool, |pipe| {
+            for key in keys.iter() {
+                pipe.del(key).ignore();
+            }
+        })
+        .map_err(|_| DeserializeError::RedisConnectionError)
+    }
 
-        let neighbors = values_str
-            .iter()
-            .map(|s| {
-                let bytes = base64::decode(s).unwrap();
-                let p = LayerNode::decode(bytes.as_slice()).unwrap(); // Deserialize
-                Ok(p)
-            })
-            .collect::<Result<Vec<LayerNode>, DeserializeError>>()?;
+    fn get_number(&self, name: &str) -> Result<usize, DeserializeError> {
+        match self.get_many(vec![self.key(name)])?.pop().flatten() {
+            Some(value) => {
+                decode_number(&value, FORMAT_BASE64).ok_or(DeserializeError::InvalidForm)
+            }
+            None => Err(DeserializeError::MissingKey),
+        }
+    }
 
-        Ok(neighbors)
+    fn set_number(&self, name: &str, number: usize) -> Result<(), DeserializeError> {
+        self.set_many(vec![(self.key(name), encode_number(number, FORMAT_BASE64))])
     }
 
-    pub fn upsert_neighbor(&mut self, node: LayerNode) -> Result<(), DeserializeError> {
-        let key = format!("{}.value.{}:{}", self.tag, node.level, node.idx);
+    fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Result<Option<T>, DeserializeError> {
+        match self.get_many(vec![self.key(name)])?.pop().flatten() {
+            Some(v) => serde_json::from_slice(&v)
+                .map(Some)
+                .map_err(|_| DeserializeError::InvalidForm),
+            None => Ok(None),
+        }
+    }
+
+    fn set_json<T: serde::Serialize>(&self, name: &str, value: &T) -> Result<(), DeserializeError> {
+        self.set_many(vec![(self.key(name), serde_json::to_vec(value).unwrap())])
+    }
+}
 
-        let node_str = node_to_base64(&node);
-        self.set(key, node_str)?;
+impl VectorStore for RedisClient {
+    /// Takes a lock shared by all servers of the store, which is renewed while it is held
+    /// and expires after [`LOCK_TTL_MS`] if its holder stops without releasing it.
+    fn lock_writes(&self) -> Result<WriteLock<'_>, DeserializeError> {
+        let guard = self.writes.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let key = self.key("lock");
+        let token = format!("{:032x}", rand::random::<u128>());
+        loop {
+            let locked: Option<String> = self.with_connection(|c| {
+                redis::cmd("SET")
+                    .arg(&key)
+                    .arg(&token)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(LOCK_TTL_MS)
+                    .query(c)
+                    .map_err(|_| DeserializeError::RedisConnectionError)
+            })?;
+            if locked.is_some() {
+                break;
+            }
+            thread::sleep(LOCK_RETRY);
+        }
 
-        Ok(())
+        let renewal = renew_lock(self.pool.clone(), key, token.clone());
+        Ok(WriteLock::new(RedisLock {
+            client: self,
+            token,
+            renewal: Some(renewal),
+            _guard: guard,
+        }))
     }
 
-    pub fn upsert_neighbors(&mut self, nodes: Vec<LayerNode>) -> Result<(), DeserializeError> {
-        let mut pairs = Vec::new();
-        for node in nodes {
-            let key = format!("{}.value.{}:{}", self.tag, node.level, node.idx);
-            let node_str = node_to_base64(&node);
-            pairs.push((key, node_str));
+    fn get_generation(&self) -> Result<u64, DeserializeError> {
+        match self.get_number("generation") {
+            Ok(generation) => Ok(generation as u64),
+            Err(DeserializeError::MissingKey) => Ok(0),
+            Err(e) => Err(e),
         }
+    }
 
-        let _ = self
-            .connection
-            .mset(pairs.as_slice())
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
+    fn bump_generation(&self) -> Result<u64, DeserializeError> {
+        let key = self.key("generation");
+        self.with_connection(|c| {
+            redis::cmd("INCR")
+                .arg(&key)
+                .query(c)
+                .map_err(|_| DeserializeError::RedisConnectionError)
+        })
+    }
 
-        Ok(())
+    fn is_shared(&self) -> bool {
+        true
     }
 
-    pub fn get_points(&mut self, indices: &Vec<u32>) -> Result<Vec<Point>, DeserializeError> {
+    fn get_neighbors(
+        &self,
+        layer: usize,
+        indices: Vec<u32>,
+    ) -> Result<Vec<LayerNode>, DeserializeError> {
         let keys = indices
             .iter()
-            .map(|x| format!("{}.value.{}", self.tag, x))
+            .map(|x| self.key(format!("{}:{}", layer, x)))
             .collect::<Vec<String>>();
 
-        if keys.is_empty() {
-            return Ok(vec![]);
+        self.get_many(keys)?
+            .into_iter()
+            .map(|value| match value {
+                Some(v) => decode_message(&v).ok_or(DeserializeError::InvalidForm),
+                None => Err(DeserializeError::MissingKey),
+            })
+            .collect()
+    }
+
+    fn upsert_neighbors(&self, nodes: Vec<LayerNode>) -> Result<(), DeserializeError> {
+        let pairs = nodes
+            .iter()
+            .map(|node| {
+                let key = self.key(format!("{}:{}", node.level, node.idx));
+                (key, encode_message(node, FORMAT_BASE64))
+            })
+            .collect();
+        self.set_many(pairs)
+    }
+
+    fn get_points_partial(&self, indices: &[u32]) -> Vec<Result<Point, DeserializeError>> {
+        let keys = indices.iter().map(|x| self.key(x)).collect::<Vec<String>>();
+
+        match self.get_many(keys) {
+            Ok(values) => values
+                .into_iter()
+                .map(|value| match value {
+                    Some(v) => decode_message(&v).ok_or(DeserializeError::InvalidForm),
+                    None => Err(DeserializeError::MissingKey),
+                })
+                .collect(),
+            Err(_) => indices
+                .iter()
+                .map(|_| Err(DeserializeError::RedisConnectionError))
+                .collect(),
         }
-        let values_str: Vec<String> = self
-            .connection
-            .mget(keys)
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
+    }
 
-        let points = values_str
+    fn add_points_batch(
+        &self,
+        v: &[Vec<f32>],
+        start_idx: usize,
+        precision: Precision,
+    ) -> Result<(), DeserializeError> {
+        let pairs = v
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let idx = start_idx + i;
+                let p = Point::new_with_precision(p.clone(), idx, precision);
+                (self.key(idx), encode_message(&p, FORMAT_BASE64))
+            })
+            .collect();
+        self.set_many(pairs)
+    }
+
+    fn get_codes(&self, indices: &[u32]) -> Result<Vec<PointQuant>, DeserializeError> {
+        let keys = indices
+            .iter()
+            .map(|x| self.key(format!("q:{}", x)))
+            .collect::<Vec<String>>();
+
+        self.get_many(keys)?
             .into_iter()
-            .map(|s| {
-                let bytes = base64::decode(s).unwrap();
-                let p = Point::decode(bytes.as_slice()).unwrap(); // Deserialize
-                Ok(p)
+            .map(|value| match value {
+                Some(v) => decode_message(&v).ok_or(DeserializeError::InvalidForm),
+                None => Err(DeserializeError::MissingKey),
             })
-            .collect::<Result<Vec<Point>, DeserializeError>>()?;
-        Ok(points)
+            .collect()
     }
 
-    pub fn add_points_batch(
-        &mut self,
-        v: &Vec<Vec<f32>>,
+    fn add_codes_batch(
+        &self,
+        codes: Vec<Vec<u8>>,
         start_idx: usize,
     ) -> Result<(), DeserializeError> {
-        let mut pairs = Vec::new();
+        let pairs = codes
+            .into_iter()
+            .enumerate()
+            .map(|(i, code)| {
+                let idx = start_idx + i;
+                let p = PointQuant::new(code, idx);
+                (
+                    self.key(format!("q:{}", idx)),
+                    encode_message(&p, FORMAT_BASE64),
+                )
+            })
+            .collect();
+        self.set_many(pairs)
+    }
 
-        for (i, p) in v.iter().enumerate() {
-            let idx = start_idx + i;
-            let p = Point::new(p.clone(), idx);
-            let p_str = point_to_base64(&p);
-            pairs.push((format!("{}.value.{}", self.tag, idx), p_str));
-        }
+    fn get_datasize(&self) -> Result<usize, DeserializeError> {
+        self.get_number("datasize")
+    }
 
-        let _ = self
-            .connection
-            .mset(pairs.as_slice())
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        Ok(())
+    fn set_datasize(&self, datasize: usize) -> Result<(), DeserializeError> {
+        self.set_number("datasize", datasize)
     }
 
-    pub fn add_points(&mut self, v: Vec<f32>, idx: usize) -> Result<(), DeserializeError> {
-        let p = Point::new(v, idx);
-        let p_str = point_to_base64(&p);
+    fn get_num_layers(&self) -> Result<usize, DeserializeError> {
+        self.get_number("num_layers")
+    }
 
-        self.set(format!("{}.value.{}", self.tag, idx), p_str)?;
-        Ok(())
+    fn set_num_layers(&self, num_layers: usize) -> Result<(), DeserializeError> {
+        self.set_number("num_layers", num_layers)
     }
 
-    pub fn set_datasize(&mut self, datasize: usize) -> Result<(), DeserializeError> {
-        let _: () = self
-            .connection
-            .set(format!("{}.value.datasize", self.tag), datasize.to_string())
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        Ok(())
+    fn get_ep(&self) -> Result<usize, DeserializeError> {
+        self.get_number("ep")
     }
 
-    pub fn get_datasize(&mut self) -> Result<usize, DeserializeError> {
-        let datasize: String = self
-            .connection
-            .get(format!("{}.value.datasize", self.tag))
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        let datasize = datasize.parse::<usize>().unwrap();
-        Ok(datasize)
+    fn set_ep(&self, ep: usize) -> Result<(), DeserializeError> {
+        self.set_number("ep", ep)
     }
 
-    pub fn set_num_layers(&mut self, num_layers: usize) -> Result<(), DeserializeError> {
-        let _: () = self
-            .connection
-            .set(
-                format!("{}.value.num_layers", self.tag),
-                num_layers.to_string(),
-            )
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        Ok(())
+    fn get_options(&self) -> Result<Option<IndexOptions>, DeserializeError> {
+        self.get_json("options")
     }
 
-    pub fn get_num_layers(&mut self) -> Result<usize, DeserializeError> {
-        let num_layers: String = self
-            .connection
-            .get(format!("{}.value.num_layers", self.tag))
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        let num_layers = num_layers.parse::<usize>().unwrap();
-        Ok(num_layers)
+    fn set_options(&self, options: &IndexOptions) -> Result<(), DeserializeError> {
+        self.set_json("options", options)
     }
 
-    pub fn set_ep(&mut self, ep: usize) -> Result<(), DeserializeError> {
-        let _: () = self
-            .connection
-            .set(format!("{}.value.ep", self.tag), ep.to_string())
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        Ok(())
+    fn get_quantizer(&self) -> Result<Option<Quantizer>, DeserializeError> {
+        self.get_json("quantizer")
     }
 
-    pub fn get_ep(&mut self) -> Result<usize, DeserializeError> {
-        let ep: String = self
-            .connection
-            .get(format!("{}.value.ep", self.tag))
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        let ep = ep.parse::<usize>().unwrap();
-        Ok(ep)
+    fn set_quantizer(&self, quantizer: &Quantizer) -> Result<(), DeserializeError> {
+        self.set_json("quantizer", quantizer)
     }
 
-    pub fn set_metadata_batch(
-        &mut self,
-        metadata: Vec<Value>,
-        idx: usize,
-    ) -> Result<(), DeserializeError> {
-        let mut pairs = Vec::new();
-        for (i, m) in metadata.iter().enumerate() {
-            let key = format!("{}.value.m:{}", self.tag, idx + i);
-            let metadata_str = serde_json::to_string(&m).unwrap();
-            pairs.push((key, metadata_str));
+    fn get_metadatas_partial(&self, indices: &[u32]) -> Vec<Result<Value, DeserializeError>> {
+        let keys = indices
+            .iter()
+            .map(|x| self.key(format!("m:{}", x)))
+            .collect::<Vec<String>>();
+
+        match self.get_many(keys) {
+            Ok(values) => values
+                .into_iter()
+                .map(|value| match value {
+                    Some(v) => serde_json::from_slice::<Value>(&v)
+                        .map_err(|_| DeserializeError::InvalidForm),
+                    None => Err(DeserializeError::MissingKey),
+                })
+                .collect(),
+            Err(_) => indices
+                .iter()
+                .map(|_| Err(DeserializeError::RedisConnectionError))
+                .collect(),
         }
-        let _ = self
-            .connection
-            .mset(pairs.as_slice())
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        Ok(())
     }
 
-    pub fn set_metadata(&mut self, metadata: Value, idx: usize) -> Result<(), DeserializeError> {
-        let key = format!("{}.value.m:{}", self.tag, idx);
-        let metadata_str = serde_json::to_string(&metadata).unwrap();
-        self.set(key, metadata_str)?;
-        Ok(())
+    fn set_metadata_batch(&self, metadata: Vec<Value>, idx: usize) -> Result<(), DeserializeError> {
+        let pairs = metadata
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let key = self.key(format!("m:{}", idx + i));
+                (key, serde_json::to_vec(m).unwrap())
+            })
+            .collect();
+        self.set_many(pairs)
     }
 
-    pub fn get_metadata(&mut self, idx: usize) -> Result<Value, DeserializeError> {
-        let key = format!("{}.value.m:{}", self.tag, idx);
-        let metadata_str: String = self
-            .connection
-            .get(&key)
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-        let metadata: Value = serde_json::from_str(&metadata_str).unwrap();
-        Ok(metadata)
+    fn get_indices_by_external_ids(
+        &self,
+        external_ids: &[String],
+    ) -> Result<Vec<Option<u32>>, DeserializeError> {
+        let keys = external_ids
+            .iter()
+            .map(|x| self.key(format!("x:{}", x)))
+            .collect::<Vec<String>>();
+
+        self.get_many(keys)?
+            .into_iter()
+            .map(|value| match value {
+                Some(v) => std::str::from_utf8(&v)
+                    .ok()
+                    .and_then(|idx| idx.parse::<u32>().ok())
+                    .map(Some)
+                    .ok_or(DeserializeError::InvalidForm),
+                None => Ok(None),
+            })
+            .collect()
     }
 
-    pub fn get_metadatas(&mut self, indices: Vec<u32>) -> Result<Vec<Value>, DeserializeError> {
+    fn get_external_ids(&self, indices: &[u32]) -> Result<Vec<Option<String>>, DeserializeError> {
         let keys = indices
             .iter()
-            .map(|x| format!("{}.value.m:{}", self.tag, x))
+            .map(|x| self.key(format!("xi:{}", x)))
             .collect::<Vec<String>>();
 
-        let metadata_str: Vec<String> = self
-            .connection
-            .mget(&keys)
-            .map_err(|_| DeserializeError::RedisConnectionError)?;
-
-        let metadata = metadata_str
+        self.get_many(keys)?
             .into_iter()
-            .map(|s| {
-                let m: Value = serde_json::from_str(&s).unwrap();
-                Ok(m)
+            .map(|value| match value {
+                Some(v) => String::from_utf8(v)
+                    .map(Some)
+                    .map_err(|_| DeserializeError::InvalidForm),
+                None => Ok(None),
             })
-            .collect::<Result<Vec<Value>, DeserializeError>>()?;
+            .collect()
+    }
 
-        Ok(metadata)
+    fn set_external_ids_batch(
+        &self,
+        external_ids: &[(String, u32)],
+    ) -> Result<(), DeserializeError> {
+        let mut pairs = Vec::new();
+        for (external_id, idx) in external_ids {
+            pairs.push((
+                self.key(format!("x:{}", external_id)),
+                idx.to_string().into_bytes(),
+            ));
+            pairs.push((
+                self.key(format!("xi:{}", idx)),
+                external_id.clone().into_bytes(),
+            ));
+        }
+        self.set_many(pairs)
+    }
+
+    fn delete_records(&self, indices: &[u32]) -> Result<(), DeserializeError> {
+        let external_ids = self.get_external_ids(indices)?;
+
+        let mut keys = Vec::new();
+        for (idx, external_id) in indices.iter().zip(external_ids) {
+            keys.push(self.key(format!("m:{}", idx)));
+            if let Some(external_id) = external_id {
+                keys.push(self.key(format!("xi:{}", idx)));
+                keys.push(self.key(format!("x:{}", external_id)));
+            }
+        }
+        self.delete_many(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    #[ignore = "requires a Redis server at REDIS_URL"]
+    fn test_round_trip() {
+        let cfg = Config::new();
+        let tag = format!("test-{:x}", rand::random::<u64>());
+        let client = RedisClient::open(&cfg.redis_url, cfg.redis_cluster, tag).unwrap();
+
+        let lock = client.lock_writes().unwrap();
+        // the lock is shared by the clients of an index, which wait for it
+        let other =
+            RedisClient::open(&cfg.redis_url, cfg.redis_cluster, client.tag.clone()).unwrap();
+        let waiter = thread::spawn(move || {
+            let _lock = other.lock_writes().unwrap();
+            other.get_datasize().unwrap()
+        });
+
+        client
+            .add_points_batch(&[vec![1.0, 2.0], vec![3.0, 4.0]], 0, Precision::F32)
+            .unwrap();
+        client
+            .set_metadata_batch(vec![json!({ "i": 0 }), json!({ "i": 1 })], 0)
+            .unwrap();
+        client
+            .set_external_ids_batch(&[("a".to_string(), 1)])
+            .unwrap();
+        client.upsert_neighbors(vec![LayerNode::new(0, 1)]).unwrap();
+        client.set_datasize(2).unwrap();
+        assert_eq!(client.bump_generation().unwrap(), 1);
+        thread::sleep(LOCK_RETRY * 2);
+        drop(lock);
+        assert_eq!(waiter.join().unwrap(), 2);
+
+        assert_eq!(client.get_generation().unwrap(), 1);
+        assert_eq!(client.get_points(&[1]).unwrap()[0].v, vec![3.0, 4.0]);
+        assert_eq!(client.get_neighbor(0, 1).unwrap().idx, 1);
+        assert_eq!(
+            client.get_indices_by_external_ids(&["a".into()]).unwrap(),
+            vec![Some(1)]
+        );
+
+        client.delete_records(&[1]).unwrap();
+        assert_eq!(
+            client.get_indices_by_external_ids(&["a".into()]).unwrap(),
+            vec![None]
+        );
+        assert!(matches!(
+            client.get_metadata(1),
+            Err(DeserializeError::MissingKey)
+        ));
+        assert_eq!(client.get_metadata(0).unwrap(), json!({ "i": 0 }));
     }
 }
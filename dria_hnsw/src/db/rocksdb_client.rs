@@ -1,79 +1,882 @@
-use crate::db::conversions::{base64_to_node, base64_to_point, node_to_base64, point_to_base64};
+use crate::db::conversions::{
+    decode_message, decode_number, encode_message, encode_number, is_base64, FORMAT_BASE64,
+    FORMAT_BINARY, FORMAT_COLUMN_FAMILIES,
+};
 use crate::db::env::Config;
+use crate::db::store::{
+    is_valid_snapshot_name, iter_metadatas_by_index, iter_points_by_index, RecordBatch,
+    VectorStore, WriteLock, ITER_BATCH_SIZE,
+};
 use crate::errors::errors::DeserializeError;
-use crate::proto::index_buffer::{LayerNode, Point};
+use crate::hnsw::options::IndexOptions;
+use crate::hnsw::quantization::Quantizer;
+use crate::proto::index_buffer::{LayerNode, Point, PointQuant, Precision};
+use log::error;
 use prost::Message;
 use rocksdb;
-use rocksdb::{DBWithThreadMode, Options, WriteBatch, DB};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{
+    BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, DBCompressionType, DBWithThreadMode,
+    Direction, IteratorMode, Options, WriteBatch, DB, DEFAULT_COLUMN_FAMILY_NAME,
+};
 use serde_json::Value;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard};
+
+/// Number of keys written at once while migrating.
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+const CF_POINTS: &str = "points";
+const CF_CODES: &str = "codes";
+const CF_GRAPH: &str = "graph";
+const CF_METADATA: &str = "metadata";
+const CF_SYSTEM: &str = "system";
+
+/// Keys and values read at once while iterating over the database.
+type Page = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+/// A record of the index, located w.r.t. the on-disk format by [`RocksdbClient::locate`].
+///
+/// Before [`FORMAT_COLUMN_FAMILIES`] all records are in the default column family, under string keys
+/// such as `{tag}.value.{layer}:{idx}`. Afterwards each kind of record has its own column family,
+/// under `{tag}.` followed by big-endian integers, so that indices are in numeric order.
+enum Key<'a> {
+    /// A point, `{idx}` in the string layout.
+    Point(u32),
+    /// A graph node at a layer, `{layer}:{idx}`.
+    Node(u32, u32),
+    /// A quantized point, `q:{idx}`.
+    Code(u32),
+    /// Metadata of a point, `m:{idx}`.
+    Metadata(u32),
+    /// Internal index of an external id, `x:{external_id}`.
+    ExternalId(&'a str),
+    /// External id of an internal index, `xi:{idx}`.
+    ExternalIdOf(u32),
+    /// Headers & settings of the index, such as `datasize` or `options`.
+    System(&'a str),
+}
+
+impl<'a> Key<'a> {
+    /// Parses the name of a key in the string layout, i.e. the part after `{tag}.value.`.
+    fn parse(name: &'a str) -> Option<Key<'a>> {
+        let key = if let Some(idx) = name.strip_prefix("m:") {
+            Key::Metadata(idx.parse().ok()?)
+        } else if let Some(idx) = name.strip_prefix("q:") {
+            Key::Code(idx.parse().ok()?)
+        } else if let Some(idx) = name.strip_prefix("xi:") {
+            Key::ExternalIdOf(idx.parse().ok()?)
+        } else if let Some(external_id) = name.strip_prefix("x:") {
+            Key::ExternalId(external_id)
+        } else if let Some((layer, idx)) = name.split_once(':') {
+            Key::Node(layer.parse().ok()?, idx.parse().ok()?)
+        } else if let Ok(idx) = name.parse() {
+            Key::Point(idx)
+        } else {
+            Key::System(name)
+        };
+        Some(key)
+    }
+
+    /// Column family & key suffix in the column family layout.
+    fn encode(&self) -> (&'static str, Vec<u8>) {
+        match self {
+            Key::Point(idx) => (CF_POINTS, idx.to_be_bytes().to_vec()),
+            Key::Node(layer, idx) => (CF_GRAPH, [layer.to_be_bytes(), idx.to_be_bytes()].concat()),
+            Key::Code(idx) => (CF_CODES, idx.to_be_bytes().to_vec()),
+            Key::Metadata(idx) => (CF_METADATA, [&b"m"[..], &idx.to_be_bytes()].concat()),
+            Key::ExternalIdOf(idx) => (CF_METADATA, [&b"i"[..], &idx.to_be_bytes()].concat()),
+            Key::ExternalId(external_id) => {
+                (CF_METADATA, [&b"x"[..], external_id.as_bytes()].concat())
+            }
+            Key::System(name) => (CF_SYSTEM, name.as_bytes().to_vec()),
+        }
+    }
+
+    /// Key name in the string layout.
+    fn name(&self) -> String {
+        match self {
+            Key::Point(idx) => idx.to_string(),
+            Key::Node(layer, idx) => format!("{}:{}", layer, idx),
+            Key::Code(idx) => format!("q:{}", idx),
+            Key::Metadata(idx) => format!("m:{}", idx),
+            Key::ExternalId(external_id) => format!("x:{}", external_id),
+            Key::ExternalIdOf(idx) => format!("xi:{}", idx),
+            Key::System(name) => name.to_string(),
+        }
+    }
+}
+
+/// Options of each column family, tuned for the size & access pattern of its values.
+fn column_family_options() -> Vec<(&'static str, Options)> {
+    let family = |block_size: usize, compression: DBCompressionType| {
+        let mut block_opts = BlockBasedOptions::default();
+        block_opts.set_block_size(block_size);
+        // points are read by key only, a bloom filter skips most blocks that do not have the key
+        block_opts.set_bloom_filter(10.0, false);
+        block_opts.set_cache_index_and_filter_blocks(true);
+
+        let mut opts = Options::default();
+        opts.set_block_based_table_factory(&block_opts);
+        opts.set_compression_type(compression);
+        opts
+    };
+
+    vec![
+        // floats are hardly compressible, and a vector is a few KBs
+        (CF_POINTS, family(16 * 1024, DBCompressionType::None)),
+        (CF_CODES, family(4 * 1024, DBCompressionType::None)),
+        // nodes are tiny and read at random while traversing
+        (CF_GRAPH, family(4 * 1024, DBCompressionType::Lz4)),
+        // JSON compresses well, and is read only for the results
+        (CF_METADATA, family(16 * 1024, DBCompressionType::Zstd)),
+        (CF_SYSTEM, Options::default()),
+    ]
+}
+
+fn column_families() -> Vec<ColumnFamilyDescriptor> {
+    column_family_options()
+        .into_iter()
+        .map(|(name, opts)| ColumnFamilyDescriptor::new(name, opts))
+        .collect()
+}
+
+/// The column families of [`column_families`] that the database at the given path already has.
+fn existing_column_families(path: &str) -> Result<Vec<ColumnFamilyDescriptor>, rocksdb::Error> {
+    let existing = DB::list_cf(&Options::default(), path)?;
+    Ok(column_families()
+        .into_iter()
+        .filter(|cf| existing.iter().any(|name| name == cf.name()))
+        .collect())
+}
+
+/// Whether the records of the given format are in column families that are open in the database.
+fn has_column_families(db: &DB, format: u32) -> bool {
+    format < FORMAT_COLUMN_FAMILIES
+        || [CF_POINTS, CF_CODES, CF_GRAPH, CF_METADATA, CF_SYSTEM]
+            .iter()
+            .all(|name| db.cf_handle(name).is_some())
+}
+
+/// Copies a snapshot, hard-linking its table files like a checkpoint does, as those are never modified.
+fn copy_snapshot(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let is_table = entry.path().extension().is_some_and(|ext| ext == "sst");
+        if !is_table || fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Identifies the directory at a path, which changes when a snapshot is restored in its place; `0` if unknown.
+fn directory_id(path: &str) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).map(|m| m.ino()).unwrap_or(0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        0
+    }
+}
+
+/// What a replica found when it caught up with its primary, see [`RocksdbClient::catch_up`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CatchUp {
+    /// The primary did not write anything.
+    Unchanged,
+    /// The primary wrote to the index.
+    Written,
+    /// The database was reopened, e.g. after the primary restored a snapshot, so that nothing cached from it holds.
+    Reopened,
+}
+
+/// Read access to the database, see [`RocksdbClient::db`].
+struct Database<'a>(RwLockReadGuard<'a, Option<DB>>);
+
+impl Deref for Database<'_> {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        self.0.as_ref().expect("the database is checked to be open")
+    }
+}
 
 #[derive(Debug)]
 pub struct RocksdbClient {
     tag: String,
-    client: DB,
+    path: String,
+    /// Directory that snapshots are saved in, see [`RocksdbClient::create_snapshot`].
+    snapshot_path: PathBuf,
+    /// The database is closed only while a snapshot is restored.
+    client: RwLock<Option<DB>>,
+    /// On-disk format that values are written in, see [`FORMAT_BASE64`], [`FORMAT_BINARY`] and [`FORMAT_COLUMN_FAMILIES`].
+    format: AtomicU32,
+    /// Whether the database is opened as a secondary of another server, see [`RocksdbClient::open_secondary`].
+    read_only: bool,
+    /// Directory for the logs of a secondary.
+    secondary_path: Option<String>,
+    /// Directory that a secondary follows, see [`directory_id`].
+    directory: AtomicU64,
+    /// Held by writers, see [`VectorStore::lock_writes`].
+    writes: Mutex<()>,
 }
 
 impl RocksdbClient {
     pub fn new(contract_id: String) -> Result<RocksdbClient, DeserializeError> {
         let cfg = Config::new();
-        // Create a new database options instance.
+        let mut client = if cfg.read_only {
+            RocksdbClient::open_secondary(
+                &cfg.rocksdb_path,
+                &cfg.rocksdb_secondary_path,
+                contract_id,
+            )?
+        } else {
+            RocksdbClient::open(&cfg.rocksdb_path, contract_id)?
+        };
+        client.snapshot_path = PathBuf::from(cfg.snapshot_path);
+        Ok(client)
+    }
+
+    /// Opens the index of the given contract in the database at the given path.
+    pub fn open(path: &str, contract_id: String) -> Result<RocksdbClient, DeserializeError> {
+        let db = Self::open_db(path).map_err(|_| DeserializeError::RocksDBConnectionError)?;
+
+        let client = RocksdbClient {
+            tag: contract_id,
+            path: path.to_string(),
+            snapshot_path: PathBuf::from(format!("{}-snapshots", path)),
+            client: RwLock::new(Some(db)),
+            format: AtomicU32::new(FORMAT_BASE64),
+            read_only: false,
+            secondary_path: None,
+            directory: AtomicU64::new(0),
+            writes: Mutex::new(()),
+        };
+        client.load_format()?;
+
+        Ok(client)
+    }
+
+    /// Opens the database at the given path with the column families that it has, or creates it with all of them.
+    ///
+    /// Column families are not added to an existing database until it is migrated, as HollowDB, which may share it,
+    /// can not open a database that has others than the default one.
+    fn open_db(path: &str) -> Result<DB, rocksdb::Error> {
         let mut opts = Options::default();
-        opts.create_if_missing(true); // Creates a database if it does not exist.
-                                      //let x = DBWithThreadMode::open(&opts, cfg.rocksdb_path);
-        let db = DB::open(&opts, cfg.rocksdb_path).unwrap();
+        opts.create_if_missing(true);
+        match existing_column_families(path) {
+            Ok(families) => DB::open_cf_descriptors(&opts, path, families),
+            Err(_) => {
+                opts.create_missing_column_families(true);
+                DB::open_cf_descriptors(&opts, path, column_families())
+            }
+        }
+    }
 
-        Ok(RocksdbClient {
+    /// Opens the index as a read-only secondary of the database at the given path, which another server writes to.
+    ///
+    /// The secondary keeps its own logs at `secondary_path`, and sees the writes of the primary
+    /// only after [`RocksdbClient::catch_up`].
+    pub fn open_secondary(
+        path: &str,
+        secondary_path: &str,
+        contract_id: String,
+    ) -> Result<RocksdbClient, DeserializeError> {
+        let directory = directory_id(path);
+        let db = Self::open_secondary_db(path, secondary_path)?;
+
+        let client = RocksdbClient {
             tag: contract_id,
-            client: db,
+            path: path.to_string(),
+            snapshot_path: PathBuf::from(format!("{}-snapshots", path)),
+            client: RwLock::new(Some(db)),
+            format: AtomicU32::new(FORMAT_BASE64),
+            read_only: true,
+            secondary_path: Some(secondary_path.to_string()),
+            directory: AtomicU64::new(directory),
+            writes: Mutex::new(()),
+        };
+        client.load_format()?;
+
+        Ok(client)
+    }
+
+    fn open_secondary_db(path: &str, secondary_path: &str) -> Result<DB, DeserializeError> {
+        // a secondary can not create column families, so only those of the primary are opened
+        let families =
+            existing_column_families(path).map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        DB::open_cf_descriptors_as_secondary(&Options::default(), path, secondary_path, families)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)
+    }
+
+    /// Opens the database of the primary again, in place of the one a secondary has open,
+    /// along with the column families that the primary created since.
+    ///
+    /// The database that is open is kept if the new one is missing the column families of its format.
+    fn reopen_secondary(&self) -> Result<(), DeserializeError> {
+        let secondary_path = self
+            .secondary_path
+            .as_ref()
+            .ok_or(DeserializeError::Unsupported)?;
+        let directory = directory_id(&self.path);
+        let db = Self::open_secondary_db(&self.path, secondary_path)?;
+        let format = self.read_format(&db)?.unwrap_or(FORMAT_COLUMN_FAMILIES);
+        if !has_column_families(&db, format) {
+            return Err(DeserializeError::RocksDBConnectionError);
+        }
+
+        *self.client.write().unwrap_or_else(PoisonError::into_inner) = Some(db);
+        self.directory.store(directory, Ordering::SeqCst);
+        self.format.store(format, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Reads the writes of the primary since the last catch up, for a client opened with [`RocksdbClient::open_secondary`].
+    ///
+    /// A primary that restored a snapshot has moved another directory in place of the one followed so far,
+    /// in which case the database is reopened.
+    pub fn catch_up(&self) -> Result<CatchUp, DeserializeError> {
+        let directory = directory_id(&self.path);
+        if directory != 0 && directory != self.directory.load(Ordering::SeqCst) {
+            self.reopen_secondary()?;
+            return Ok(CatchUp::Reopened);
+        }
+
+        let db = self.db()?;
+        let sequence = db.latest_sequence_number();
+        db.try_catch_up_with_primary()
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        if db.latest_sequence_number() == sequence {
+            return Ok(CatchUp::Unchanged);
+        }
+
+        // the primary may have migrated in the meantime, into column families that are not open yet
+        let format = self.read_format(&db)?.unwrap_or(FORMAT_COLUMN_FAMILIES);
+        if !has_column_families(&db, format) {
+            drop(db);
+            self.reopen_secondary()?;
+            return Ok(CatchUp::Reopened);
+        }
+        self.format.store(format, Ordering::SeqCst);
+        Ok(CatchUp::Written)
+    }
+
+    /// Reads the on-disk format of the index.
+    ///
+    /// Indices without a format are in base64 if they already exist (e.g. written by HollowDB) or if the database
+    /// has no column families, so that HollowDB can still open it, and new indices are created with column families. A secondary can not write the format of a new index,
+    /// so it assumes the one that the primary will create it with.
+    fn load_format(&self) -> Result<(), DeserializeError> {
+        let db = self.db()?;
+        let format = match self.read_format(&db)? {
+            Some(format) => format,
+            None => {
+                drop(db);
+                self.set_format(FORMAT_COLUMN_FAMILIES)?;
+                FORMAT_COLUMN_FAMILIES
+            }
+        };
+        // a secondary opens only the column families that the primary had created
+        if !has_column_families(&*self.db()?, format) {
+            return Err(DeserializeError::RocksDBConnectionError);
+        }
+
+        self.format.store(format, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Reads the on-disk format from the given database, `None` for a new index of a primary, whose format is not written yet.
+    fn read_format(&self, db: &DB) -> Result<Option<u32>, DeserializeError> {
+        let format_key = format!("{}.value.format", self.tag);
+        let value = db
+            .get(format_key.as_bytes())
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        if let Some(value) = value {
+            let format =
+                decode_number(&value, FORMAT_BASE64).ok_or(DeserializeError::InvalidForm)?;
+            return Ok(Some(format as u32));
+        }
+
+        let datasize_key = format!("{}.value.datasize", self.tag);
+        let exists = db
+            .get(datasize_key.as_bytes())
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?
+            .is_some();
+        if exists || db.cf_handle(CF_SYSTEM).is_none() {
+            Ok(Some(FORMAT_BASE64))
+        } else if self.read_only {
+            Ok(Some(FORMAT_COLUMN_FAMILIES))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The format is stored as a decimal string, so that it can be read before the format is known.
+    fn set_format(&self, format: u32) -> Result<(), DeserializeError> {
+        self.db()?
+            .put(
+                format!("{}.value.format", self.tag).into_bytes(),
+                format.to_string().as_bytes(),
+            )
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        self.format.store(format, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn format(&self) -> u32 {
+        self.format.load(Ordering::SeqCst)
+    }
+
+    /// The database, which blocks a restore until it is dropped.
+    fn db(&self) -> Result<Database<'_>, DeserializeError> {
+        let db = self.client.read().unwrap_or_else(PoisonError::into_inner);
+        match *db {
+            Some(_) => Ok(Database(db)),
+            None => Err(DeserializeError::RocksDBConnectionError),
+        }
+    }
+
+    fn cf<'d>(db: &'d DB, name: &str) -> &'d ColumnFamily {
+        db.cf_handle(name)
+            .expect("column families are opened with the database")
+    }
+
+    /// Column family & key of a record in the given format.
+    fn key(&self, key: &Key, format: u32) -> (&'static str, Vec<u8>) {
+        if format >= FORMAT_COLUMN_FAMILIES {
+            let (cf, suffix) = key.encode();
+            let mut bytes = format!("{}.", self.tag).into_bytes();
+            bytes.extend(suffix);
+            (cf, bytes)
+        } else {
+            let bytes = format!("{}.value.{}", self.tag, key.name()).into_bytes();
+            (DEFAULT_COLUMN_FAMILY_NAME, bytes)
+        }
+    }
+
+    fn locate<'d>(&self, db: &'d DB, key: &Key, format: u32) -> (&'d ColumnFamily, Vec<u8>) {
+        let (cf, key) = self.key(key, format);
+        (Self::cf(db, cf), key)
+    }
+
+    fn get(&self, key: Key) -> Result<Option<Vec<u8>>, DeserializeError> {
+        let db = self.db()?;
+        let (cf, key) = self.locate(&db, &key, self.format());
+        db.get_cf(cf, key)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)
+    }
+
+    fn multi_get<'a>(
+        &self,
+        keys: impl IntoIterator<Item = Key<'a>>,
+    ) -> Vec<Result<Option<Vec<u8>>, DeserializeError>> {
+        let db = match self.db() {
+            Ok(db) => db,
+            Err(_) => {
+                return keys
+                    .into_iter()
+                    .map(|_| Err(DeserializeError::RocksDBConnectionError))
+                    .collect()
+            }
+        };
+        let format = self.format();
+        let keys = keys
+            .into_iter()
+            .map(|key| self.locate(&db, &key, format))
+            .collect::<Vec<(&ColumnFamily, Vec<u8>)>>();
+        db.multi_get_cf(keys.iter().map(|(cf, key)| (*cf, key)))
+            .into_iter()
+            .map(|value| value.map_err(|_| DeserializeError::RocksDBConnectionError))
+            .collect()
+    }
+
+    fn put(&self, key: Key, value: impl AsRef<[u8]>) -> Result<(), DeserializeError> {
+        let db = self.db()?;
+        let (cf, key) = self.locate(&db, &key, self.format());
+        db.put_cf(cf, key, value)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)
+    }
+
+    fn batch_put(&self, db: &DB, batch: &mut WriteBatch, key: Key, value: impl AsRef<[u8]>) {
+        let (cf, key) = self.locate(db, &key, self.format());
+        batch.put_cf(cf, key, value);
+    }
+
+    fn batch_delete(&self, db: &DB, batch: &mut WriteBatch, key: Key) {
+        let (cf, key) = self.locate(db, &key, self.format());
+        batch.delete_cf(cf, key);
+    }
+
+    fn snapshot_dir(&self, name: &str) -> Result<PathBuf, DeserializeError> {
+        if !is_valid_snapshot_name(name) {
+            return Err(DeserializeError::InvalidForm);
+        }
+        Ok(self.snapshot_path.join(name))
+    }
+
+    /// Iterates over the keys of a kind, e.g. [`Key::Metadata`], in pages of [`ITER_BATCH_SIZE`] entries,
+    /// starting right after the given index (if any).
+    fn iter_pages(
+        &self,
+        key: fn(u32) -> Key<'static>,
+        after: Option<u32>,
+        format: u32,
+    ) -> impl Iterator<Item = Result<Page, DeserializeError>> + '_ {
+        let (cf, mut prefix) = self.key(&key(0), format);
+        // drop the index of the key, leaving the prefix of all keys of its kind
+        if format >= FORMAT_COLUMN_FAMILIES {
+            prefix.truncate(prefix.len() - 4);
+        } else {
+            prefix.pop();
+        }
+        let (mut start, mut exclusive) = match after {
+            Some(idx) => (self.key(&key(idx), format).1, true),
+            None => (prefix.clone(), false),
+        };
+
+        // keys are read in pages, so that the database is not held while the caller iterates
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let page = self.db().and_then(|db| {
+                db.iterator_cf(
+                    Self::cf(&db, cf),
+                    IteratorMode::From(&start, Direction::Forward),
+                )
+                .skip_while(|item| match item {
+                    Ok((key, _)) => exclusive && key.as_ref() == start.as_slice(),
+                    Err(_) => false,
+                })
+                .take_while(|item| match item {
+                    Ok((key, _)) => key.starts_with(&prefix),
+                    Err(_) => true,
+                })
+                .take(ITER_BATCH_SIZE)
+                .map(|item| item.map_err(|_| DeserializeError::RocksDBConnectionError))
+                .collect::<Result<Vec<_>, DeserializeError>>()
+            });
+
+            match &page {
+                Ok(page) => {
+                    done = page.len() < ITER_BATCH_SIZE;
+                    if let Some((key, _)) = page.last() {
+                        start = key.to_vec();
+                        exclusive = true;
+                    }
+                }
+                Err(_) => done = true,
+            }
+            // the last page may be empty, when the previous one was full
+            match &page {
+                Ok(page) if page.is_empty() => None,
+                _ => Some(page),
+            }
         })
     }
 
+    fn get_number(&self, name: &str) -> Result<usize, DeserializeError> {
+        match self.get(Key::System(name))? {
+            Some(value) => {
+                decode_number(&value, self.format()).ok_or(DeserializeError::InvalidForm)
+            }
+            None => Err(DeserializeError::MissingKey),
+        }
+    }
+
+    fn set_number(&self, name: &str, number: usize) -> Result<(), DeserializeError> {
+        self.put(Key::System(name), encode_number(number, self.format()))
+    }
+
+    /// Converts an index to the latest format, and returns the number of converted keys.
+    pub fn migrate(&self) -> Result<usize, DeserializeError> {
+        let mut count = 0;
+        if self.format() == FORMAT_BASE64 {
+            count += self.migrate_binary()?;
+        }
+        if self.format() == FORMAT_BINARY {
+            count += self.migrate_column_families()?;
+        }
+        Ok(count)
+    }
+
+    /// Converts an index in base64 format to binary format.
+    ///
+    /// Messages are converted in batches and the numbers along with the format at the very end,
+    /// so an interrupted migration can be run again; messages of both formats are readable in the meantime.
+    fn migrate_binary(&self) -> Result<usize, DeserializeError> {
+        let db = self.db()?;
+        let prefix = format!("{}.value.", self.tag);
+        let mut count = 0;
+        let mut batch = WriteBatch::default();
+        for item in db.prefix_iterator(prefix.as_bytes()) {
+            let (key, value) = item.map_err(|_| DeserializeError::RocksDBConnectionError)?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+
+            // points are `idx`, nodes are `layer:idx` and codes are `q:idx`
+            let name = &key[prefix.len()..];
+            let is_message = name.starts_with(b"q:")
+                || (!name.is_empty() && name.iter().all(|c| c.is_ascii_digit() || *c == b':'));
+            if is_message && is_base64(&value) {
+                let bytes = base64::decode(&value).map_err(|_| DeserializeError::InvalidForm)?;
+                batch.put(&key, bytes);
+                count += 1;
+            }
+
+            if batch.len() >= MIGRATION_BATCH_SIZE {
+                db.write(std::mem::take(&mut batch))
+                    .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+            }
+        }
+
+        for name in ["datasize", "ep", "num_layers"] {
+            match self.get_number(name) {
+                Ok(number) => {
+                    let key = format!("{}{}", prefix, name);
+                    batch.put(key.as_bytes(), encode_number(number, FORMAT_BINARY));
+                    count += 1;
+                }
+                Err(DeserializeError::MissingKey) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let key = format!("{}format", prefix);
+        batch.put(key.as_bytes(), FORMAT_BINARY.to_string().as_bytes());
+        db.write(batch)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        self.format.store(FORMAT_BINARY, Ordering::SeqCst);
+
+        Ok(count)
+    }
+
+    /// Moves the records of an index in binary format from the default column family to their own column families.
+    ///
+    /// Each batch moves its keys atomically and the format is written at the very end, so an interrupted
+    /// migration can be run again; the index must not be served in the meantime though.
+    fn migrate_column_families(&self) -> Result<usize, DeserializeError> {
+        self.create_column_families()?;
+        let db = self.db()?;
+        let prefix = format!("{}.value.", self.tag);
+        let mut count = 0;
+        let mut batch = WriteBatch::default();
+        for item in db.prefix_iterator(prefix.as_bytes()) {
+            let (key, value) = item.map_err(|_| DeserializeError::RocksDBConnectionError)?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+
+            let name = std::str::from_utf8(&key[prefix.len()..])
+                .map_err(|_| DeserializeError::InvalidForm)?;
+            // the format stays in the default column family, so that it is known before the layout is
+            if name == "format" {
+                continue;
+            }
+            let record = Key::parse(name).ok_or(DeserializeError::InvalidForm)?;
+            let (cf, new_key) = self.locate(&db, &record, FORMAT_COLUMN_FAMILIES);
+            batch.put_cf(cf, new_key, &value);
+            batch.delete(&key);
+            count += 1;
+
+            if batch.len() >= MIGRATION_BATCH_SIZE {
+                db.write(std::mem::take(&mut batch))
+                    .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+            }
+        }
+
+        db.write(batch)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        drop(db);
+        self.set_format(FORMAT_COLUMN_FAMILIES)?;
+
+        Ok(count)
+    }
+
+    /// Creates the column families that the database does not have yet, see [`RocksdbClient::open_db`].
+    fn create_column_families(&self) -> Result<(), DeserializeError> {
+        let mut client = self.client.write().unwrap_or_else(PoisonError::into_inner);
+        let db = client
+            .as_mut()
+            .ok_or(DeserializeError::RocksDBConnectionError)?;
+        for (name, opts) in column_family_options() {
+            if db.cf_handle(name).is_none() {
+                db.create_cf(name, &opts)
+                    .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn set(&self, key: String, value: String) -> Result<(), DeserializeError> {
         let _: () = self
-            .client
+            .db()?
             .put(key.as_bytes(), value.as_bytes())
             .map_err(|_| DeserializeError::RedisConnectionError)?;
         Ok(())
     }
+}
+
+/// Values are read & written in the format of the database, see [`RocksdbClient::format`].
+impl VectorStore for RocksdbClient {
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
-    pub fn get_neighbor(&self, layer: usize, idx: usize) -> Result<LayerNode, DeserializeError> {
-        let key = format!("{}.value.{}:{}", self.tag, layer, idx);
+    fn lock_writes(&self) -> Result<WriteLock<'_>, DeserializeError> {
+        let guard = self.writes.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(WriteLock::new(guard))
+    }
 
-        let value = self
-            .client
-            .get(key.as_bytes())
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?; // Handle RocksDB errors appropriately
+    /// Saves a RocksDB checkpoint of the whole database, which hard-links its table files
+    /// if the snapshot directory is on the same file system.
+    fn create_snapshot(&self, name: &str) -> Result<(), DeserializeError> {
+        if self.read_only {
+            return Err(DeserializeError::Unsupported);
+        }
+        let path = self.snapshot_dir(name)?;
+        if path.exists() {
+            return Err(DeserializeError::AlreadyExists);
+        }
+        fs::create_dir_all(&self.snapshot_path)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
 
-        let node_str = match value {
-            Some(value) => String::from_utf8(value).map_err(|_| DeserializeError::InvalidForm)?, // Convert bytes to String and handle UTF-8 error
-            None => return Err(DeserializeError::MissingKey), // Handle case where key is not found
+        let db = self.db()?;
+        Checkpoint::new(&*db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(&path))
+            .map_err(|_| DeserializeError::RocksDBConnectionError)
+    }
+
+    fn list_snapshots(&self) -> Result<Vec<String>, DeserializeError> {
+        let entries = match fs::read_dir(&self.snapshot_path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(_) => return Err(DeserializeError::RocksDBConnectionError),
         };
 
-        Ok(base64_to_node(&node_str))
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|_| DeserializeError::RocksDBConnectionError)?;
+            if let Some(name) = entry.file_name().to_str() {
+                if entry.path().is_dir() && is_valid_snapshot_name(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
     }
 
-    pub fn get_neighbors(
+    /// Swaps the database directory with a copy of the snapshot while no request holds the database,
+    /// and puts the previous directory back if the copy can not be opened.
+    ///
+    /// Replicas open the restored directory when they next catch up, see [`RocksdbClient::catch_up`].
+    ///
+    /// If the previous directory can not be opened again after a failed swap, the database stays closed,
+    /// and every request fails until the server is restarted.
+    fn restore_snapshot(&self, name: &str) -> Result<(), DeserializeError> {
+        if self.read_only {
+            return Err(DeserializeError::Unsupported);
+        }
+        let snapshot = self.snapshot_dir(name)?;
+        if !snapshot.is_dir() {
+            return Err(DeserializeError::MissingKey);
+        }
+
+        // copied before closing the database, so that requests wait only for the swap
+        let staging = format!("{}.restore", self.path);
+        let backup = format!("{}.old", self.path);
+        for leftover in [&staging, &backup] {
+            if Path::new(leftover).exists() {
+                fs::remove_dir_all(leftover).map_err(|e| {
+                    error!("Error removing {} before a restore: {}", leftover, e);
+                    DeserializeError::RocksDBConnectionError
+                })?;
+            }
+        }
+        copy_snapshot(&snapshot, Path::new(&staging)).map_err(|e| {
+            error!("Error copying snapshot {} to {}: {}", name, staging, e);
+            DeserializeError::RocksDBConnectionError
+        })?;
+
+        let mut client = self.client.write().unwrap_or_else(PoisonError::into_inner);
+        drop(client.take());
+        let moved = fs::rename(&self.path, &backup);
+        let restored = match &moved {
+            Ok(()) => fs::rename(&staging, &self.path)
+                .map_err(|e| e.to_string())
+                .and_then(|_| Self::open_db(&self.path).map_err(|e| e.to_string())),
+            Err(e) => Err(e.to_string()),
+        };
+        match restored {
+            Ok(db) => {
+                *client = Some(db);
+                drop(client);
+                if let Err(e) = fs::remove_dir_all(&backup) {
+                    error!("Error removing the previous database at {}: {}", backup, e);
+                }
+            }
+            Err(e) => {
+                error!("Error restoring snapshot {}: {}", name, e);
+                if moved.is_ok() {
+                    let rollback = match Path::new(&self.path).exists() {
+                        true => fs::remove_dir_all(&self.path),
+                        false => Ok(()),
+                    }
+                    .and_then(|_| fs::rename(&backup, &self.path));
+                    if let Err(e) = rollback {
+                        error!("Error moving {} back to {}: {}", backup, self.path, e);
+                    }
+                }
+                match Self::open_db(&self.path) {
+                    Ok(db) => *client = Some(db),
+                    Err(e) => error!(
+                        "The database at {} could not be opened again after a failed restore, \
+                         the previous one may be at {}: {}",
+                        self.path, backup, e
+                    ),
+                }
+                return Err(DeserializeError::RocksDBConnectionError);
+            }
+        }
+
+        self.load_format()
+    }
+
+    fn get_neighbor(&self, layer: usize, idx: usize) -> Result<LayerNode, DeserializeError> {
+        let value = self.get(Key::Node(layer as u32, idx as u32))?;
+
+        match value {
+            Some(value) => decode_message(&value).ok_or(DeserializeError::InvalidForm),
+            None => Err(DeserializeError::MissingKey), // Handle case where key is not found
+        }
+    }
+
+    fn get_neighbors(
         &self,
         layer: usize,
         indices: Vec<u32>,
     ) -> Result<Vec<LayerNode>, DeserializeError> {
-        // Collect keys as a Vec<Vec<u8>> for multi_get
-        let keys = indices
-            .iter()
-            .map(|&x| format!("{}.value.{}:{}", self.tag, layer, x).into_bytes())
-            .collect::<Vec<Vec<u8>>>();
-
         // Use multi_get to fetch values for all keys at once
-        let values = self.client.multi_get(keys);
+        let values = self.multi_get(indices.iter().map(|&x| Key::Node(layer as u32, x)));
 
         let mut neighbors = Vec::new();
         for value_result in values {
             // Correctly handle the Result<Option<Vec<u8>>, E> for each value
             match value_result {
                 Ok(Some(v)) => {
-                    let node_str =
-                        String::from_utf8(v).map_err(|_| DeserializeError::InvalidForm)?; // Convert bytes to String and handle UTF-8 error
-                    let node = base64_to_node(&node_str); // Convert String to LayerNode and handle base64 error
+                    let node = decode_message(&v).ok_or(DeserializeError::InvalidForm)?;
                     neighbors.push(node);
                 }
                 Ok(None) => return Err(DeserializeError::MissingKey), // Handle case where key is not found
@@ -84,51 +887,40 @@ impl RocksdbClient {
         Ok(neighbors)
     }
 
-    pub fn upsert_neighbor(&self, node: LayerNode) -> Result<(), DeserializeError> {
-        let key = format!("{}.value.{}:{}", self.tag, node.level, node.idx);
-
-        let node_str = node_to_base64(&node);
-        self.set(key, node_str)?;
-
-        Ok(())
+    fn upsert_neighbor(&self, node: LayerNode) -> Result<(), DeserializeError> {
+        self.put(
+            Key::Node(node.level, node.idx),
+            encode_message(&node, self.format()),
+        )
     }
 
-    pub fn upsert_neighbors(&self, nodes: Vec<LayerNode>) -> Result<(), DeserializeError> {
+    fn upsert_neighbors(&self, nodes: Vec<LayerNode>) -> Result<(), DeserializeError> {
+        let db = self.db()?;
         let mut batch = WriteBatch::default();
         for node in nodes {
-            let key = format!("{}.value.{}:{}", self.tag, node.level, node.idx);
-            let node_str = node_to_base64(&node);
-            batch.put(key.as_bytes(), node_str.as_bytes());
+            let value = encode_message(&node, self.format());
+            self.batch_put(&db, &mut batch, Key::Node(node.level, node.idx), value);
         }
 
-        let _ = self
-            .client
-            .write(batch)
+        db.write(batch)
             .map_err(|_| DeserializeError::RocksDBConnectionError)?;
 
         Ok(())
     }
 
-    pub fn get_points(&self, indices: &Vec<u32>) -> Result<Vec<Point>, DeserializeError> {
-        let keys = indices
-            .iter()
-            .map(|x| format!("{}.value.{}", self.tag, x).into_bytes())
-            .collect::<Vec<Vec<u8>>>();
-
-        if keys.is_empty() {
+    fn get_points(&self, indices: &[u32]) -> Result<Vec<Point>, DeserializeError> {
+        if indices.is_empty() {
             return Ok(vec![]);
         }
 
         // Assuming multi_get directly returns Vec<Result<Option<Vec<u8>>, E>>
-        let values = self.client.multi_get(keys);
+        let values = self.multi_get(indices.iter().map(|&x| Key::Point(x)));
 
         let mut points = Vec::new();
         for value_result in values {
             match value_result {
                 Ok(Some(value)) => {
-                    let point_str =
-                        String::from_utf8(value).map_err(|_| DeserializeError::InvalidForm)?; // Handle UTF-8 conversion error
-                    let point = base64_to_point(&point_str); // Handle potential error from base64_to_point
+                    let point = decode_message(&value).ok_or(DeserializeError::InvalidForm)?;
                     points.push(point);
                 }
                 Ok(None) => return Err(DeserializeError::MissingKey), // Key not found
@@ -139,170 +931,88 @@ impl RocksdbClient {
         Ok(points)
     }
 
-    pub fn add_points(&self, v: Vec<f32>, idx: usize) -> Result<(), DeserializeError> {
+    fn add_points(&self, v: Vec<f32>, idx: usize) -> Result<(), DeserializeError> {
         let p = Point::new(v, idx);
-        let p_str = point_to_base64(&p);
-        let key = format!("{}.value.{}", self.tag, idx).into_bytes();
-        self.client
-            .put(key, p_str.as_bytes())
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        self.put(Key::Point(idx as u32), encode_message(&p, self.format()))?;
         //self.put_multi_hashtag(&[idx.to_string()], &[json!(p_str)], false)?;
         Ok(())
     }
 
-    pub fn add_points_batch(
+    fn add_points_batch(
         &self,
-        v: &Vec<Vec<f32>>,
+        v: &[Vec<f32>],
         start_idx: usize,
+        precision: Precision,
     ) -> Result<(), DeserializeError> {
+        let db = self.db()?;
         let mut batch = WriteBatch::default();
         for (i, p) in v.iter().enumerate() {
             let idx = start_idx + i;
-            let p = Point::new(p.clone(), idx);
-            let p_str = point_to_base64(&p);
-            let key = format!("{}.value.{}", self.tag, idx).into_bytes();
+            let p = Point::new_with_precision(p.clone(), idx, precision);
 
             //keys.push(idx.to_string());
             //values.push(json!(p_str));
 
-            batch.put(key, p_str.as_bytes());
+            let value = encode_message(&p, self.format());
+            self.batch_put(&db, &mut batch, Key::Point(idx as u32), value);
         }
 
-        self.client
-            .write(batch)
+        db.write(batch)
             .map_err(|_| DeserializeError::RocksDBConnectionError)?;
 
         Ok(())
     }
 
-    pub fn set_datasize(&self, datasize: usize) -> Result<(), DeserializeError> {
+    fn set_datasize(&self, datasize: usize) -> Result<(), DeserializeError> {
         //self.put_multi_hashtag(&["datasize".to_string()], &[json!(datasize)], false)?;
-        self.client
-            .put(
-                format!("{}.value.datasize", self.tag).into_bytes(),
-                datasize.to_string().as_bytes(),
-            )
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-        Ok(())
+        self.set_number("datasize", datasize)
     }
 
-    pub fn get_datasize(&self) -> Result<usize, DeserializeError> {
-        let datasize_key: String = format!("{}.value.datasize", self.tag);
-        let value = self
-            .client
-            .get(datasize_key.as_bytes())
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-
-        let datasize = match value {
-            Some(value_bytes) => {
-                let value_str =
-                    String::from_utf8(value_bytes).map_err(|_| DeserializeError::InvalidForm)?; // Handle UTF-8 error gracefully
-                value_str
-                    .parse::<usize>()
-                    .map_err(|_| DeserializeError::InvalidForm)? // Handle parse error gracefully
-            }
-            None => return Err(DeserializeError::MissingKey), // Handle case where key is not found
-        };
-        Ok(datasize)
+    fn get_datasize(&self) -> Result<usize, DeserializeError> {
+        self.get_number("datasize")
     }
 
-    pub fn get_num_layers(&self) -> Result<usize, DeserializeError> {
-        let num_layers_key: String = format!("{}.value.num_layers", self.tag);
-        let value = self
-            .client
-            .get(num_layers_key.as_bytes())
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-
-        let num_layers = match value {
-            Some(value_bytes) => {
-                let value_str =
-                    String::from_utf8(value_bytes).map_err(|_| DeserializeError::InvalidForm)?; // Handle UTF-8 error gracefully
-                value_str
-                    .parse::<usize>()
-                    .map_err(|_| DeserializeError::InvalidForm)? // Handle parse error gracefully
-            }
-            None => return Err(DeserializeError::MissingKey), // Handle case where key is not found
-        };
-        Ok(num_layers)
+    fn get_num_layers(&self) -> Result<usize, DeserializeError> {
+        self.get_number("num_layers")
     }
 
-    pub fn set_num_layers(&self, num_layers: usize, expire: bool) -> Result<(), DeserializeError> {
-        self.client
-            .put(
-                format!("{}.value.num_layers", self.tag).into_bytes(),
-                num_layers.to_string().as_bytes(),
-            )
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-        Ok(())
+    fn set_num_layers(&self, num_layers: usize) -> Result<(), DeserializeError> {
+        self.set_number("num_layers", num_layers)
     }
 
-    pub fn set_ep(&self, ep: usize, expire: bool) -> Result<(), DeserializeError> {
-        self.client
-            .put(
-                format!("{}.value.ep", self.tag).into_bytes(),
-                ep.to_string().as_bytes(),
-            )
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-        Ok(())
+    fn set_ep(&self, ep: usize) -> Result<(), DeserializeError> {
+        self.set_number("ep", ep)
     }
 
-    pub fn get_ep(&self) -> Result<usize, DeserializeError> {
-        let ep_key: String = format!("{}.value.ep", self.tag);
-        let value = self
-            .client
-            .get(ep_key.as_bytes())
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-
-        // Attempt to convert the fetched value from bytes to String, then parse it into usize
-        let ep_usize = match value {
-            Some(value_bytes) => {
-                // Convert bytes to String
-                let value_str =
-                    String::from_utf8(value_bytes).map_err(|_| DeserializeError::InvalidForm)?; // Handle UTF-8 error gracefully
-                                                                                                // Parse String to usize
-                value_str
-                    .parse::<usize>()
-                    .map_err(|_| DeserializeError::InvalidForm)? // Handle parse error gracefully
-            }
-            None => return Err(DeserializeError::MissingKey), // Handle case where key is not found
-        };
-        Ok(ep_usize)
+    fn get_ep(&self) -> Result<usize, DeserializeError> {
+        self.get_number("ep")
     }
 
-    pub fn set_metadata(&self, metadata: Value, idx: usize) -> Result<(), DeserializeError> {
-        let key = format!("{}.value.m:{}", self.tag, idx);
+    fn set_metadata(&self, metadata: Value, idx: usize) -> Result<(), DeserializeError> {
         let metadata_str = serde_json::to_vec(&metadata).unwrap();
-        self.client
-            .put(key.as_bytes(), metadata_str)
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
-        Ok(())
+        self.put(Key::Metadata(idx as u32), metadata_str)
     }
 
-    pub fn set_metadata_batch(
-        &self,
-        metadata: Vec<Value>,
-        idx: usize,
-    ) -> Result<(), DeserializeError> {
+    fn set_metadata_batch(&self, metadata: Vec<Value>, idx: usize) -> Result<(), DeserializeError> {
+        let db = self.db()?;
         let mut batch = WriteBatch::default();
 
         for (i, m) in metadata.iter().enumerate() {
-            let key = format!("{}.value.m:{}", self.tag, idx + i);
             let metadata_str = serde_json::to_vec(&m).unwrap();
-            batch.put(key.as_bytes(), metadata_str);
+            self.batch_put(
+                &db,
+                &mut batch,
+                Key::Metadata((idx + i) as u32),
+                metadata_str,
+            );
         }
-        self.client
-            .write(batch)
+        db.write(batch)
             .map_err(|_| DeserializeError::RocksDBConnectionError)?;
         Ok(())
     }
 
-    pub fn get_metadata(&self, idx: usize) -> Result<Value, DeserializeError> {
-        let key = format!("{}.value.m:{}", self.tag, idx);
-
-        let value = self
-            .client
-            .get(key.as_bytes())
-            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+    fn get_metadata(&self, idx: usize) -> Result<Value, DeserializeError> {
+        let value = self.get(Key::Metadata(idx as u32))?;
 
         let metadata = match value {
             Some(value) => serde_json::from_slice(&value).unwrap()
@@ -312,14 +1022,9 @@ impl RocksdbClient {
         Ok(metadata)
     }
 
-    pub fn get_metadatas(&self, indices: Vec<u32>) -> Result<Vec<Value>, DeserializeError> {
-        let keys = indices
-            .iter()
-            .map(|x| format!("{}.value.m:{}", self.tag, x).as_bytes().to_vec())
-            .collect::<Vec<Vec<u8>>>();
-
+    fn get_metadatas(&self, indices: Vec<u32>) -> Result<Vec<Value>, DeserializeError> {
         // Assuming multi_get returns Vec<Result<Option<Vec<u8>>, E>> directly
-        let values = self.client.multi_get(&keys);
+        let values = self.multi_get(indices.iter().map(|&x| Key::Metadata(x)));
 
         let mut metadata = Vec::new();
         for value_result in values {
@@ -338,4 +1043,601 @@ impl RocksdbClient {
 
         Ok(metadata)
     }
+
+    fn get_metadatas_partial(&self, indices: &[u32]) -> Vec<Result<Value, DeserializeError>> {
+        self.multi_get(indices.iter().map(|&x| Key::Metadata(x)))
+            .into_iter()
+            .map(|value_result| match value_result {
+                Ok(Some(v)) => {
+                    serde_json::from_slice::<Value>(&v).map_err(|_| DeserializeError::InvalidForm)
+                }
+                Ok(None) => Err(DeserializeError::MissingKey),
+                Err(_) => Err(DeserializeError::RocksDBConnectionError),
+            })
+            .collect()
+    }
+
+    fn get_points_partial(&self, indices: &[u32]) -> Vec<Result<Point, DeserializeError>> {
+        self.multi_get(indices.iter().map(|&x| Key::Point(x)))
+            .into_iter()
+            .map(|value_result| match value_result {
+                Ok(Some(v)) => decode_message(&v).ok_or(DeserializeError::InvalidForm),
+                Ok(None) => Err(DeserializeError::MissingKey),
+                Err(_) => Err(DeserializeError::RocksDBConnectionError),
+            })
+            .collect()
+    }
+
+    fn iter_points(&self) -> Box<dyn Iterator<Item = Result<Vec<Point>, DeserializeError>> + '_> {
+        // points have no prefix of their own in older formats, so they are read by index instead
+        let format = self.format();
+        if format < FORMAT_COLUMN_FAMILIES {
+            return iter_points_by_index(self);
+        }
+
+        let pages = self.iter_pages(Key::Point, None, format).map(|page| {
+            page?
+                .iter()
+                .map(|(_, value)| decode_message(value).ok_or(DeserializeError::InvalidForm))
+                .collect()
+        });
+        Box::new(pages)
+    }
+
+    fn iter_metadatas(
+        &self,
+        after: Option<u32>,
+    ) -> Box<dyn Iterator<Item = Result<(u32, Value), DeserializeError>> + '_> {
+        // keys are in numeric order with column families, while in older formats they are compared
+        // as strings, i.e. `1, 10, 11, 2, ...`, so they are read by index instead
+        let format = self.format();
+        if format < FORMAT_COLUMN_FAMILIES {
+            return iter_metadatas_by_index(self, after);
+        }
+        let pages = self.iter_pages(Key::Metadata, after, format);
+
+        let iter = pages
+            .flat_map(|page| match page {
+                Ok(page) => page.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            })
+            .map(move |item| {
+                let (key, value) = item?;
+                let idx = key[key.len() - 4..]
+                    .try_into()
+                    .map(u32::from_be_bytes)
+                    .map_err(|_| DeserializeError::InvalidForm)?;
+                let metadata = serde_json::from_slice::<Value>(&value)
+                    .map_err(|_| DeserializeError::InvalidForm)?;
+                Ok((idx, metadata))
+            });
+        Box::new(iter)
+    }
+
+    fn get_indices_by_external_ids(
+        &self,
+        external_ids: &[String],
+    ) -> Result<Vec<Option<u32>>, DeserializeError> {
+        let keys = external_ids.iter().map(|x| Key::ExternalId(x));
+
+        let mut indices = Vec::new();
+        for value_result in self.multi_get(keys) {
+            match value_result {
+                Ok(Some(v)) => {
+                    let idx_str =
+                        String::from_utf8(v).map_err(|_| DeserializeError::InvalidForm)?;
+                    let idx = idx_str
+                        .parse::<u32>()
+                        .map_err(|_| DeserializeError::InvalidForm)?;
+                    indices.push(Some(idx));
+                }
+                Ok(None) => indices.push(None),
+                Err(_) => return Err(DeserializeError::RocksDBConnectionError),
+            }
+        }
+
+        Ok(indices)
+    }
+
+    fn get_external_ids(&self, indices: &[u32]) -> Result<Vec<Option<String>>, DeserializeError> {
+        let keys = indices.iter().map(|&x| Key::ExternalIdOf(x));
+
+        let mut external_ids = Vec::new();
+        for value_result in self.multi_get(keys) {
+            match value_result {
+                Ok(Some(v)) => external_ids.push(Some(
+                    String::from_utf8(v).map_err(|_| DeserializeError::InvalidForm)?,
+                )),
+                Ok(None) => external_ids.push(None),
+                Err(_) => return Err(DeserializeError::RocksDBConnectionError),
+            }
+        }
+
+        Ok(external_ids)
+    }
+
+    fn set_external_ids_batch(
+        &self,
+        external_ids: &[(String, u32)],
+    ) -> Result<(), DeserializeError> {
+        let db = self.db()?;
+        let mut batch = WriteBatch::default();
+        for (external_id, idx) in external_ids {
+            self.batch_put(
+                &db,
+                &mut batch,
+                Key::ExternalId(external_id),
+                idx.to_string().as_bytes(),
+            );
+            self.batch_put(
+                &db,
+                &mut batch,
+                Key::ExternalIdOf(*idx),
+                external_id.as_bytes(),
+            );
+        }
+
+        db.write(batch)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        Ok(())
+    }
+
+    fn delete_records(&self, indices: &[u32]) -> Result<(), DeserializeError> {
+        let external_ids = self.get_external_ids(indices)?;
+
+        let db = self.db()?;
+        let mut batch = WriteBatch::default();
+        for (idx, external_id) in indices.iter().zip(external_ids) {
+            self.batch_delete(&db, &mut batch, Key::Metadata(*idx));
+            if let Some(external_id) = external_id {
+                self.batch_delete(&db, &mut batch, Key::ExternalIdOf(*idx));
+                self.batch_delete(&db, &mut batch, Key::ExternalId(&external_id));
+            }
+        }
+
+        db.write(batch)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        Ok(())
+    }
+
+    fn add_records(&self, records: RecordBatch) -> Result<(), DeserializeError> {
+        let replaced_ids = self.get_external_ids(&records.replaced)?;
+
+        let db = self.db()?;
+        let format = self.format();
+        let mut batch = WriteBatch::default();
+        for (i, v) in records.vectors.iter().enumerate() {
+            let idx = records.start_idx + i;
+            let p = Point::new_with_precision(v.clone(), idx, records.precision);
+            let value = encode_message(&p, format);
+            self.batch_put(&db, &mut batch, Key::Point(idx as u32), value);
+        }
+        for (i, m) in records.metadata.iter().enumerate() {
+            let idx = (records.start_idx + i) as u32;
+            let value = serde_json::to_vec(m).unwrap();
+            self.batch_put(&db, &mut batch, Key::Metadata(idx), value);
+        }
+        for node in records.tombstones.iter() {
+            let value = encode_message(node, format);
+            self.batch_put(&db, &mut batch, Key::Node(node.level, node.idx), value);
+        }
+        // deletes come before the new external ids, as later writes of a batch take precedence
+        for (idx, external_id) in records.replaced.iter().zip(replaced_ids) {
+            self.batch_delete(&db, &mut batch, Key::Metadata(*idx));
+            if let Some(external_id) = external_id {
+                self.batch_delete(&db, &mut batch, Key::ExternalIdOf(*idx));
+                self.batch_delete(&db, &mut batch, Key::ExternalId(&external_id));
+            }
+        }
+        for (external_id, idx) in records.external_ids.iter() {
+            let value = idx.to_string();
+            self.batch_put(&db, &mut batch, Key::ExternalId(external_id), value);
+            self.batch_put(&db, &mut batch, Key::ExternalIdOf(*idx), external_id);
+        }
+        let datasize = records.start_idx + records.vectors.len();
+        let value = encode_number(datasize, format);
+        self.batch_put(&db, &mut batch, Key::System("datasize"), value);
+
+        db.write(batch)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)
+    }
+
+    fn get_options(&self) -> Result<Option<IndexOptions>, DeserializeError> {
+        let value = self.get(Key::System("options"))?;
+
+        match value {
+            Some(v) => serde_json::from_slice(&v)
+                .map(Some)
+                .map_err(|_| DeserializeError::InvalidForm),
+            None => Ok(None),
+        }
+    }
+
+    fn set_options(&self, options: &IndexOptions) -> Result<(), DeserializeError> {
+        let options_str = serde_json::to_vec(options).unwrap();
+        self.put(Key::System("options"), options_str)
+    }
+
+    fn get_quantizer(&self) -> Result<Option<Quantizer>, DeserializeError> {
+        let value = self.get(Key::System("quantizer"))?;
+
+        match value {
+            Some(v) => serde_json::from_slice(&v)
+                .map(Some)
+                .map_err(|_| DeserializeError::InvalidForm),
+            None => Ok(None),
+        }
+    }
+
+    fn set_quantizer(&self, quantizer: &Quantizer) -> Result<(), DeserializeError> {
+        let quantizer_str = serde_json::to_vec(quantizer).unwrap();
+        self.put(Key::System("quantizer"), quantizer_str)
+    }
+
+    fn get_codes(&self, indices: &[u32]) -> Result<Vec<PointQuant>, DeserializeError> {
+        if indices.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut codes = Vec::new();
+        for value_result in self.multi_get(indices.iter().map(|&x| Key::Code(x))) {
+            match value_result {
+                Ok(Some(v)) => {
+                    codes.push(decode_message(&v).ok_or(DeserializeError::InvalidForm)?);
+                }
+                Ok(None) => return Err(DeserializeError::MissingKey),
+                Err(_) => return Err(DeserializeError::RocksDBConnectionError),
+            }
+        }
+
+        Ok(codes)
+    }
+
+    fn add_codes_batch(
+        &self,
+        codes: Vec<Vec<u8>>,
+        start_idx: usize,
+    ) -> Result<(), DeserializeError> {
+        let db = self.db()?;
+        let mut batch = WriteBatch::default();
+        for (i, code) in codes.into_iter().enumerate() {
+            let idx = start_idx + i;
+            let p = PointQuant::new(code, idx);
+            let value = encode_message(&p, self.format());
+            self.batch_put(&db, &mut batch, Key::Code(idx as u32), value);
+        }
+
+        db.write(batch)
+            .map_err(|_| DeserializeError::RocksDBConnectionError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::conversions::{node_to_base64, point_to_base64};
+    use serde_json::json;
+
+    #[test]
+    fn test_migrate() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-migrate");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(path);
+
+        // an index written by HollowDB
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            let db = DB::open(&opts, path).unwrap();
+            let point = Point::new(vec![1.0, 2.0], 0);
+            db.put(b"hollow.value.0", point_to_base64(&point)).unwrap();
+            let node = LayerNode::new(0, 0);
+            db.put(b"hollow.value.0:0", node_to_base64(&node)).unwrap();
+            db.put(b"hollow.value.m:0", b"{}").unwrap();
+            db.put(b"hollow.value.datasize", b"1").unwrap();
+            db.put(b"hollow.value.ep", b"0").unwrap();
+            db.put(b"hollow.value.num_layers", b"1").unwrap();
+        }
+
+        // HollowDB can open only the default column family, so no other is created until the migration,
+        // and a new index is in its format too
+        let new = RocksdbClient::open(path, "new".to_string()).unwrap();
+        assert_eq!(new.format(), FORMAT_BASE64);
+        drop(new);
+
+        let client = RocksdbClient::open(path, "hollow".to_string()).unwrap();
+        assert_eq!(client.format(), FORMAT_BASE64);
+        assert_eq!(client.get_datasize().unwrap(), 1);
+        assert_eq!(
+            DB::list_cf(&Options::default(), path).unwrap(),
+            vec![DEFAULT_COLUMN_FAMILY_NAME.to_string()]
+        );
+        assert_eq!(client.get_points(&[0]).unwrap()[0].v, vec![1.0, 2.0]);
+
+        // point, node and the 3 numbers to binary, then all 6 keys to column families
+        assert_eq!(client.migrate().unwrap(), 11);
+        assert_eq!(client.format(), FORMAT_COLUMN_FAMILIES);
+        assert_eq!(client.migrate().unwrap(), 0);
+        assert_eq!(DB::list_cf(&Options::default(), path).unwrap().len(), 6);
+
+        let db = client.db().unwrap();
+        assert!(db.get(b"hollow.value.0").unwrap().is_none());
+        let (cf, key) = client.locate(&db, &Key::Point(0), client.format());
+        let value = db.get_cf(cf, key).unwrap().unwrap();
+        assert!(!is_base64(&value));
+        drop(db);
+        assert_eq!(client.get_points(&[0]).unwrap()[0].v, vec![1.0, 2.0]);
+        assert_eq!(client.get_neighbor(0, 0).unwrap(), LayerNode::new(0, 0));
+        assert_eq!(client.get_metadata(0).unwrap(), serde_json::json!({}));
+        assert_eq!(client.get_datasize().unwrap(), 1);
+        assert_eq!(client.get_num_layers().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_iter_metadatas_in_index_order() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-iter-metadatas");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(path);
+
+        // an index written by HollowDB, whose keys are sorted as strings
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            let db = DB::open(&opts, path).unwrap();
+            for idx in 0..12 {
+                db.put(format!("hollow.value.m:{}", idx), b"{}").unwrap();
+            }
+            db.put(b"hollow.value.datasize", b"12").unwrap();
+        }
+
+        let client = RocksdbClient::open(path, "hollow".to_string()).unwrap();
+        assert_eq!(client.format(), FORMAT_BASE64);
+        let indices = client
+            .iter_metadatas(Some(0))
+            .map(|item| item.unwrap().0)
+            .collect::<Vec<u32>>();
+        assert_eq!(indices, (1..12).collect::<Vec<u32>>());
+
+        client.migrate().unwrap();
+        let indices = client
+            .iter_metadatas(Some(0))
+            .map(|item| item.unwrap().0)
+            .collect::<Vec<u32>>();
+        assert_eq!(indices, (1..12).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_add_records() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-add-records");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(path);
+        let client = RocksdbClient::open(path, "hollow".to_string()).unwrap();
+
+        let vectors = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
+        let records = |start_idx, external_id: &str, replaced: Vec<u32>, tombstones| RecordBatch {
+            start_idx,
+            vectors: &vectors,
+            precision: Precision::F32,
+            metadata: vec![json!({ "i": start_idx }), json!({ "i": start_idx + 1 })],
+            external_ids: vec![(external_id.to_string(), start_idx as u32 + 1)],
+            replaced,
+            tombstones,
+        };
+        client.add_records(records(0, "a", vec![], vec![])).unwrap();
+
+        // the new record takes over the external id of the one it replaces
+        let mut node = LayerNode::new(0, 1);
+        node.visible = false;
+        client
+            .add_records(records(2, "a", vec![1], vec![node]))
+            .unwrap();
+        assert_eq!(client.get_datasize().unwrap(), 4);
+        assert_eq!(
+            client.get_indices_by_external_ids(&["a".into()]).unwrap(),
+            vec![Some(3)]
+        );
+        assert_eq!(
+            client.get_external_ids(&[1, 3]).unwrap(),
+            vec![None, Some("a".to_string())]
+        );
+        assert!(matches!(
+            client.get_metadata(1),
+            Err(DeserializeError::MissingKey)
+        ));
+        assert!(!client.get_neighbor(0, 1).unwrap().visible);
+        assert_eq!(client.get_points(&[3]).unwrap()[0].v, vec![3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_column_families() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-column-families");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(path);
+
+        let client = RocksdbClient::open(path, "new".to_string()).unwrap();
+        assert_eq!(client.format(), FORMAT_COLUMN_FAMILIES);
+
+        client.set_datasize(12).unwrap();
+        client
+            .set_metadata_batch(vec![serde_json::json!({}); 12], 0)
+            .unwrap();
+        client
+            .set_external_ids_batch(&[("a".to_string(), 2), ("b".to_string(), 10)])
+            .unwrap();
+        assert_eq!(client.get_datasize().unwrap(), 12);
+        assert_eq!(
+            client
+                .get_indices_by_external_ids(&["b".to_string(), "c".to_string()])
+                .unwrap(),
+            vec![Some(10), None]
+        );
+
+        // indices are in numeric order, and external ids are not mistaken for metadata
+        let indices = client
+            .iter_metadatas(Some(1))
+            .map(|item| item.unwrap().0)
+            .collect::<Vec<u32>>();
+        assert_eq!(indices, (2..12).collect::<Vec<u32>>());
+
+        client.delete_records(&[10]).unwrap();
+        assert_eq!(
+            client.get_external_ids(&[2, 10]).unwrap(),
+            vec![Some("a".to_string()), None]
+        );
+        assert_eq!(client.iter_metadatas(Some(9)).count(), 1);
+
+        // metadata is read in pages
+        client
+            .set_metadata_batch(vec![serde_json::json!({}); 600], 12)
+            .unwrap();
+        assert_eq!(client.iter_metadatas(None).count(), 611);
+
+        // points are read in pages too, the last one being full
+        let vectors = (0..2 * ITER_BATCH_SIZE)
+            .map(|i| vec![i as f32])
+            .collect::<Vec<Vec<f32>>>();
+        client
+            .add_points_batch(&vectors, 0, Precision::F32)
+            .unwrap();
+        let pages = client
+            .iter_points()
+            .collect::<Result<Vec<Vec<Point>>, DeserializeError>>()
+            .unwrap();
+        assert_eq!(pages.len(), 2);
+        let indices = pages.iter().flatten().map(|p| p.idx).collect::<Vec<u32>>();
+        assert_eq!(
+            indices,
+            (0..2 * ITER_BATCH_SIZE as u32).collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-snapshots");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(format!("{}-snapshots", path));
+
+        let client = RocksdbClient::open(path, "snapshotted".to_string()).unwrap();
+        assert_eq!(client.list_snapshots().unwrap(), Vec::<String>::new());
+
+        client.set_datasize(1).unwrap();
+        client.create_snapshot("first").unwrap();
+        assert!(matches!(
+            client.create_snapshot("first"),
+            Err(DeserializeError::AlreadyExists)
+        ));
+        assert!(matches!(
+            client.create_snapshot("../first"),
+            Err(DeserializeError::InvalidForm)
+        ));
+        client.set_datasize(2).unwrap();
+        client.create_snapshot("second").unwrap();
+        assert_eq!(client.list_snapshots().unwrap(), vec!["first", "second"]);
+
+        // a snapshot can be restored more than once, and the index is writable afterwards
+        client.set_datasize(3).unwrap();
+        client.restore_snapshot("first").unwrap();
+        assert_eq!(client.get_datasize().unwrap(), 1);
+        client.set_datasize(4).unwrap();
+        client.restore_snapshot("first").unwrap();
+        assert_eq!(client.get_datasize().unwrap(), 1);
+        client.restore_snapshot("second").unwrap();
+        assert_eq!(client.get_datasize().unwrap(), 2);
+
+        assert!(matches!(
+            client.restore_snapshot("third"),
+            Err(DeserializeError::MissingKey)
+        ));
+        assert_eq!(client.get_datasize().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_secondary() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-secondary");
+        let secondary_path = std::env::temp_dir().join("dria-hnsw-test-secondary-replica");
+        let (path, secondary_path) = (path.to_str().unwrap(), secondary_path.to_str().unwrap());
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(secondary_path);
+
+        let primary = RocksdbClient::open(path, "replicated".to_string()).unwrap();
+        primary.set_datasize(1).unwrap();
+
+        let replica =
+            RocksdbClient::open_secondary(path, secondary_path, "replicated".to_string()).unwrap();
+        assert!(replica.is_read_only());
+        assert_eq!(replica.format(), FORMAT_COLUMN_FAMILIES);
+        assert_eq!(replica.get_datasize().unwrap(), 1);
+        assert!(replica.set_datasize(5).is_err());
+
+        // writes of the primary are seen only after catching up
+        primary.set_datasize(2).unwrap();
+        assert_eq!(replica.get_datasize().unwrap(), 1);
+        assert_eq!(replica.catch_up().unwrap(), CatchUp::Written);
+        assert_eq!(replica.get_datasize().unwrap(), 2);
+        assert_eq!(replica.catch_up().unwrap(), CatchUp::Unchanged);
+    }
+
+    #[test]
+    fn test_secondary_after_migration() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-secondary-migration");
+        let secondary_path =
+            std::env::temp_dir().join("dria-hnsw-test-secondary-migration-replica");
+        let (path, secondary_path) = (path.to_str().unwrap(), secondary_path.to_str().unwrap());
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(secondary_path);
+
+        // an index written by HollowDB, which has no column families yet
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            let db = DB::open(&opts, path).unwrap();
+            let point = Point::new(vec![1.0, 2.0], 0);
+            db.put(b"hollow.value.0", point_to_base64(&point)).unwrap();
+            db.put(b"hollow.value.datasize", b"1").unwrap();
+        }
+        let replica =
+            RocksdbClient::open_secondary(path, secondary_path, "hollow".to_string()).unwrap();
+        assert_eq!(replica.format(), FORMAT_BASE64);
+
+        // the replica opens the column families that the primary creates while migrating
+        let primary = RocksdbClient::open(path, "hollow".to_string()).unwrap();
+        primary.migrate().unwrap();
+        assert_eq!(replica.catch_up().unwrap(), CatchUp::Reopened);
+        assert_eq!(replica.format(), FORMAT_COLUMN_FAMILIES);
+        assert_eq!(replica.get_datasize().unwrap(), 1);
+        assert_eq!(replica.get_points(&[0]).unwrap()[0].v, vec![1.0, 2.0]);
+
+        primary.set_datasize(2).unwrap();
+        assert_eq!(replica.catch_up().unwrap(), CatchUp::Written);
+        assert_eq!(replica.get_datasize().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_secondary_after_restore() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-secondary-restore");
+        let secondary_path = std::env::temp_dir().join("dria-hnsw-test-secondary-restore-replica");
+        let (path, secondary_path) = (path.to_str().unwrap(), secondary_path.to_str().unwrap());
+        let _ = std::fs::remove_dir_all(path);
+        let _ = std::fs::remove_dir_all(format!("{}-snapshots", path));
+        let _ = std::fs::remove_dir_all(secondary_path);
+
+        let primary = RocksdbClient::open(path, "restored".to_string()).unwrap();
+        primary.set_datasize(1).unwrap();
+        primary.create_snapshot("first").unwrap();
+        primary.set_datasize(2).unwrap();
+
+        let replica =
+            RocksdbClient::open_secondary(path, secondary_path, "restored".to_string()).unwrap();
+        assert_eq!(replica.get_datasize().unwrap(), 2);
+
+        // the replica follows the restored directory, rather than the one moved away
+        primary.restore_snapshot("first").unwrap();
+        assert_eq!(replica.catch_up().unwrap(), CatchUp::Reopened);
+        assert_eq!(replica.get_datasize().unwrap(), 1);
+        primary.set_datasize(3).unwrap();
+        assert_eq!(replica.catch_up().unwrap(), CatchUp::Written);
+        assert_eq!(replica.get_datasize().unwrap(), 3);
+    }
 }
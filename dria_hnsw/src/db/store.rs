@@ -0,0 +1,300 @@
+use crate::errors::errors::DeserializeError;
+use crate::hnsw::options::IndexOptions;
+use crate::hnsw::quantization::Quantizer;
+use crate::proto::index_buffer::{LayerNode, Point, PointQuant, Precision};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of entries read at once by [`VectorStore::iter_metadatas`] and [`VectorStore::iter_points`].
+pub(crate) const ITER_BATCH_SIZE: usize = 256;
+
+/// Whether a snapshot name can be used as a directory name, i.e. it has only alphanumerics, `-` and `_`.
+pub fn is_valid_snapshot_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A snapshot name from the current time, e.g. `snapshot-1718000000`.
+pub fn new_snapshot_name() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("snapshot-{}", now.as_secs())
+}
+
+/// Reads the points of a store by their indices in pages, skipping the missing ones.
+pub(crate) fn iter_points_by_index<S: VectorStore + ?Sized>(
+    store: &S,
+) -> Box<dyn Iterator<Item = Result<Vec<Point>, DeserializeError>> + '_> {
+    let datasize = match store.get_datasize() {
+        Ok(datasize) => datasize as u32,
+        Err(DeserializeError::MissingKey) => 0,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+
+    Box::new((0..datasize).step_by(ITER_BATCH_SIZE).map(move |from| {
+        let indices = (from..datasize.min(from + ITER_BATCH_SIZE as u32)).collect::<Vec<u32>>();
+        store
+            .get_points_partial(&indices)
+            .into_iter()
+            .filter(|point| !matches!(point, Err(DeserializeError::MissingKey)))
+            .collect()
+    }))
+}
+
+/// Reads the metadata of a store by their indices in pages, after the given index and skipping the missing ones.
+pub(crate) fn iter_metadatas_by_index<S: VectorStore + ?Sized>(
+    store: &S,
+    after: Option<u32>,
+) -> Box<dyn Iterator<Item = Result<(u32, Value), DeserializeError>> + '_> {
+    let datasize = match store.get_datasize() {
+        Ok(datasize) => datasize as u32,
+        Err(DeserializeError::MissingKey) => 0,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+    let start = after.map(|idx| idx + 1).unwrap_or(0);
+
+    let batches = (start..datasize)
+        .step_by(ITER_BATCH_SIZE)
+        .flat_map(move |from| {
+            let indices = (from..datasize.min(from + ITER_BATCH_SIZE as u32)).collect::<Vec<u32>>();
+            let metadatas = store.get_metadatas_partial(&indices);
+            indices.into_iter().zip(metadatas)
+        });
+
+    Box::new(batches.filter_map(|(idx, metadata)| match metadata {
+        Ok(metadata) => Some(Ok((idx, metadata))),
+        Err(DeserializeError::MissingKey) => None,
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// Held while writing to a store, so that writers of an index do not interleave; see [`VectorStore::lock_writes`].
+pub struct WriteLock<'a> {
+    _guard: Box<dyn Guard + 'a>,
+}
+
+trait Guard {}
+
+impl<T> Guard for T {}
+
+impl<'a> WriteLock<'a> {
+    /// Wraps a guard that releases the lock when dropped.
+    pub fn new<G: 'a>(guard: G) -> Self {
+        WriteLock {
+            _guard: Box::new(guard),
+        }
+    }
+}
+
+/// New records of an insertion, written at once by [`VectorStore::add_records`].
+pub struct RecordBatch<'a> {
+    /// Index of the first record.
+    pub start_idx: usize,
+    pub vectors: &'a [Vec<f32>],
+    pub precision: Precision,
+    pub metadata: Vec<Value>,
+    /// External ids of the new records, mapped to their indices.
+    pub external_ids: Vec<(String, u32)>,
+    /// Indices of the records replaced by new ones, whose metadata and external ids are deleted.
+    pub replaced: Vec<u32>,
+    /// Graph nodes of the replaced records that already exist, marked as deleted.
+    pub tombstones: Vec<LayerNode>,
+}
+
+/// Storage of an index, i.e. its points, graph nodes, metadata and headers.
+///
+/// Implemented by [`RocksdbClient`](crate::db::rocksdb_client::RocksdbClient),
+/// [`RedisClient`](crate::db::redis_client::RedisClient) and [`MemoryClient`](crate::db::memory_client::MemoryClient),
+/// and the server is given one of them as `Data<dyn VectorStore>`.
+pub trait VectorStore: Send + Sync {
+    /// Whether other servers may write to this store, in which case in-process caches can go stale.
+    fn is_shared(&self) -> bool {
+        false
+    }
+
+    /// Whether this store only serves reads, e.g. a replica of an index that another server writes to.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Blocks until no one else writes to this store, and holds off other writers until the lock is dropped.
+    fn lock_writes(&self) -> Result<WriteLock<'_>, DeserializeError>;
+
+    /// Number of writes to this store, which servers of a shared store compare to tell whether their caches are stale.
+    fn get_generation(&self) -> Result<u64, DeserializeError> {
+        Ok(0)
+    }
+
+    /// Counts a write to this store, i.e. an insertion or deletion, and returns the new generation.
+    fn bump_generation(&self) -> Result<u64, DeserializeError> {
+        Ok(0)
+    }
+
+    //*************** Graph ***************//
+
+    fn get_neighbors(
+        &self,
+        layer: usize,
+        indices: Vec<u32>,
+    ) -> Result<Vec<LayerNode>, DeserializeError>;
+
+    fn upsert_neighbors(&self, nodes: Vec<LayerNode>) -> Result<(), DeserializeError>;
+
+    fn get_neighbor(&self, layer: usize, idx: usize) -> Result<LayerNode, DeserializeError> {
+        self.get_neighbors(layer, vec![idx as u32])?
+            .pop()
+            .ok_or(DeserializeError::MissingKey)
+    }
+
+    fn upsert_neighbor(&self, node: LayerNode) -> Result<(), DeserializeError> {
+        self.upsert_neighbors(vec![node])
+    }
+
+    //*************** Points ***************//
+
+    /// Reports a result for each index instead of failing on the first error.
+    fn get_points_partial(&self, indices: &[u32]) -> Vec<Result<Point, DeserializeError>>;
+
+    fn add_points_batch(
+        &self,
+        v: &[Vec<f32>],
+        start_idx: usize,
+        precision: Precision,
+    ) -> Result<(), DeserializeError>;
+
+    fn get_points(&self, indices: &[u32]) -> Result<Vec<Point>, DeserializeError> {
+        self.get_points_partial(indices).into_iter().collect()
+    }
+
+    fn add_points(&self, v: Vec<f32>, idx: usize) -> Result<(), DeserializeError> {
+        self.add_points_batch(&[v], idx, Precision::F32)
+    }
+
+    /// Iterates over all points in pages, e.g. to compare a query to each of them.
+    ///
+    /// Stores may iterate in their own key order instead of index order.
+    fn iter_points(&self) -> Box<dyn Iterator<Item = Result<Vec<Point>, DeserializeError>> + '_> {
+        iter_points_by_index(self)
+    }
+
+    fn get_codes(&self, indices: &[u32]) -> Result<Vec<PointQuant>, DeserializeError>;
+
+    /// Stores the quantized codes of vectors, starting from the given index.
+    fn add_codes_batch(
+        &self,
+        codes: Vec<Vec<u8>>,
+        start_idx: usize,
+    ) -> Result<(), DeserializeError>;
+
+    //*************** Headers ***************//
+
+    fn get_datasize(&self) -> Result<usize, DeserializeError>;
+
+    fn set_datasize(&self, datasize: usize) -> Result<(), DeserializeError>;
+
+    fn get_num_layers(&self) -> Result<usize, DeserializeError>;
+
+    fn set_num_layers(&self, num_layers: usize) -> Result<(), DeserializeError>;
+
+    fn get_ep(&self) -> Result<usize, DeserializeError>;
+
+    fn set_ep(&self, ep: usize) -> Result<(), DeserializeError>;
+
+    /// Returns the options of this index, `None` if the index has none stored.
+    fn get_options(&self) -> Result<Option<IndexOptions>, DeserializeError>;
+
+    fn set_options(&self, options: &IndexOptions) -> Result<(), DeserializeError>;
+
+    /// Returns the trained quantizer of this index, `None` if it is not trained yet.
+    fn get_quantizer(&self) -> Result<Option<Quantizer>, DeserializeError>;
+
+    fn set_quantizer(&self, quantizer: &Quantizer) -> Result<(), DeserializeError>;
+
+    //*************** Metadata ***************//
+
+    /// Reports a result for each index instead of failing on the first error.
+    fn get_metadatas_partial(&self, indices: &[u32]) -> Vec<Result<Value, DeserializeError>>;
+
+    fn set_metadata_batch(&self, metadata: Vec<Value>, idx: usize) -> Result<(), DeserializeError>;
+
+    fn get_metadatas(&self, indices: Vec<u32>) -> Result<Vec<Value>, DeserializeError> {
+        self.get_metadatas_partial(&indices).into_iter().collect()
+    }
+
+    fn get_metadata(&self, idx: usize) -> Result<Value, DeserializeError> {
+        self.get_metadatas(vec![idx as u32])?
+            .pop()
+            .ok_or(DeserializeError::MissingKey)
+    }
+
+    fn set_metadata(&self, metadata: Value, idx: usize) -> Result<(), DeserializeError> {
+        self.set_metadata_batch(vec![metadata], idx)
+    }
+
+    /// Iterates over metadata entries in index order, starting right after the given index (if any).
+    ///
+    /// Deleted entries are skipped.
+    fn iter_metadatas(
+        &self,
+        after: Option<u32>,
+    ) -> Box<dyn Iterator<Item = Result<(u32, Value), DeserializeError>> + '_> {
+        iter_metadatas_by_index(self, after)
+    }
+
+    //*************** External ids ***************//
+
+    /// Returns the internal index for each external id, `None` if the external id is unknown.
+    fn get_indices_by_external_ids(
+        &self,
+        external_ids: &[String],
+    ) -> Result<Vec<Option<u32>>, DeserializeError>;
+
+    /// Returns the external id for each internal index, `None` if the index has no external id.
+    fn get_external_ids(&self, indices: &[u32]) -> Result<Vec<Option<String>>, DeserializeError>;
+
+    /// Maps each external id to its internal index, in both directions.
+    fn set_external_ids_batch(
+        &self,
+        external_ids: &[(String, u32)],
+    ) -> Result<(), DeserializeError>;
+
+    /// Deletes the metadata and external ids of the given indices.
+    ///
+    /// Points are kept, as deleted nodes are still used to traverse the graph.
+    fn delete_records(&self, indices: &[u32]) -> Result<(), DeserializeError>;
+
+    /// Writes new records along with the datasize, and deletes the records they replace.
+    ///
+    /// Stores that can write a batch atomically do so, so that an external id is never left
+    /// pointing at a deleted record.
+    fn add_records(&self, batch: RecordBatch) -> Result<(), DeserializeError> {
+        self.add_points_batch(batch.vectors, batch.start_idx, batch.precision)?;
+        self.set_metadata_batch(batch.metadata, batch.start_idx)?;
+        self.upsert_neighbors(batch.tombstones)?;
+        // the external ids of replaced records are deleted before they are mapped to the new ones
+        self.delete_records(&batch.replaced)?;
+        self.set_external_ids_batch(&batch.external_ids)?;
+        self.set_datasize(batch.start_idx + batch.vectors.len())
+    }
+
+    //*************** Snapshots ***************//
+
+    /// Saves a consistent, point-in-time copy of the store under the given name.
+    fn create_snapshot(&self, _name: &str) -> Result<(), DeserializeError> {
+        Err(DeserializeError::Unsupported)
+    }
+
+    /// Returns the names of the saved snapshots, in alphabetical order.
+    fn list_snapshots(&self) -> Result<Vec<String>, DeserializeError> {
+        Err(DeserializeError::Unsupported)
+    }
+
+    /// Replaces the contents of the store with a saved snapshot, which is kept.
+    fn restore_snapshot(&self, _name: &str) -> Result<(), DeserializeError> {
+        Err(DeserializeError::Unsupported)
+    }
+}
@@ -1,9 +1,8 @@
-use actix_web::{
-    error::ResponseError,
-    http::{header::ContentType, StatusCode},
-    HttpResponse,
-};
+use crate::responses::responses::ErrorResponse;
+use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
 use derive_more::{Display, Error};
+use log::error;
+use serde_json::{json, Value};
 use std::fmt;
 
 #[derive(Debug)]
@@ -14,6 +13,11 @@ pub enum DeserializeError {
     RedisConnectionError,
     DNSResolverError,
     ClusterConnectionError,
+    Unsupported,
+    AlreadyExists,
+    EmptyIndex,
+    DimensionMismatch { expected: usize, found: usize },
+    UnsupportedMetric,
 }
 
 impl fmt::Display for DeserializeError {
@@ -27,13 +31,22 @@ impl fmt::Display for DeserializeError {
             DeserializeError::ClusterConnectionError => {
                 write!(f, "Error connecting to Cluster at init")
             }
+            DeserializeError::Unsupported => write!(f, "Operation is not supported by the store"),
+            DeserializeError::AlreadyExists => write!(f, "Key already exists"),
+            DeserializeError::EmptyIndex => write!(f, "Index is empty"),
+            DeserializeError::DimensionMismatch { expected, found } => write!(
+                f,
+                "Vector has {} dimensions, but the index has {}",
+                found, expected
+            ),
+            DeserializeError::UnsupportedMetric => write!(f, "Distance metric is not supported"),
         }
     }
 }
 
 impl std::error::Error for DeserializeError {}
 
-#[derive(Debug, Display, Error)]
+#[derive(Debug, Clone, Copy, Display, Error)]
 pub enum MiddlewareError {
     #[display(fmt = "internal error")]
     InternalError,
@@ -47,9 +60,7 @@ pub enum MiddlewareError {
 
 impl ResponseError for MiddlewareError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::html())
-            .body(self.to_string())
+        ApiError::Middleware(*self).error_response()
     }
 
     fn status_code(&self) -> StatusCode {
@@ -71,3 +82,158 @@ impl fmt::Display for ValidationError {
 }
 
 impl std::error::Error for ValidationError {}
+
+/// Error of a request, responded with a stable [`ApiError::code`] that clients can branch on.
+#[derive(Debug)]
+pub enum ApiError {
+    Validation(ValidationError),
+    Store(DeserializeError),
+    Middleware(MiddlewareError),
+    /// The store is a read-only replica.
+    ReadOnly,
+    /// A batch has more records than can be inserted at once.
+    BatchTooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// Requested records that do not exist, by their internal and external ids.
+    RecordsNotFound {
+        ids: Vec<u32>,
+        external_ids: Vec<String>,
+    },
+    SnapshotNotFound(String),
+    SnapshotExists(String),
+    /// Snapshots are only supported by a writable RocksDB store.
+    SnapshotsUnsupported,
+    /// An unexpected error, described by what failed.
+    Internal(String),
+}
+
+impl ApiError {
+    /// Machine-readable kind of the error, which does not change between releases.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "invalid_request",
+            ApiError::Store(e) => match e {
+                DeserializeError::EmptyIndex => "empty_index",
+                DeserializeError::DimensionMismatch { .. } => "dimension_mismatch",
+                DeserializeError::AlreadyExists => "already_exists",
+                DeserializeError::Unsupported | DeserializeError::UnsupportedMetric => {
+                    "unsupported"
+                }
+                _ => "storage_error",
+            },
+            ApiError::Middleware(e) => match e {
+                MiddlewareError::InternalError => "internal_error",
+                MiddlewareError::APIKeyError => "unauthorized",
+                MiddlewareError::Timeout => "timeout",
+            },
+            ApiError::ReadOnly => "read_only",
+            ApiError::BatchTooLarge { .. } => "batch_too_large",
+            ApiError::RecordsNotFound { .. } => "records_not_found",
+            ApiError::SnapshotNotFound(_) => "snapshot_not_found",
+            ApiError::SnapshotExists(_) => "snapshot_exists",
+            ApiError::SnapshotsUnsupported => "snapshots_unsupported",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// What the error is about, `null` if there is nothing more than the message.
+    pub fn details(&self) -> Value {
+        match self {
+            ApiError::Store(DeserializeError::DimensionMismatch { expected, found }) => {
+                json!({ "expected": expected, "found": found })
+            }
+            ApiError::BatchTooLarge { size, max } => json!({ "size": size, "max": max }),
+            ApiError::RecordsNotFound { ids, external_ids } => {
+                json!({ "ids": ids, "external_ids": external_ids })
+            }
+            ApiError::SnapshotNotFound(name) | ApiError::SnapshotExists(name) => {
+                json!({ "name": name })
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Validation(e) => write!(f, "{}", e),
+            ApiError::Store(e) => write!(f, "{}", e),
+            ApiError::Middleware(e) => write!(f, "{}", e),
+            ApiError::ReadOnly => write!(f, "Index is read-only"),
+            ApiError::BatchTooLarge { max, .. } => {
+                write!(f, "Batch size should not be larger than {}", max)
+            }
+            ApiError::RecordsNotFound { .. } => write!(f, "Records not found"),
+            ApiError::SnapshotNotFound(name) => write!(f, "Snapshot {} not found", name),
+            ApiError::SnapshotExists(name) => write!(f, "Snapshot {} already exists", name),
+            ApiError::SnapshotsUnsupported => write!(
+                f,
+                "Snapshots are only supported by a writable RocksDB store"
+            ),
+            ApiError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<DeserializeError> for ApiError {
+    fn from(e: DeserializeError) -> Self {
+        ApiError::Store(e)
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(e: ValidationError) -> Self {
+        ApiError::Validation(e)
+    }
+}
+
+impl From<MiddlewareError> for ApiError {
+    fn from(e: MiddlewareError) -> Self {
+        ApiError::Middleware(e)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("{}", self);
+        }
+        HttpResponse::build(status).json(ErrorResponse {
+            success: false,
+            data: self.to_string(),
+            code: status.as_u16() as u32,
+            error: self.code(),
+            details: self.details(),
+        })
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Store(e) => match e {
+                DeserializeError::EmptyIndex => StatusCode::NOT_FOUND,
+                DeserializeError::DimensionMismatch { .. } => StatusCode::BAD_REQUEST,
+                DeserializeError::AlreadyExists => StatusCode::CONFLICT,
+                DeserializeError::Unsupported | DeserializeError::UnsupportedMetric => {
+                    StatusCode::NOT_IMPLEMENTED
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            ApiError::Middleware(e) => e.status_code(),
+            ApiError::ReadOnly => StatusCode::FORBIDDEN,
+            ApiError::BatchTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::RecordsNotFound { .. } | ApiError::SnapshotNotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            ApiError::SnapshotExists(_) => StatusCode::CONFLICT,
+            ApiError::SnapshotsUnsupported => StatusCode::NOT_IMPLEMENTED,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use serde_json::{Map, Value};
+
+/// Returns `true` if every field in `filter` is equal to the same top-level field of `metadata`.
+///
+/// An empty filter matches everything, and non-object metadata only matches an empty filter.
+pub fn matches_filter(filter: &Map<String, Value>, metadata: &Value) -> bool {
+    if filter.is_empty() {
+        return true;
+    }
+
+    match metadata {
+        Value::Object(map) => filter
+            .iter()
+            .all(|(key, expected)| map.get(key) == Some(expected)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_matches_filter() {
+        let metadata = json!({"lang": "en", "year": 2023, "text": "hello"});
+
+        let filter = json!({"lang": "en"});
+        assert!(matches_filter(filter.as_object().unwrap(), &metadata));
+
+        let filter = json!({"lang": "en", "year": 2024});
+        assert!(!matches_filter(filter.as_object().unwrap(), &metadata));
+
+        let filter = json!({"author": "dria"});
+        assert!(!matches_filter(filter.as_object().unwrap(), &metadata));
+
+        assert!(matches_filter(&Map::new(), &json!("plain text")));
+    }
+}
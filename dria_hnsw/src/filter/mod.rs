@@ -1 +1,3 @@
+pub mod metadata;
+pub mod projection;
 pub mod text_based;
\ No newline at end of file
@@ -0,0 +1,127 @@
+use crate::db::conversions::vector_to_base64;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// How returned vectors are encoded in a response.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorEncoding {
+    /// An array of floats.
+    #[default]
+    Float,
+    /// A base64 string of little-endian `f32` bytes.
+    Base64,
+}
+
+/// Describes which parts of a stored record are returned to the client.
+///
+/// - `include`: if given, only these top-level metadata fields are kept; an empty list drops metadata entirely.
+/// - `exclude`: these top-level metadata fields are removed, applied after `include`.
+/// - `include_vector`: whether the stored vector is returned as well.
+/// - `vector_encoding`: how the returned vector is encoded.
+#[derive(Debug, Clone, Default)]
+pub struct Projection {
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub include_vector: bool,
+    pub vector_encoding: VectorEncoding,
+}
+
+impl Projection {
+    pub fn new(
+        include: Option<Vec<String>>,
+        exclude: Option<Vec<String>>,
+        include_vector: Option<bool>,
+        vector_encoding: Option<VectorEncoding>,
+    ) -> Self {
+        Projection {
+            include,
+            exclude,
+            include_vector: include_vector.unwrap_or(false),
+            vector_encoding: vector_encoding.unwrap_or_default(),
+        }
+    }
+
+    /// Encodes a vector w.r.t. the requested encoding.
+    pub fn encode_vector(&self, v: &[f32]) -> Value {
+        match self.vector_encoding {
+            VectorEncoding::Float => json!(v),
+            VectorEncoding::Base64 => json!(vector_to_base64(v)),
+        }
+    }
+
+    /// Returns `false` if metadata is not needed at all, so that reading it from the database can be skipped.
+    pub fn needs_metadata(&self) -> bool {
+        !matches!(&self.include, Some(fields) if fields.is_empty())
+    }
+
+    /// Applies the field lists to a metadata value. Non-object metadata is returned as is.
+    pub fn apply(&self, metadata: Value) -> Value {
+        match metadata {
+            Value::Object(mut map) => {
+                if let Some(include) = &self.include {
+                    map.retain(|k, _| include.contains(k));
+                }
+                if let Some(exclude) = &self.exclude {
+                    map.retain(|k, _| !exclude.contains(k));
+                }
+                Value::Object(map)
+            }
+            other => other,
+        }
+    }
+
+    /// Applies the projection in-place to a search hit of the form `{"id", "score", "metadata", "vector"}`.
+    pub fn apply_to_hit(&self, hit: &mut Value) {
+        if let Value::Object(map) = hit {
+            if !self.include_vector {
+                map.remove("vector");
+            }
+            if !self.needs_metadata() {
+                map.remove("metadata");
+            } else if let Some(metadata) = map.remove("metadata") {
+                map.insert("metadata".to_string(), self.apply(metadata));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_projection_fields() {
+        let metadata = json!({"title": "dria", "text": "a long body", "url": "https://dria.co"});
+
+        let projection = Projection::new(Some(vec!["title".to_string()]), None, None, None);
+        assert_eq!(projection.apply(metadata.clone()), json!({"title": "dria"}));
+
+        let projection = Projection::new(None, Some(vec!["text".to_string()]), None, None);
+        assert_eq!(
+            projection.apply(metadata.clone()),
+            json!({"title": "dria", "url": "https://dria.co"})
+        );
+
+        let projection = Projection::default();
+        assert_eq!(projection.apply(metadata.clone()), metadata);
+        assert_eq!(projection.apply(json!("plain text")), json!("plain text"));
+    }
+
+    #[test]
+    fn test_projection_hit() {
+        let mut hit =
+            json!({"id": 0, "score": 0.9, "metadata": {"title": "dria"}, "vector": [1.0]});
+        Projection::new(Some(vec![]), None, None, None).apply_to_hit(&mut hit);
+        assert_eq!(hit, json!({"id": 0, "score": 0.9}));
+    }
+
+    #[test]
+    fn test_projection_vector_encoding() {
+        let projection = Projection::new(None, None, Some(true), Some(VectorEncoding::Base64));
+        assert_eq!(projection.encode_vector(&[1.0]), json!("AACAPw=="));
+
+        let projection = Projection::new(None, None, Some(true), None);
+        assert_eq!(projection.encode_vector(&[1.0]), json!([1.0]));
+    }
+}
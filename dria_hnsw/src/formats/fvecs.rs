@@ -0,0 +1,125 @@
+use super::invalid_data;
+use std::io::{self, BufReader, ErrorKind, Read};
+
+/// Reads the vectors of an `.fvecs` file, where each vector is its dimension as a little-endian `i32`
+/// followed by that many little-endian `f32`s.
+pub fn read<R: Read>(reader: R) -> impl Iterator<Item = io::Result<Vec<f32>>> {
+    read_words(reader).map(|words| Ok(words?.into_iter().map(f32::from_le_bytes).collect()))
+}
+
+/// Reads the vectors of an `.ivecs` file, which is laid out like an `.fvecs` file but with `i32`s,
+/// e.g. the indices of the nearest neighbors of each query in the ground truth of a dataset.
+pub fn read_ivecs<R: Read>(reader: R) -> impl Iterator<Item = io::Result<Vec<u32>>> {
+    read_words(reader).map(|words| {
+        words?
+            .into_iter()
+            .map(|w| {
+                u32::try_from(i32::from_le_bytes(w)).map_err(|_| invalid_data("Negative index"))
+            })
+            .collect()
+    })
+}
+
+/// Reads the vectors of 4-byte words, each prefixed by its dimension.
+fn read_words<R: Read>(reader: R) -> impl Iterator<Item = io::Result<Vec<[u8; 4]>>> {
+    let mut reader = BufReader::new(reader);
+    let mut dim = None;
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let result = read_vector(&mut reader, &mut dim);
+        if !matches!(result, Ok(Some(_))) {
+            done = true;
+        }
+        result.transpose()
+    })
+}
+
+/// Reads the next vector, `None` at the end of the file.
+fn read_vector<R: Read>(
+    reader: &mut R,
+    dim: &mut Option<usize>,
+) -> io::Result<Option<Vec<[u8; 4]>>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let d = i32::from_le_bytes(header);
+    if d <= 0 {
+        return Err(invalid_data(format!("Invalid vector dimension {}", d)));
+    }
+    let d = d as usize;
+    match *dim {
+        Some(expected) if expected != d => {
+            return Err(invalid_data(format!(
+                "Vector of dimension {} in a file of dimension {}",
+                d, expected
+            )))
+        }
+        _ => *dim = Some(d),
+    }
+
+    let mut bytes = vec![0u8; d * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| [b[0], b[1], b[2], b[3]])
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(vectors: &[Vec<f32>]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for v in vectors {
+            bytes.extend((v.len() as i32).to_le_bytes());
+            for x in v {
+                bytes.extend(x.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_read() {
+        let vectors = vec![vec![1.0, 2.0, 3.0], vec![-1.5, 0.0, 4.25]];
+        let read = read(encode(&vectors).as_slice())
+            .collect::<io::Result<Vec<Vec<f32>>>>()
+            .unwrap();
+        assert_eq!(read, vectors);
+
+        // a truncated vector is an error, rather than the end of the file
+        let bytes = encode(&vectors);
+        let read = super::read(&bytes[..bytes.len() - 2]).collect::<Vec<_>>();
+        assert_eq!(read.len(), 2);
+        assert!(read[1].is_err());
+
+        let mixed = encode(&[vec![1.0], vec![1.0, 2.0]]);
+        assert!(super::read(mixed.as_slice()).any(|v| v.is_err()));
+    }
+
+    #[test]
+    fn test_read_ivecs() {
+        let mut bytes = Vec::new();
+        for v in [[3i32, 1, 4], [1, 5, 9]] {
+            bytes.extend(3i32.to_le_bytes());
+            for x in v {
+                bytes.extend(x.to_le_bytes());
+            }
+        }
+        let read = read_ivecs(bytes.as_slice())
+            .collect::<io::Result<Vec<Vec<u32>>>>()
+            .unwrap();
+        assert_eq!(read, vec![vec![3, 1, 4], vec![1, 5, 9]]);
+    }
+}
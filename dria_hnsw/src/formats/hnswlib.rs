@@ -0,0 +1,463 @@
+use super::invalid_data;
+use crate::db::store::VectorStore;
+use crate::errors::errors::DeserializeError;
+use crate::hnsw::options::IndexOptions;
+use crate::proto::index_buffer::LayerNode;
+use serde_json::Value;
+use simsimd::SimSIMD;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// Flag of a deleted element, in the third byte of its level 0 link list.
+const DELETE_MARK: u8 = 0x01;
+
+/// Number of elements read from or written to the store at once.
+const BATCH_SIZE: usize = 10_000;
+
+/// An index in the format of hnswlib's `saveIndex`, see <https://github.com/nmslib/hnswlib/blob/master/hnswlib/hnswalg.h>.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HnswlibIndex {
+    /// Maximum number of neighbors above level 0.
+    pub m: usize,
+    /// Maximum number of neighbors at level 0.
+    pub m_max0: usize,
+    pub ef_construction: usize,
+    /// Normalization factor of the level assignment, `1 / ln(M)`.
+    pub ml: f64,
+    /// Element that searches start from, `None` if the index is empty.
+    pub entry_point: Option<u32>,
+    /// Elements by their internal id.
+    pub elements: Vec<Element>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    /// Label of the element in hnswlib, i.e. its id outside of the index.
+    pub label: u64,
+    pub deleted: bool,
+    pub vector: Vec<f32>,
+    /// Neighbors of the element at each level it is in, from level 0 upwards.
+    pub neighbors: Vec<Vec<u32>>,
+}
+
+/// Reads an index saved by hnswlib.
+///
+/// The file does not record the space of the index, and the dimension is inferred from the size of the elements.
+pub fn read<R: Read>(reader: R) -> io::Result<HnswlibIndex> {
+    let mut reader = BufReader::new(reader);
+    let r = &mut reader;
+
+    let offset_level0 = read_size(r)?;
+    let _max_elements = read_size(r)?;
+    let count = read_size(r)?;
+    let size_data_per_element = read_size(r)?;
+    let label_offset = read_size(r)?;
+    let offset_data = read_size(r)?;
+    let max_level = i32::from_le_bytes(read_bytes(r)?);
+    let entry_point = u32::from_le_bytes(read_bytes(r)?);
+    let m = read_size(r)?;
+    let m_max0 = read_size(r)?;
+    let _m = read_size(r)?;
+    let ml = f64::from_le_bytes(read_bytes(r)?);
+    let ef_construction = read_size(r)?;
+
+    // level 0 elements are their link list, then their vector, then their label
+    let size_links0 = m_max0 * 4 + 4;
+    let size_links = m * 4 + 4;
+    if offset_level0 != 0
+        || offset_data != size_links0
+        || label_offset < offset_data
+        || (label_offset - offset_data) % 4 != 0
+        || size_data_per_element != label_offset + 8
+    {
+        return Err(invalid_data("Invalid hnswlib index header"));
+    }
+
+    let mut elements = Vec::new();
+    let mut data = vec![0u8; size_data_per_element];
+    for _ in 0..count {
+        r.read_exact(&mut data)?;
+        elements.push(Element {
+            label: u64::from_le_bytes(data[label_offset..].try_into().unwrap()),
+            deleted: data[2] & DELETE_MARK != 0,
+            vector: data[offset_data..label_offset]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            neighbors: vec![read_links(&data[..size_links0], m_max0, count)?],
+        });
+    }
+
+    // followed by the link lists of the levels above 0, prefixed by their total size
+    for element in elements.iter_mut() {
+        let size = u32::from_le_bytes(read_bytes(r)?) as usize;
+        if size % size_links != 0 {
+            return Err(invalid_data("Invalid hnswlib link list"));
+        }
+        let mut links = vec![0u8; size];
+        r.read_exact(&mut links)?;
+        for level in links.chunks_exact(size_links) {
+            element.neighbors.push(read_links(level, m, count)?);
+        }
+    }
+
+    let entry_point = match elements.get(entry_point as usize) {
+        Some(element) if element.neighbors.len() as i32 == max_level + 1 => Some(entry_point),
+        None if count == 0 => None,
+        _ => return Err(invalid_data("Invalid hnswlib entry point")),
+    };
+
+    Ok(HnswlibIndex {
+        m,
+        m_max0,
+        ef_construction,
+        ml,
+        entry_point,
+        elements,
+    })
+}
+
+/// Writes an index in the format that hnswlib loads with `loadIndex`.
+pub fn write<W: Write>(index: &HnswlibIndex, writer: W) -> io::Result<()> {
+    let mut w = BufWriter::new(writer);
+
+    let dim = index.elements.first().map_or(0, |e| e.vector.len());
+    let size_links0 = index.m_max0 * 4 + 4;
+    let size_links = index.m * 4 + 4;
+    let label_offset = size_links0 + dim * 4;
+    let count = index.elements.len();
+    let max_level = index.entry_point.map_or(-1, |ep| {
+        index.elements[ep as usize].neighbors.len() as i32 - 1
+    });
+
+    for size in [0, count, count, label_offset + 8, label_offset, size_links0] {
+        w.write_all(&(size as u64).to_le_bytes())?;
+    }
+    w.write_all(&max_level.to_le_bytes())?;
+    w.write_all(&index.entry_point.unwrap_or(u32::MAX).to_le_bytes())?;
+    for size in [index.m, index.m_max0, index.m] {
+        w.write_all(&(size as u64).to_le_bytes())?;
+    }
+    w.write_all(&index.ml.to_le_bytes())?;
+    w.write_all(&(index.ef_construction as u64).to_le_bytes())?;
+
+    for element in &index.elements {
+        if element.vector.len() != dim {
+            return Err(invalid_data(
+                "Vectors of the index have different dimensions",
+            ));
+        }
+        let links = element
+            .neighbors
+            .first()
+            .ok_or_else(|| invalid_data("Element without level 0"))?;
+        write_links(&mut w, links, index.m_max0, element.deleted)?;
+        for x in &element.vector {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        w.write_all(&element.label.to_le_bytes())?;
+    }
+
+    for element in &index.elements {
+        let levels = &element.neighbors[1..];
+        w.write_all(&((levels.len() * size_links) as u32).to_le_bytes())?;
+        for links in levels {
+            write_links(&mut w, links, index.m, false)?;
+        }
+    }
+
+    w.flush()
+}
+
+/// Writes an index into an empty store, keeping the levels and neighbors of its elements.
+///
+/// Internal ids of hnswlib become the indices of the store, and labels become external ids.
+/// Neighbors are stored with their cosine distance, which is what the server searches with,
+/// so the index should be built in the `cosine` space, or in the `ip` space over normalized vectors.
+pub fn import(
+    index: &HnswlibIndex,
+    store: &dyn VectorStore,
+    options: &IndexOptions,
+    mut metadata: Box<dyn Iterator<Item = io::Result<Value>>>,
+) -> io::Result<()> {
+    store.set_options(options).map_err(store_error)?;
+
+    for (i, chunk) in index.elements.chunks(BATCH_SIZE).enumerate() {
+        let start = i * BATCH_SIZE;
+        let indices = (start..start + chunk.len())
+            .map(|idx| idx as u32)
+            .collect::<Vec<u32>>();
+
+        let vectors = chunk
+            .iter()
+            .map(|element| element.vector.clone())
+            .collect::<Vec<Vec<f32>>>();
+        let metadata_batch = chunk
+            .iter()
+            .map(|_| {
+                metadata.next().unwrap_or_else(|| {
+                    Err(invalid_data(
+                        "Metadata has fewer lines than there are elements",
+                    ))
+                })
+            })
+            .collect::<io::Result<Vec<Value>>>()?;
+        // labels of deleted elements may have been reused by others
+        let external_ids = indices
+            .iter()
+            .zip(chunk)
+            .filter(|(_, element)| !element.deleted)
+            .map(|(&idx, element)| (element.label.to_string(), idx))
+            .collect::<Vec<(String, u32)>>();
+        let deleted = indices
+            .iter()
+            .zip(chunk)
+            .filter(|(_, element)| element.deleted)
+            .map(|(&idx, _)| idx)
+            .collect::<Vec<u32>>();
+
+        let mut nodes = Vec::new();
+        for (&idx, element) in indices.iter().zip(chunk) {
+            for (level, links) in element.neighbors.iter().enumerate() {
+                let mut node = LayerNode::new(level, idx as usize);
+                node.visible = level > 0 || !element.deleted;
+                node.neighbors = links
+                    .iter()
+                    .map(|&n| {
+                        let v = &index.elements[n as usize].vector;
+                        (n, SimSIMD::cosine(&element.vector, v).unwrap_or(f32::MAX))
+                    })
+                    .collect();
+                nodes.push(node);
+            }
+        }
+
+        store
+            .add_points_batch(&vectors, start, options.precision)
+            .map_err(store_error)?;
+        store
+            .set_metadata_batch(metadata_batch, start)
+            .map_err(store_error)?;
+        store
+            .set_external_ids_batch(&external_ids)
+            .map_err(store_error)?;
+        store.upsert_neighbors(nodes).map_err(store_error)?;
+        if !deleted.is_empty() {
+            store.delete_records(&deleted).map_err(store_error)?;
+        }
+    }
+
+    // headers are written last, so that an interrupted import is not taken for an index
+    store
+        .set_datasize(index.elements.len())
+        .map_err(store_error)?;
+    if let Some(ep) = index.entry_point {
+        let num_layers = index.elements[ep as usize].neighbors.len();
+        store.set_num_layers(num_layers).map_err(store_error)?;
+        store.set_ep(ep as usize).map_err(store_error)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the index of a store, with at most `m` neighbors per element above level 0 and twice as many at level 0.
+///
+/// Labels are the external ids of the elements if they are integers, otherwise their indices.
+/// Longer neighbor lists keep their closest neighbors.
+pub fn export(
+    store: &dyn VectorStore,
+    m: usize,
+    ef_construction: usize,
+) -> io::Result<HnswlibIndex> {
+    let mut index = HnswlibIndex {
+        m,
+        m_max0: m * 2,
+        ef_construction,
+        ml: 1.0 / (m as f64).ln(),
+        entry_point: None,
+        elements: Vec::new(),
+    };
+
+    let num_layers = match store.get_num_layers() {
+        Ok(num_layers) => num_layers,
+        Err(DeserializeError::MissingKey) => return Ok(index),
+        Err(e) => return Err(store_error(e)),
+    };
+    let datasize = store.get_datasize().map_err(store_error)?;
+
+    for start in (0..datasize).step_by(BATCH_SIZE) {
+        let indices = (start..datasize.min(start + BATCH_SIZE))
+            .map(|idx| idx as u32)
+            .collect::<Vec<u32>>();
+        let points = store.get_points(&indices).map_err(store_error)?;
+        let nodes = store
+            .get_neighbors(0, indices.clone())
+            .map_err(store_error)?;
+        let external_ids = store.get_external_ids(&indices).map_err(store_error)?;
+
+        for (((idx, point), node), external_id) in
+            indices.iter().zip(points).zip(nodes).zip(external_ids)
+        {
+            index.elements.push(Element {
+                label: external_id
+                    .and_then(|id| id.parse().ok())
+                    .unwrap_or(*idx as u64),
+                deleted: !node.visible,
+                vector: point.vector().into_owned(),
+                neighbors: vec![closest(&node, index.m_max0)],
+            });
+        }
+    }
+
+    // a node is in every level below its own, so each level is looked up among the nodes of the level below
+    let mut members = (0..datasize).collect::<Vec<usize>>();
+    for level in 1..num_layers {
+        let mut next = Vec::new();
+        for idx in members {
+            match store.get_neighbor(level, idx) {
+                Ok(node) => {
+                    index.elements[idx].neighbors.push(closest(&node, m));
+                    next.push(idx);
+                }
+                Err(DeserializeError::MissingKey) => {}
+                Err(e) => return Err(store_error(e)),
+            }
+        }
+        members = next;
+    }
+
+    index.entry_point = Some(store.get_ep().map_err(store_error)? as u32);
+    Ok(index)
+}
+
+fn store_error(e: DeserializeError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads a `size_t`, which hnswlib writes as a little-endian `u64`.
+fn read_size<R: Read>(reader: &mut R) -> io::Result<usize> {
+    usize::try_from(u64::from_le_bytes(read_bytes(reader)?))
+        .map_err(|_| invalid_data("Invalid hnswlib index header"))
+}
+
+/// Reads a link list, i.e. the number of neighbors in the first 2 bytes, then the space for `max` neighbors.
+fn read_links(bytes: &[u8], max: usize, count: usize) -> io::Result<Vec<u32>> {
+    let size = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+    if size > max {
+        return Err(invalid_data("Invalid hnswlib link list"));
+    }
+    bytes[4..4 + size * 4]
+        .chunks_exact(4)
+        .map(|b| match u32::from_le_bytes([b[0], b[1], b[2], b[3]]) {
+            n if (n as usize) < count => Ok(n),
+            _ => Err(invalid_data("Neighbor out of the hnswlib index")),
+        })
+        .collect()
+}
+
+fn write_links<W: Write>(w: &mut W, links: &[u32], max: usize, deleted: bool) -> io::Result<()> {
+    w.write_all(&(links.len() as u16).to_le_bytes())?;
+    w.write_all(&[if deleted { DELETE_MARK } else { 0 }, 0])?;
+    for n in links {
+        w.write_all(&n.to_le_bytes())?;
+    }
+    w.write_all(&vec![0u8; (max - links.len()) * 4])
+}
+
+/// Neighbors of a node, at most `max` of the closest.
+fn closest(node: &LayerNode, max: usize) -> Vec<u32> {
+    let mut neighbors = node.neighbors.iter().collect::<Vec<(&u32, &f32)>>();
+    neighbors.sort_by(|a, b| a.1.total_cmp(b.1).then(a.0.cmp(b.0)));
+    neighbors.into_iter().take(max).map(|(&n, _)| n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory_client::MemoryClient;
+    use serde_json::json;
+
+    fn index() -> HnswlibIndex {
+        HnswlibIndex {
+            m: 2,
+            m_max0: 4,
+            ef_construction: 100,
+            ml: 1.0 / 2f64.ln(),
+            entry_point: Some(1),
+            elements: vec![
+                Element {
+                    label: 10,
+                    deleted: false,
+                    vector: vec![1.0, 0.0],
+                    neighbors: vec![vec![1, 2]],
+                },
+                Element {
+                    label: 11,
+                    deleted: false,
+                    vector: vec![0.0, 1.0],
+                    neighbors: vec![vec![0, 2], vec![2], vec![]],
+                },
+                Element {
+                    label: 12,
+                    deleted: true,
+                    vector: vec![1.0, 1.0],
+                    neighbors: vec![vec![0, 1], vec![1]],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut bytes = Vec::new();
+        write(&index(), &mut bytes).unwrap();
+
+        // header, level 0 elements of 4 + 4 * 4 + 2 * 4 + 8 bytes, and the levels above
+        assert_eq!(bytes.len(), 96 + 3 * 36 + (4 + 4 + 2 * 12 + 4 + 12));
+        assert_eq!(read(bytes.as_slice()).unwrap(), index());
+
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_import_export() {
+        let store = MemoryClient::new();
+        let metadata = (0..3).map(|i| Ok(json!({ "i": i })));
+        import(
+            &index(),
+            &store,
+            &IndexOptions::default(),
+            Box::new(metadata),
+        )
+        .unwrap();
+
+        assert_eq!(store.get_datasize().unwrap(), 3);
+        assert_eq!(store.get_num_layers().unwrap(), 3);
+        assert_eq!(store.get_ep().unwrap(), 1);
+        assert_eq!(store.get_metadata(1).unwrap(), json!({ "i": 1 }));
+        assert!(store.get_metadata(2).is_err());
+        assert!(!store.get_neighbor(0, 2).unwrap().visible);
+        assert_eq!(
+            store
+                .get_indices_by_external_ids(&["11".to_string(), "12".to_string()])
+                .unwrap(),
+            vec![Some(1), None]
+        );
+
+        let exported = export(&store, 2, 100).unwrap();
+        let mut expected = index();
+        // the label of a deleted element is not kept
+        expected.elements[2].label = 2;
+        // neighbors are ordered by distance rather than as they were given
+        let mut exported = exported;
+        for element in exported.elements.iter_mut() {
+            element.neighbors.iter_mut().for_each(|links| links.sort());
+        }
+        assert_eq!(exported, expected);
+    }
+}
@@ -0,0 +1,41 @@
+use super::invalid_data;
+use crate::models::request_models::InsertModel;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Reads records from JSON lines, each in the format of a record of `insert_vector`,
+/// i.e. with a `vector`, its `metadata` and an optional `external_id`. Blank lines are skipped.
+pub fn read<R: Read>(reader: R) -> impl Iterator<Item = io::Result<InsertModel>> {
+    BufReader::new(reader)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str::<InsertModel>(&line?)
+                .map_err(|e| invalid_data(format!("Invalid record at line {}: {}", i + 1, e)))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_read() {
+        let lines = r#"{"vector": [1.0, 2.0], "metadata": {"a": 1}, "external_id": "x"}
+
+{"vector": [3.0, 4.0], "metadata": {}}
+{"vector": [5.0]}"#;
+        let records = read(lines.as_bytes()).collect::<Vec<io::Result<InsertModel>>>();
+        assert_eq!(records.len(), 3);
+
+        let first = records[0].as_ref().unwrap();
+        assert_eq!(first.vector, vec![1.0, 2.0]);
+        assert_eq!(first.metadata, json!({"a": 1}));
+        assert_eq!(first.external_id.as_deref(), Some("x"));
+        assert_eq!(records[1].as_ref().unwrap().external_id, None);
+
+        let error = records[2].as_ref().unwrap_err().to_string();
+        assert!(error.contains("line 4"), "{}", error);
+    }
+}
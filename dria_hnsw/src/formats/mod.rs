@@ -0,0 +1,107 @@
+//! Readers of vector files and hnswlib indices, used to build an index offline, see `dria_hnsw-cli`.
+pub mod fvecs;
+pub mod hnswlib;
+pub mod jsonl;
+pub mod npy;
+pub mod parquet;
+
+use crate::models::request_models::InsertModel;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Records of a vector file, in the order they are stored.
+pub type Records = Box<dyn Iterator<Item = io::Result<InsertModel>>>;
+
+/// Error for a file that is not in the expected format.
+pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Supported vector files, see [`Format::from_path`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Little-endian `f32` vectors, each prefixed by its dimension as an `i32`.
+    Fvecs,
+    /// A 2-dimensional NumPy array of `f32` or `f64`.
+    Npy,
+    /// One JSON object per line, in the format of the records of `insert_vector`.
+    Jsonl,
+    /// A `vector` list column of `f32` or `f64`, with the metadata in the other columns.
+    Parquet,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "fvecs" => Some(Format::Fvecs),
+            "npy" => Some(Format::Npy),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            "parquet" => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+
+    /// Infers the format of a file from its extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        Format::from_name(path.extension()?.to_str()?)
+    }
+}
+
+/// Reads the records of a vector file.
+///
+/// Files without metadata, i.e. other than [`Format::Jsonl`] and [`Format::Parquet`], may be given a JSONL file with the metadata
+/// of each vector on the same line number, otherwise their metadata is empty.
+pub fn read_records(path: &Path, format: Format, metadata: Option<&Path>) -> io::Result<Records> {
+    let records: Records = match format {
+        Format::Jsonl => {
+            if metadata.is_some() {
+                return Err(invalid_data(
+                    "JSONL files have their own metadata, it can not be given separately",
+                ));
+            }
+            return Ok(Box::new(jsonl::read(File::open(path)?)));
+        }
+        Format::Parquet => {
+            if metadata.is_some() {
+                return Err(invalid_data(
+                    "Parquet files have their own metadata, it can not be given separately",
+                ));
+            }
+            return Ok(Box::new(parquet::read(File::open(path)?)?));
+        }
+        Format::Fvecs => Box::new(fvecs::read(File::open(path)?).map(to_record)),
+        Format::Npy => Box::new(npy::read(File::open(path)?)?.map(to_record)),
+    };
+
+    match metadata {
+        Some(metadata) => {
+            let mut metadata = read_metadata(metadata)?;
+            Ok(Box::new(records.map(move |record| {
+                let mut record = record?;
+                record.metadata = metadata.next().ok_or_else(|| {
+                    invalid_data("Metadata has fewer lines than there are vectors")
+                })??;
+                Ok(record)
+            })))
+        }
+        None => Ok(records),
+    }
+}
+
+/// Reads a JSONL file with the metadata of each vector on its line.
+pub fn read_metadata(path: &Path) -> io::Result<impl Iterator<Item = io::Result<Value>>> {
+    let lines = BufReader::new(File::open(path)?).lines();
+    Ok(lines.map(|line| {
+        serde_json::from_str(&line?).map_err(|e| invalid_data(format!("Invalid metadata: {}", e)))
+    }))
+}
+
+fn to_record(vector: io::Result<Vec<f32>>) -> io::Result<InsertModel> {
+    Ok(InsertModel {
+        vector: vector?,
+        metadata: json!({}),
+        external_id: None,
+    })
+}
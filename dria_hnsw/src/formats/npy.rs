@@ -0,0 +1,184 @@
+use super::invalid_data;
+use std::io::{self, BufReader, Read};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Type of the elements of an array.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dtype {
+    F32,
+    F64,
+}
+
+/// Header of an `.npy` file, see <https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>.
+#[derive(Debug, PartialEq)]
+struct Header {
+    dtype: Dtype,
+    rows: usize,
+    cols: usize,
+}
+
+/// Reads the rows of a 2-dimensional `.npy` array of little-endian `f32` or `f64`, in C order.
+///
+/// `f64` values are converted to `f32`.
+pub fn read<R: Read + 'static>(
+    reader: R,
+) -> io::Result<impl Iterator<Item = io::Result<Vec<f32>>>> {
+    let mut reader = BufReader::new(reader);
+    let header = read_header(&mut reader)?;
+
+    let width = match header.dtype {
+        Dtype::F32 => 4,
+        Dtype::F64 => 8,
+    };
+    let mut row = vec![0u8; header.cols * width];
+    Ok((0..header.rows).map(move |_| {
+        reader.read_exact(&mut row)?;
+        Ok(match header.dtype {
+            Dtype::F32 => row
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            Dtype::F64 => row
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+                .collect(),
+        })
+    }))
+}
+
+fn read_header<R: Read>(reader: &mut R) -> io::Result<Header> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid_data("Not an .npy file"));
+    }
+
+    // the header length is a u16 in version 1, and a u32 afterwards
+    let len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => {
+            return Err(invalid_data(format!(
+                "Unsupported .npy version {}",
+                version
+            )))
+        }
+    };
+    let mut header = vec![0u8; len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8(header).map_err(|_| invalid_data("Invalid .npy header"))?;
+
+    parse_header(&header)
+}
+
+/// Parses the Python dictionary of the header, e.g. `{'descr': '<f4', 'fortran_order': False, 'shape': (10, 4), }`.
+fn parse_header(header: &str) -> io::Result<Header> {
+    let dtype = match field(header, "descr") {
+        Some("'<f4'") => Dtype::F32,
+        Some("'<f8'") => Dtype::F64,
+        descr => {
+            return Err(invalid_data(format!(
+                "Unsupported .npy type {}, expected little-endian float32 or float64",
+                descr.unwrap_or("(none)")
+            )))
+        }
+    };
+
+    if field(header, "fortran_order") != Some("False") {
+        return Err(invalid_data(
+            "Fortran-ordered .npy arrays are not supported",
+        ));
+    }
+
+    let shape = field(header, "shape")
+        .and_then(|shape| shape.strip_prefix('('))
+        .and_then(|shape| shape.strip_suffix(')'))
+        .ok_or_else(|| invalid_data("Invalid .npy shape"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| invalid_data("Invalid .npy shape"))?;
+    match shape[..] {
+        [rows, cols] => Ok(Header { dtype, rows, cols }),
+        _ => Err(invalid_data(format!(
+            "Expected a 2-dimensional .npy array, found {} dimensions",
+            shape.len()
+        ))),
+    }
+}
+
+/// Value of a key in the header dictionary, as written.
+fn field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(header: &str, values: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([1, 0]);
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(values);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (10, 4), }";
+        assert_eq!(
+            parse_header(header).unwrap(),
+            Header {
+                dtype: Dtype::F64,
+                rows: 10,
+                cols: 4
+            }
+        );
+        assert!(
+            parse_header("{'descr': '<f4', 'fortran_order': False, 'shape': (10,), }").is_err()
+        );
+        assert!(
+            parse_header("{'descr': '<f4', 'fortran_order': True, 'shape': (1, 2), }").is_err()
+        );
+        assert!(
+            parse_header("{'descr': '<i8', 'fortran_order': False, 'shape': (1, 2), }").is_err()
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let values = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let bytes = encode(
+            "{'descr': '<f4', 'fortran_order': False, 'shape': (3, 2), }",
+            &values,
+        );
+        let rows = read(io::Cursor::new(bytes))
+            .unwrap()
+            .collect::<io::Result<Vec<Vec<f32>>>>()
+            .unwrap();
+        assert_eq!(rows, vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]]);
+    }
+}
@@ -0,0 +1,267 @@
+use super::invalid_data;
+use crate::models::request_models::InsertModel;
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{Array, RecordBatch};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::DataType;
+use serde_json::{json, Map, Number, Value};
+use std::fs::File;
+use std::io;
+
+/// Column of the vectors.
+const VECTOR: &str = "vector";
+
+/// Optional column of the external ids.
+const EXTERNAL_ID: &str = "external_id";
+
+/// Reads records from a Parquet file, with their vectors in a `vector` column, a list of `f32` or `f64`,
+/// and an optional `external_id` column of strings or integers.
+///
+/// Every other column is a key of the metadata, which omits its null values. Values that are not booleans,
+/// numbers, strings, lists or structs, e.g. timestamps, are formatted as strings.
+pub fn read(file: File) -> io::Result<impl Iterator<Item = io::Result<InsertModel>>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)
+        .map_err(|e| invalid_data(format!("Invalid Parquet file: {}", e)))?;
+    if builder.schema().column_with_name(VECTOR).is_none() {
+        return Err(invalid_data(format!(
+            "Parquet file has no {} column",
+            VECTOR
+        )));
+    }
+    let reader = builder
+        .build()
+        .map_err(|e| invalid_data(format!("Invalid Parquet file: {}", e)))?;
+
+    Ok(reader.flat_map(|batch| {
+        let records = batch
+            .map_err(|e| invalid_data(format!("Invalid Parquet file: {}", e)))
+            .and_then(|batch| to_records(&batch));
+        match records {
+            Ok(records) => records.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        }
+    }))
+}
+
+fn to_records(batch: &RecordBatch) -> io::Result<Vec<InsertModel>> {
+    let schema = batch.schema();
+    let mut vectors = None;
+    let mut external_ids = None;
+    let mut columns = Vec::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        match field.name().as_str() {
+            VECTOR => vectors = Some(column),
+            EXTERNAL_ID => external_ids = Some(column),
+            name => columns.push((name, column)),
+        }
+    }
+    let vectors = vectors.ok_or_else(|| invalid_data("Parquet file has no vector column"))?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let external_id = match external_ids {
+                Some(column) => match to_json(column.as_ref(), row)? {
+                    Value::Null => None,
+                    Value::String(id) => Some(id),
+                    Value::Number(id) if id.is_u64() || id.is_i64() => Some(id.to_string()),
+                    _ => return Err(invalid_data("External ids should be strings or integers")),
+                },
+                None => None,
+            };
+
+            let mut metadata = Map::new();
+            for (name, column) in &columns {
+                let value = to_json(column.as_ref(), row)?;
+                if !value.is_null() {
+                    metadata.insert(name.to_string(), value);
+                }
+            }
+
+            Ok(InsertModel {
+                vector: to_vector(vectors.as_ref(), row)?,
+                metadata: Value::Object(metadata),
+                external_id,
+            })
+        })
+        .collect()
+}
+
+/// Vector of a row of a list column.
+fn to_vector(column: &dyn Array, row: usize) -> io::Result<Vec<f32>> {
+    if column.is_null(row) {
+        return Err(invalid_data("Vectors can not be null"));
+    }
+    let values = match column.data_type() {
+        DataType::List(_) => column.as_list::<i32>().value(row),
+        DataType::LargeList(_) => column.as_list::<i64>().value(row),
+        DataType::FixedSizeList(_, _) => column.as_fixed_size_list().value(row),
+        data_type => {
+            return Err(invalid_data(format!(
+                "The vector column should be a list, not {}",
+                data_type
+            )))
+        }
+    };
+    if values.null_count() > 0 {
+        return Err(invalid_data("Vectors can not have null values"));
+    }
+
+    match values.data_type() {
+        DataType::Float32 => Ok(values.as_primitive::<Float32Type>().values().to_vec()),
+        DataType::Float64 => Ok(values
+            .as_primitive::<Float64Type>()
+            .values()
+            .iter()
+            .map(|&v| v as f32)
+            .collect()),
+        data_type => Err(invalid_data(format!(
+            "Vectors should be lists of f32 or f64, not {}",
+            data_type
+        ))),
+    }
+}
+
+/// JSON value of a row of a column.
+fn to_json(column: &dyn Array, row: usize) -> io::Result<Value> {
+    if column.is_null(row) {
+        return Ok(Value::Null);
+    }
+
+    let value = match column.data_type() {
+        DataType::Boolean => json!(column.as_boolean().value(row)),
+        DataType::Int8 => json!(column.as_primitive::<Int8Type>().value(row)),
+        DataType::Int16 => json!(column.as_primitive::<Int16Type>().value(row)),
+        DataType::Int32 => json!(column.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => json!(column.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => json!(column.as_primitive::<UInt8Type>().value(row)),
+        DataType::UInt16 => json!(column.as_primitive::<UInt16Type>().value(row)),
+        DataType::UInt32 => json!(column.as_primitive::<UInt32Type>().value(row)),
+        DataType::UInt64 => json!(column.as_primitive::<UInt64Type>().value(row)),
+        // JSON has no NaN or infinity
+        DataType::Float32 => {
+            Number::from_f64(column.as_primitive::<Float32Type>().value(row) as f64)
+                .map_or(Value::Null, Value::Number)
+        }
+        DataType::Float64 => Number::from_f64(column.as_primitive::<Float64Type>().value(row))
+            .map_or(Value::Null, Value::Number),
+        DataType::Utf8 => json!(column.as_string::<i32>().value(row)),
+        DataType::LargeUtf8 => json!(column.as_string::<i64>().value(row)),
+        DataType::List(_) => to_json_list(column.as_list::<i32>().value(row).as_ref())?,
+        DataType::LargeList(_) => to_json_list(column.as_list::<i64>().value(row).as_ref())?,
+        DataType::FixedSizeList(_, _) => {
+            to_json_list(column.as_fixed_size_list().value(row).as_ref())?
+        }
+        DataType::Struct(fields) => {
+            let column = column.as_struct();
+            let mut object = Map::new();
+            for (field, values) in fields.iter().zip(column.columns()) {
+                object.insert(field.name().clone(), to_json(values.as_ref(), row)?);
+            }
+            Value::Object(object)
+        }
+        _ => {
+            let formatter = ArrayFormatter::try_new(column, &FormatOptions::default())
+                .map_err(|e| invalid_data(format!("Unsupported metadata: {}", e)))?;
+            Value::String(formatter.value(row).to_string())
+        }
+    };
+    Ok(value)
+}
+
+fn to_json_list(values: &dyn Array) -> io::Result<Value> {
+    (0..values.len())
+        .map(|i| to_json(values, i))
+        .collect::<io::Result<Vec<Value>>>()
+        .map(Value::Array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::parquet::arrow::ArrowWriter;
+    use arrow_array::builder::{
+        FixedSizeListBuilder, Float32Builder, Float64Builder, ListBuilder, StringBuilder,
+    };
+    use arrow_array::{ArrayRef, Int64Array, StringArray};
+    use std::sync::Arc;
+
+    fn write(path: &std::path::Path, columns: Vec<(&str, ArrayRef)>) {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_read() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-parquet.parquet");
+        let mut vectors = ListBuilder::new(Float32Builder::new());
+        vectors.values().append_slice(&[1.0, 2.0]);
+        vectors.append(true);
+        vectors.values().append_slice(&[3.0, 4.0]);
+        vectors.append(true);
+        let mut tags = ListBuilder::new(StringBuilder::new());
+        tags.values().append_value("a");
+        tags.append(true);
+        tags.append(false);
+        write(
+            &path,
+            vec![
+                ("vector", Arc::new(vectors.finish()) as ArrayRef),
+                (
+                    "external_id",
+                    Arc::new(StringArray::from(vec![Some("x"), None])),
+                ),
+                ("year", Arc::new(Int64Array::from(vec![2020, 2021]))),
+                ("tags", Arc::new(tags.finish())),
+            ],
+        );
+
+        let records = read(File::open(&path).unwrap())
+            .unwrap()
+            .collect::<io::Result<Vec<InsertModel>>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].vector, vec![1.0, 2.0]);
+        assert_eq!(records[0].external_id.as_deref(), Some("x"));
+        assert_eq!(records[0].metadata, json!({"year": 2020, "tags": ["a"]}));
+        assert_eq!(records[1].vector, vec![3.0, 4.0]);
+        assert_eq!(records[1].external_id, None);
+        assert_eq!(records[1].metadata, json!({"year": 2021}));
+    }
+
+    #[test]
+    fn test_read_fixed_size_f64() {
+        let path = std::env::temp_dir().join("dria-hnsw-test-parquet-f64.parquet");
+        let mut vectors = FixedSizeListBuilder::new(Float64Builder::new(), 2);
+        vectors.values().append_slice(&[0.5, 1.5]);
+        vectors.append(true);
+        write(
+            &path,
+            vec![("vector", Arc::new(vectors.finish()) as ArrayRef)],
+        );
+
+        let records = read(File::open(&path).unwrap())
+            .unwrap()
+            .collect::<io::Result<Vec<InsertModel>>>()
+            .unwrap();
+        assert_eq!(records[0].vector, vec![0.5, 1.5]);
+        assert_eq!(records[0].metadata, json!({}));
+
+        write(
+            &path,
+            vec![("id", Arc::new(Int64Array::from(vec![1])) as ArrayRef)],
+        );
+        let error = read(File::open(&path).unwrap()).err().unwrap().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("no vector column"), "{}", error);
+    }
+}
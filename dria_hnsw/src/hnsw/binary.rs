@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+/// Binary quantizer, which keeps only the sign of each dimension, i.e. 1 bit per dimension.
+///
+/// Codes are compared with the Hamming distance, which is normalized by the number of dimensions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryQuantizer {
+    dim: usize,
+}
+
+impl BinaryQuantizer {
+    pub fn new(dim: usize) -> Self {
+        BinaryQuantizer { dim }
+    }
+
+    /// Packs the signs of a vector into bytes, most significant bit first.
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        v.chunks(8)
+            .map(|chunk| {
+                chunk.iter().enumerate().fold(
+                    0u8,
+                    |byte, (i, x)| if *x > 0.0 { byte | (0x80 >> i) } else { byte },
+                )
+            })
+            .collect()
+    }
+
+    /// Restores a vector of `1.0` and `-1.0` values from its bits.
+    pub fn decode(&self, code: &[u8]) -> Vec<f32> {
+        (0..self.dim)
+            .map(|i| {
+                if code[i / 8] & (0x80 >> (i % 8)) != 0 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+            .collect()
+    }
+
+    /// Hamming distance of two codes, normalized to `[0, 1]`.
+    pub fn distance(&self, a: &[u8], b: &[u8]) -> f32 {
+        hamming(a, b) as f32 / self.dim.max(1) as f32
+    }
+}
+
+/// Number of differing bits of two codes.
+pub fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    let mut a_words = a.chunks_exact(8);
+    let mut b_words = b.chunks_exact(8);
+    let mut count = a_words
+        .by_ref()
+        .zip(b_words.by_ref())
+        .map(|(x, y)| {
+            let x = u64::from_le_bytes(x.try_into().unwrap());
+            let y = u64::from_le_bytes(y.try_into().unwrap());
+            (x ^ y).count_ones()
+        })
+        .sum();
+
+    for (x, y) in a_words.remainder().iter().zip(b_words.remainder()) {
+        count += (x ^ y).count_ones();
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_quantizer() {
+        let bq = BinaryQuantizer::new(10);
+        let v = vec![0.5, -0.1, 0.2, 0.0, -3.0, 1.0, 1.0, -1.0, 0.3, -0.3];
+
+        let code = bq.encode(&v);
+        assert_eq!(code, vec![0b1010_0110, 0b1000_0000]);
+        assert_eq!(
+            bq.decode(&code),
+            vec![1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0, -1.0]
+        );
+
+        let other = bq.encode(&v.iter().map(|x| -x).collect::<Vec<f32>>());
+        assert_eq!(bq.distance(&code, &code), 0.0);
+        // zero is not positive, so it has the same bit in both
+        assert_eq!(bq.distance(&code, &other), 0.9);
+    }
+
+    #[test]
+    fn test_hamming() {
+        let a = vec![0xFF; 19];
+        let mut b = vec![0xFF; 19];
+        b[0] = 0x0F;
+        b[18] = 0xFE;
+        assert_eq!(hamming(&a, &b), 5);
+    }
+}
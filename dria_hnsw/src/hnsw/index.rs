@@ -11,83 +11,239 @@ use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use simsimd::SimSIMD;
 
+use rand::rngs::StdRng;
 use rand::{thread_rng, Rng, SeedableRng};
 
-use crate::proto::index_buffer::{LayerNode, Point};
+use crate::proto::index_buffer::{LayerNode, Point, PointQuant};
 use prost::Message;
 
 use crate::errors::errors::DeserializeError;
-use crate::hnsw::utils::{create_max_heap, create_min_heap, IntoHeap, IntoMap, Numeric};
+use crate::hnsw::utils::{create_max_heap, IntoHeap, IntoMap, Numeric};
 
-use crate::hnsw::scalar::ScalarQuantizer;
+use crate::hnsw::quantization::{Quantizer, QueryCode};
 use rayon::prelude::*;
 use serde_json::{json, Value};
 
-use crate::db::rocksdb_client::RocksdbClient;
+use crate::db::store::{VectorStore, ITER_BATCH_SIZE};
+use crate::filter::projection::Projection;
 use crate::hnsw::sync_map::SynchronizedNodes;
+use crate::hnsw::trace::{micros, CacheCounters, Hop, LayerTrace, SearchTimings, SearchTrace};
 
 pub const SINGLE_THREADED_HNSW_BUILD_THRESHOLD: usize = 256;
 
+/// A vector to search for, along with its prepared code if the quantizer uses one.
+struct Query<'a> {
+    v: &'a [f32],
+    code: Option<QueryCode>,
+}
+
+/// Limits of a search, after which it returns the best results found so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchBudget {
+    /// Maximum number of points compared to the query.
+    pub max_visits: Option<usize>,
+    /// Time at which the search stops.
+    pub deadline: Option<Instant>,
+}
+
+/// What a search has used of its [`SearchBudget`], along with the layers it went through if it is traced.
+struct SearchState {
+    limits: SearchBudget,
+    visits: usize,
+    exhausted: bool,
+    layers: Option<Vec<LayerTrace>>,
+    /// Whether a node missing from the store is awaited, as it may be being inserted by another thread.
+    wait_for_nodes: bool,
+}
+
+impl SearchState {
+    fn new(limits: SearchBudget, trace: bool) -> SearchState {
+        SearchState {
+            limits,
+            visits: 0,
+            exhausted: false,
+            layers: trace.then(Vec::new),
+            wait_for_nodes: false,
+        }
+    }
+
+    /// State of the searches of an insertion, which are not limited and wait for concurrently inserted nodes.
+    fn insertion() -> SearchState {
+        SearchState {
+            wait_for_nodes: true,
+            ..SearchState::new(SearchBudget::default(), false)
+        }
+    }
+
+    fn enter_layer(&mut self, level: usize) {
+        if let Some(layers) = self.layers.as_mut() {
+            layers.push(LayerTrace {
+                level,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Records a candidate whose neighbors are visited.
+    fn hop(&mut self, id: u32, distance: f32) {
+        if let Some(layer) = self.layers.as_mut().and_then(|layers| layers.last_mut()) {
+            layer.hops.push(Hop { id, distance });
+        }
+    }
+
+    /// Whether the search must stop, checking the deadline.
+    fn is_exhausted(&mut self) -> bool {
+        if let Some(deadline) = self.limits.deadline {
+            self.exhausted |= Instant::now() >= deadline;
+        }
+        self.exhausted
+    }
+
+    /// Counts the visits to the given points, dropping those beyond the budget.
+    fn visit(&mut self, indices: &mut Vec<u32>) {
+        if let Some(max_visits) = self.limits.max_visits {
+            let remaining = max_visits.saturating_sub(self.visits);
+            if indices.len() >= remaining {
+                indices.truncate(remaining);
+                self.exhausted = true;
+            }
+        }
+        self.visits += indices.len();
+        if let Some(layer) = self.layers.as_mut().and_then(|layers| layers.last_mut()) {
+            layer.visited += indices.len();
+        }
+    }
+}
+
+/// Results of a search over the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResults {
+    pub results: Vec<Value>,
+    /// Whether the search ran out of its budget, so that closer points may have been missed.
+    pub partial: bool,
+    /// How the search went, if it is explained.
+    pub trace: Option<SearchTrace>,
+}
+
 /*
 Redis Scheme
 
 Points: "0", "1" ...
 Graph:  graph_level.idx : "2.5320" layer 2, node idx 5320
 */
-pub struct HNSW {
+/// An HNSW index over a store, which is `dyn VectorStore` unless a concrete store is given.
+pub struct HNSW<S: VectorStore + ?Sized = dyn VectorStore> {
     pub m: usize,
     pub m_max0: usize,
-    pub rng_seed: u64,
+    /// Seed of the levels of new points, which are random if `None`.
+    pub rng_seed: Option<u64>,
     pub ml: f32,
     pub ef_construction: usize,
     pub ef: usize,
-    pub db: Data<RocksdbClient>,
-    quantizer: ScalarQuantizer,
+    pub db: Data<S>,
+    quantizer: Option<Arc<Quantizer>>,
+    code_map: Option<Cache<String, PointQuant>>,
+    rescore: bool,
     metric: Option<String>,
+    budget: SearchBudget,
+    explain: bool,
+    cache_counters: CacheCounters,
+    extend_candidates: bool,
+    keep_pruned_connections: bool,
 }
 
-impl HNSW {
+impl<S: VectorStore + ?Sized> HNSW<S> {
     pub fn new(
         M: usize,
         ef_construction: usize,
         ef: usize,
         //contract_id: String,
         metric: Option<String>,
-        db: Data<RocksdbClient>,
-    ) -> HNSW {
+        db: Data<S>,
+    ) -> HNSW<S> {
         let m = M;
         let m_max0 = M * 2;
         let ml = 1.0 / (M as f32).ln();
         //let db = RocksdbClient::new(contract_id).expect("Error creating RocksdbClient");
-        let sq = ScalarQuantizer::new(256, 1000, 256);
 
         HNSW {
             m,
             m_max0,
-            rng_seed: 0,
+            rng_seed: None,
             ml,
             ef_construction,
             ef,
             db,
-            quantizer: sq,
+            quantizer: None,
+            code_map: None,
+            rescore: true,
             metric,
+            budget: SearchBudget::default(),
+            explain: false,
+            cache_counters: CacheCounters::default(),
+            extend_candidates: false,
+            keep_pruned_connections: true,
         }
     }
 
+    /// Derives the level of each new point from the seed and its index, so that levels do not depend on
+    /// the order of insertion.
     pub fn set_rng_seed(&mut self, seed: u64) {
-        self.rng_seed = seed;
+        self.rng_seed = Some(seed);
     }
 
     pub fn set_ef(&mut self, ef: usize) {
         self.ef = ef;
     }
 
-    pub fn select_layer(&self) -> usize {
-        let mut random = thread_rng();
-        let rand_float: f32 = random.gen_range(1e-6..1.0); // Avoid very small values
+    /// Traverses the index over quantized codes instead of full-precision points.
+    pub fn set_quantizer(
+        &mut self,
+        quantizer: Arc<Quantizer>,
+        code_map: Cache<String, PointQuant>,
+    ) {
+        self.quantizer = Some(quantizer);
+        self.code_map = Some(code_map);
+    }
+
+    /// Whether the results of a quantized search are rescored with full-precision points.
+    pub fn set_rescore(&mut self, rescore: bool) {
+        self.rescore = rescore;
+    }
+
+    /// Limits of each search, which are unlimited by default. Insertions are never limited.
+    pub fn set_budget(&mut self, budget: SearchBudget) {
+        self.budget = budget;
+    }
+
+    /// Whether searches return their trace, see [`SearchResults::trace`].
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain;
+    }
+
+    /// Options of the neighbor selection heuristic, see [`HNSW::select_neighbors`].
+    pub fn set_neighbor_selection(
+        &mut self,
+        extend_candidates: bool,
+        keep_pruned_connections: bool,
+    ) {
+        self.extend_candidates = extend_candidates;
+        self.keep_pruned_connections = keep_pruned_connections;
+    }
+
+    pub fn select_layer(&self, idx: usize) -> usize {
+        let rand_float: f32 = match self.rng_seed {
+            // each point has its own generator, whatever the order of insertion
+            Some(seed) => {
+                StdRng::seed_from_u64(seed ^ (idx as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+                    .gen_range(1e-6..1.0)
+            }
+            None => thread_rng().gen_range(1e-6..1.0), // Avoid very small values
+        };
         let result = (-1.0 * rand_float.ln() * self.ml) as usize;
 
         // Optionally clamp to a maximum value if applicable
@@ -95,24 +251,94 @@ impl HNSW {
         std::cmp::min(result, max_layer)
     }
 
+    /// Distance between two vectors, `f32::MAX` if they can not be compared.
+    ///
+    /// Queries are checked beforehand, see [`HNSW::check_query`].
     fn distance(&self, x: &[f32], y: &[f32], dist: &Option<String>) -> f32 {
         let dist = match dist.as_ref().map(String::as_str) {
             Some("sqeuclidean") => SimSIMD::sqeuclidean(x, y),
             Some("inner") => SimSIMD::inner(x, y),
             Some("cosine") | None => SimSIMD::cosine(x, y),
-            _ => panic!("Unsupported distance metric"),
+            _ => None,
         };
-        if dist.is_none() {
-            println!("Error in distance"); //make the error propagate
+        dist.unwrap_or(f32::MAX)
+    }
+
+    /// Entry point of the graph, [`DeserializeError::EmptyIndex`] if nothing was inserted yet.
+    fn entry_point(&self) -> Result<u32, DeserializeError> {
+        match self.db.get_ep() {
+            Ok(ep) => Ok(ep as u32),
+            Err(DeserializeError::MissingKey) => Err(DeserializeError::EmptyIndex),
+            Err(e) => Err(e),
         }
-        dist.unwrap()
+    }
+
+    /// Checks that `q` can be compared to the points of the index, which have the dimension of the entry point.
+    fn check_query(
+        &self,
+        q: &[f32],
+        ep: u32,
+        point_map: Cache<String, Point>,
+    ) -> Result<(), DeserializeError> {
+        if !matches!(
+            self.metric.as_deref(),
+            None | Some("cosine" | "sqeuclidean" | "inner")
+        ) {
+            return Err(DeserializeError::UnsupportedMetric);
+        }
+
+        let expected = self.dimension(ep, point_map)?;
+        if q.len() != expected {
+            return Err(DeserializeError::DimensionMismatch {
+                expected,
+                found: q.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that vectors to insert have the dimension of the points of the index, or of the first one if it is empty.
+    pub fn check_vectors(
+        &self,
+        vectors: &[Vec<f32>],
+        point_map: Cache<String, Point>,
+    ) -> Result<(), DeserializeError> {
+        let expected = match self.db.get_ep() {
+            Ok(ep) => self.dimension(ep as u32, point_map)?,
+            Err(DeserializeError::MissingKey) => match vectors.first() {
+                Some(v) => v.len(),
+                None => return Ok(()),
+            },
+            Err(e) => return Err(e),
+        };
+        match vectors.iter().find(|v| v.len() != expected) {
+            Some(v) => Err(DeserializeError::DimensionMismatch {
+                expected,
+                found: v.len(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Dimension of the points, which is the one of the entry point.
+    fn dimension(
+        &self,
+        ep: u32,
+        point_map: Cache<String, Point>,
+    ) -> Result<usize, DeserializeError> {
+        Ok(self
+            .get_points_w_memory(&[ep], point_map)?
+            .first()
+            .ok_or(DeserializeError::MissingKey)?
+            .vector()
+            .len())
     }
 
     fn get_points_w_memory(
         &self,
-        indices: &Vec<u32>,
+        indices: &[u32],
         point_map: Cache<String, Point>,
-    ) -> Vec<Point> {
+    ) -> Result<Vec<Point>, DeserializeError> {
         // Initialize points with None to reserve the space and maintain order
         let mut points: Vec<Option<Point>> = vec![None; indices.len()];
 
@@ -128,23 +354,19 @@ impl HNSW {
             }
         }
 
+        self.cache_counters.vectors(
+            indices.len() - missing_indices_with_pos.len(),
+            missing_indices_with_pos.len(),
+        );
         if !missing_indices_with_pos.is_empty() {
             let missing_indices: Vec<u32> = missing_indices_with_pos
                 .iter()
                 .map(|&(_, idx)| idx)
                 .collect();
 
-            let fetched_points = self.db.get_points(&missing_indices);
-
-            if fetched_points.is_err() {
-                println!(
-                    "Error getting points, get points _w _memory {:?}",
-                    &missing_indices
-                );
-            }
-
-            let fetched_points = fetched_points.unwrap();
+            let fetched_points = self.db.get_points(&missing_indices)?;
 
+            // half-precision points are cached as they are, which halves the cache, and converted on every distance
             for point in fetched_points {
                 let key = format!("p:{}", point.idx);
                 point_map.insert(key, point.clone());
@@ -157,7 +379,77 @@ impl HNSW {
                 }
             }
         }
-        points.into_iter().filter_map(|p| p).collect()
+        Ok(points.into_iter().flatten().collect())
+    }
+
+    fn get_codes_w_memory(
+        &self,
+        indices: &[u32],
+        code_map: Cache<String, PointQuant>,
+    ) -> Result<Vec<PointQuant>, DeserializeError> {
+        let mut codes: Vec<Option<PointQuant>> = vec![None; indices.len()];
+        let mut missing_indices_with_pos: Vec<(usize, u32)> = Vec::new();
+
+        for (pos, idx) in indices.iter().enumerate() {
+            let key = format!("q:{}", idx);
+            if let Some(code) = code_map.get(&key) {
+                codes[pos] = Some(code);
+            } else {
+                missing_indices_with_pos.push((pos, *idx));
+            }
+        }
+
+        self.cache_counters.vectors(
+            indices.len() - missing_indices_with_pos.len(),
+            missing_indices_with_pos.len(),
+        );
+        if !missing_indices_with_pos.is_empty() {
+            let missing_indices: Vec<u32> = missing_indices_with_pos
+                .iter()
+                .map(|&(_, idx)| idx)
+                .collect();
+
+            let fetched_codes = self.db.get_codes(&missing_indices)?;
+
+            for ((pos, _), code) in missing_indices_with_pos.into_iter().zip(fetched_codes) {
+                code_map.insert(format!("q:{}", code.idx), code.clone());
+                codes[pos] = Some(code);
+            }
+        }
+        Ok(codes.into_iter().flatten().collect())
+    }
+
+    /// Prepares a query once, e.g. its distance table, so that it is reused for every distance.
+    fn query<'a>(&self, q: &'a [f32]) -> Query<'a> {
+        let code = self
+            .quantizer
+            .as_ref()
+            .and_then(|quantizer| quantizer.prepare(q, &self.metric));
+        Query { v: q, code }
+    }
+
+    /// Distances from `q` to the given points, computed over their quantized codes if the index is quantized.
+    fn distances_w_memory(
+        &self,
+        q: &Query,
+        indices: &[u32],
+        point_map: Cache<String, Point>,
+    ) -> Result<Vec<f32>, DeserializeError> {
+        Ok(match (&self.quantizer, &self.code_map) {
+            (Some(quantizer), Some(code_map)) => self
+                .get_codes_w_memory(indices, code_map.clone())?
+                .iter()
+                .map(|c| match &q.code {
+                    Some(code) => code.distance(&c.v),
+                    None => self.distance(q.v, &quantizer.decode(&c.v), &self.metric),
+                })
+                .collect(),
+            _ => self
+                .get_points_w_memory(indices, point_map)?
+                .iter()
+                .map(|p| self.distance(q.v, &p.vector(), &self.metric))
+                .collect(),
+        })
     }
 
     fn get_neighbors_w_memory(
@@ -165,7 +457,7 @@ impl HNSW {
         layer: usize,
         indices: &Vec<u32>,
         node_map: Arc<SynchronizedNodes>,
-    ) -> Vec<LayerNode> {
+    ) -> Result<Vec<LayerNode>, DeserializeError> {
         let mut nodes = Vec::with_capacity(indices.len());
         let mut missing_indices = Vec::new();
 
@@ -180,44 +472,55 @@ impl HNSW {
                 }
             }
         }
+        self.cache_counters
+            .nodes(indices.len() - missing_indices.len(), missing_indices.len());
         if !missing_indices.is_empty() {
-            let fetched_nodes = self
-                .db
-                .get_neighbors(layer, missing_indices)
-                .expect("Error getting neighbors");
+            let fetched_nodes = self.db.get_neighbors(layer, missing_indices)?;
 
             for fetched_node in fetched_nodes.iter() {
-                let index = indices.iter().position(|&i| i == fetched_node.idx).unwrap();
+                let index = indices
+                    .iter()
+                    .position(|&i| i == fetched_node.idx)
+                    .ok_or(DeserializeError::InvalidForm)?;
                 nodes[index] = fetched_node.clone();
             }
             node_map.insert_batch_and_notify(fetched_nodes);
         }
 
-        nodes
+        Ok(nodes)
     }
 
+    /// Node of a point on a layer, waiting for it if it is missing and `wait` is set.
+    ///
+    /// Only insertions wait, as a node missing during a search never shows up if the index is incomplete.
     fn get_neighbor_w_memory(
         &self,
         layer: usize,
         idx: usize,
         node_map: Arc<SynchronizedNodes>,
-    ) -> LayerNode {
+        wait: bool,
+    ) -> Result<LayerNode, DeserializeError> {
         let key = format!("{}:{}", layer, idx);
 
         let node_option = node_map.get_or_wait_opt(&key);
-        return if let Some(node) = node_option {
-            node.clone()
-        } else {
-            let node_ = self.db.get_neighbor(layer, idx);
-            if node_.is_err() {
+        if let Some(node) = node_option {
+            self.cache_counters.nodes(1, 0);
+            return Ok(node);
+        }
+
+        self.cache_counters.nodes(0, 1);
+        match self.db.get_neighbor(layer, idx) {
+            Ok(node) => {
+                node_map.insert_and_notify(&node);
+                Ok(node)
+            }
+            // the node is being inserted by another thread
+            Err(DeserializeError::MissingKey) if wait => {
                 println!("Sync issue, awaiting notification...");
-                let value = node_map.get_or_wait(&key);
-                return value.clone();
+                Ok(node_map.get_or_wait(&key))
             }
-            let node_ = node_.unwrap();
-            node_map.insert_and_notify(&node_);
-            node_
-        };
+            Err(e) => Err(e),
+        }
     }
 
     pub fn insert_w_preset(
@@ -239,21 +542,33 @@ impl HNSW {
         let mut num_layers = nl.load(Ordering::Relaxed);
 
         let L = if num_layers == 0 { 0 } else { num_layers - 1 };
-        let l = self.select_layer();
+        let l = self.select_layer(idx);
 
-        let qs = self.get_points_w_memory(&vec![idx as u32], point_map.clone());
-        let q = qs[0].v.clone();
+        let qs = self.get_points_w_memory(&[idx as u32], point_map.clone())?;
+        let q = qs
+            .first()
+            .ok_or(DeserializeError::MissingKey)?
+            .vector()
+            .to_vec();
 
         if ep_index.is_some() {
             let ep_index_ = ep_index.unwrap();
 
-            let points = self.get_points_w_memory(&vec![ep_index_], point_map.clone());
-            let point = points.first().unwrap();
-            let dist = self.distance(&q, &point.v, &self.metric);
+            let query = self.query(&q);
+            let dist = self.distances_w_memory(&query, &[ep_index_], point_map.clone())?[0];
             let mut ep = HashMap::from([(ep_index_, dist)]);
+            let mut state = SearchState::insertion();
 
             for i in ((l + 1)..=L).rev() {
-                W = self.search_layer(&q, ep.clone(), 1, i, node_map.clone(), point_map.clone())?;
+                W = self.search_layer(
+                    &query,
+                    ep.clone(),
+                    1,
+                    i,
+                    node_map.clone(),
+                    point_map.clone(),
+                    &mut state,
+                )?;
 
                 if let Some((_, value)) = W.iter().next() {
                     if &dist < value {
@@ -264,19 +579,27 @@ impl HNSW {
 
             for l_c in (0..=std::cmp::min(L, l)).rev() {
                 W = self.search_layer(
-                    &q,
+                    &query,
                     ep,
                     self.ef_construction,
                     l_c,
                     node_map.clone(),
                     point_map.clone(),
+                    &mut state,
                 )?;
 
                 //upsert expire = true by default, populate upserted_keys for replication
                 node_map.insert_and_notify(&LayerNode::new(l_c, idx));
 
                 ep = W.clone();
-                let neighbors = self.select_neighbors(&q, W, l_c, true);
+                let neighbors = self.select_neighbors(
+                    &query,
+                    idx as u32,
+                    W,
+                    l_c,
+                    node_map.clone(),
+                    point_map.clone(),
+                )?;
 
                 let M = if l_c == 0 { self.m_max0 } else { self.m };
 
@@ -285,7 +608,7 @@ impl HNSW {
                 indices.push(idx as u32);
                 let idx_i = indices.len() - 1;
 
-                let mut nodes = self.get_neighbors_w_memory(l_c, &indices, node_map.clone());
+                let mut nodes = self.get_neighbors_w_memory(l_c, &indices, node_map.clone())?;
 
                 for (i, (e_i, dist)) in neighbors.iter().enumerate() {
                     if i == idx_i {
@@ -297,16 +620,24 @@ impl HNSW {
                     nodes[idx_i].neighbors.insert(*e_i, *dist);
                 }
 
-                // TODO: remove redundant
-                for (i, (e_i, dist)) in neighbors.iter().enumerate() {
-                    if i == idx_i {
-                        // We want to skip last layernode, which is idx -> layernode
-                        continue;
-                    }
-                    let eConn = nodes[i].neighbors.clone();
+                // neighbors with too many connections are pruned, w.r.t. their own vector
+                for (i, (e_i, _)) in neighbors.iter().enumerate() {
+                    let eConn = nodes[i]
+                        .neighbors
+                        .clone()
+                        .into_iter()
+                        .collect::<HashMap<_, _>>();
                     if eConn.len() > M {
-                        let eNewConn = self.select_neighbors(&q, eConn, l_c, true);
-                        nodes[i].neighbors = eNewConn.clone();
+                        let e = self.vectors_w_memory(&[*e_i], point_map.clone())?.remove(0);
+                        let eNewConn = self.select_neighbors(
+                            &self.query(&e),
+                            *e_i,
+                            eConn,
+                            l_c,
+                            node_map.clone(),
+                            point_map.clone(),
+                        )?;
+                        nodes[i].neighbors = eNewConn.into_iter().collect();
                     }
                 }
 
@@ -330,15 +661,57 @@ impl HNSW {
         Ok(())
     }
 
+    /// Marks the given nodes as deleted, and removes their metadata & external ids.
+    ///
+    /// Deleted nodes stay in the graph so that the search can still go through them,
+    /// but they are excluded from the search results.
+    pub fn mark_deleted(
+        &self,
+        indices: &[u32],
+        node_map: Arc<SynchronizedNodes>,
+    ) -> Result<(), DeserializeError> {
+        let nodes = self.hide_nodes(indices, node_map)?;
+
+        self.db.upsert_neighbors(nodes)?;
+        self.db.delete_records(indices)?;
+
+        Ok(())
+    }
+
+    /// Marks the bottom-layer nodes of the given indices as deleted in the node map, without writing them,
+    /// and returns them so that they can be written along with other changes.
+    pub fn hide_nodes(
+        &self,
+        indices: &[u32],
+        node_map: Arc<SynchronizedNodes>,
+    ) -> Result<Vec<LayerNode>, DeserializeError> {
+        let mut nodes = Vec::with_capacity(indices.len());
+        for &idx in indices {
+            let key = format!("{}:{}", 0, idx);
+            let mut node = match node_map.get_or_wait_opt(&key) {
+                Some(node) => node,
+                None => self.db.get_neighbor(0, idx as usize)?,
+            };
+            node.visible = false;
+            nodes.push(node);
+        }
+
+        node_map.insert_batch_and_notify(nodes.clone());
+        Ok(nodes)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn search_layer(
         &self,
-        q: &Vec<f32>,
+        q: &Query,
         ep: HashMap<u32, f32>,
         ef: usize,
         l_c: usize,
         node_map: Arc<SynchronizedNodes>,
         point_map: Cache<String, Point>,
+        state: &mut SearchState,
     ) -> Result<HashMap<u32, f32>, DeserializeError> {
+        state.enter_layer(l_c);
         let mut v = HashSet::new();
 
         for (k, _) in ep.iter() {
@@ -352,11 +725,17 @@ impl HNSW {
             let c = C.pop().unwrap().0;
             let f_value = W.peek().unwrap().0 .0;
 
-            if c.0 .0 > f_value {
+            if c.0 .0 > f_value || state.is_exhausted() {
                 break;
             }
+            state.hop(c.1, c.0 .0);
 
-            let layernd = self.get_neighbor_w_memory(l_c, c.1 as usize, node_map.clone());
+            let layernd = self.get_neighbor_w_memory(
+                l_c,
+                c.1 as usize,
+                node_map.clone(),
+                state.wait_for_nodes,
+            )?;
 
             let mut pairs: Vec<_> = layernd.neighbors.into_iter().collect();
             //pairs.sort_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap());
@@ -373,17 +752,13 @@ impl HNSW {
             });
             let sorted_keys: Vec<u32> = pairs.into_iter().map(|(k, _)| k).collect();
 
-            let neighbors: Vec<u32> = sorted_keys
+            let mut neighbors: Vec<u32> = sorted_keys
                 .into_iter()
                 .filter_map(|x| if !v.contains(&x) { Some(x) } else { None })
                 .collect();
+            state.visit(&mut neighbors);
 
-            let points = self.get_points_w_memory(&neighbors, point_map.clone());
-
-            let distances = points
-                .iter()
-                .map(|x| self.distance(&q, &x.v, &self.metric))
-                .collect::<Vec<f32>>();
+            let distances = self.distances_w_memory(q, &neighbors, point_map.clone())?;
 
             for (i, d) in neighbors.iter().zip(distances.iter()) {
                 v.insert(i.clone());
@@ -413,88 +788,536 @@ impl HNSW {
         Ok(W.into_map())
     }
 
+    /// Selects at most `M` neighbors of `q` among the candidates `C`, given with their distance to `q`,
+    /// with the heuristic of the HNSW paper (Algorithm 4).
+    ///
+    /// A candidate is selected only if it is closer to `q` than to every neighbor selected before it,
+    /// so that neighbors lie in different directions rather than in the same cluster. If `extend_candidates`
+    /// is set, the neighbors of the candidates are candidates as well, and if `keep_pruned_connections` is set,
+    /// the closest of the discarded candidates fill the remaining places.
     fn select_neighbors(
         &self,
-        q: &Vec<f32>,
+        q: &Query,
+        q_idx: u32,
         C: HashMap<u32, f32>,
         l_c: usize,
-        k_p_c: bool,
-    ) -> HashMap<u32, f32> {
-        let mut R = create_min_heap();
-        let mut W = C.into_minheap();
-
-        let mut M = 0;
+        node_map: Arc<SynchronizedNodes>,
+        point_map: Cache<String, Point>,
+    ) -> Result<HashMap<u32, f32>, DeserializeError> {
+        let M = if l_c > 0 { self.m } else { self.m_max0 };
 
-        if l_c > 0 {
-            M = self.m
-        } else {
-            M = self.m_max0;
+        let mut W = C;
+        if self.extend_candidates {
+            let candidates = W.keys().copied().collect::<Vec<u32>>();
+            let adjacent = self
+                .get_neighbors_w_memory(l_c, &candidates, node_map)?
+                .into_iter()
+                .flat_map(|node| node.neighbors.into_keys())
+                .filter(|e| *e != q_idx && !W.contains_key(e))
+                .collect::<HashSet<u32>>()
+                .into_iter()
+                .collect::<Vec<u32>>();
+            let distances = self.distances_w_memory(q, &adjacent, point_map.clone())?;
+            W.extend(adjacent.into_iter().zip(distances));
         }
 
-        let mut W_d = create_min_heap();
-        while W.len() > 0 && R.len() < M {
-            let e = W.pop().unwrap().0;
-
-            if R.len() == 0 || e.0 < R.peek().unwrap().0 .0 {
-                R.push(Reverse(e));
+        let mut W = W
+            .into_iter()
+            .map(|(e, d)| (d, e))
+            .collect::<Vec<(f32, u32)>>();
+        W.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let indices = W.iter().map(|(_, e)| *e).collect::<Vec<u32>>();
+        let vectors = self.vectors_w_memory(&indices, point_map)?;
+
+        let mut R: Vec<(u32, f32, &Vec<f32>)> = Vec::with_capacity(M);
+        let mut W_d = Vec::new();
+        for ((d, e), v) in W.into_iter().zip(vectors.iter()) {
+            if R.len() >= M {
+                break;
+            }
+            // compared over the same vectors, as quantized distances to q may be on another scale
+            let d_q = self.distance(q.v, v, &self.metric);
+            if R.iter()
+                .all(|(_, _, r)| d_q < self.distance(v, r, &self.metric))
+            {
+                R.push((e, d, v));
             } else {
-                W_d.push(Reverse(e));
+                W_d.push((e, d));
             }
         }
 
-        if k_p_c {
-            while W_d.len() > 0 && R.len() < M {
-                R.push(W_d.pop().unwrap());
-            }
+        let mut R = R
+            .into_iter()
+            .map(|(e, d, _)| (e, d))
+            .collect::<HashMap<u32, f32>>();
+        if self.keep_pruned_connections {
+            // discarded candidates are in the order of their distance to q
+            let remaining = M.saturating_sub(R.len());
+            R.extend(W_d.into_iter().take(remaining));
         }
-        R.into_map()
+        Ok(R)
+    }
+
+    /// Vectors of the given points as they are compared in the index, i.e. decoded from their codes if the index is quantized.
+    fn vectors_w_memory(
+        &self,
+        indices: &[u32],
+        point_map: Cache<String, Point>,
+    ) -> Result<Vec<Vec<f32>>, DeserializeError> {
+        Ok(match (&self.quantizer, &self.code_map) {
+            (Some(quantizer), Some(code_map)) => self
+                .get_codes_w_memory(indices, code_map.clone())?
+                .iter()
+                .map(|c| quantizer.decode(&c.v))
+                .collect(),
+            _ => self
+                .get_points_w_memory(indices, point_map)?
+                .iter()
+                .map(|p| p.vector().into_owned())
+                .collect(),
+        })
     }
 
+    /// Searches the `K` nearest neighbors of `q` over the graph, within the budget of the index.
+    ///
+    /// Fails with [`DeserializeError::EmptyIndex`] if nothing was inserted yet, and with
+    /// [`DeserializeError::DimensionMismatch`] if `q` does not have the dimension of the points.
     pub fn knn_search(
         &self,
-        q: &Vec<f32>,
+        q: &[f32],
         K: usize,
         node_map: Arc<SynchronizedNodes>,
         point_map: Cache<String, Point>,
-    ) -> Vec<Value> {
+        projection: &Projection,
+    ) -> Result<SearchResults, DeserializeError> {
         let mut W = HashMap::new();
+        let cache_stats = self.cache_counters.stats();
+        let start = Instant::now();
 
-        let ep_index = self.db.get_ep().expect("") as u32;
-        let num_layers = self.db.get_num_layers().expect("Error getting num_layers");
+        let ep_index = self.entry_point()?;
+        self.check_query(q, ep_index, point_map.clone())?;
+        let num_layers = self.db.get_num_layers()?;
 
-        let points = self.get_points_w_memory(&vec![ep_index], point_map.clone());
-        let point = points.first().unwrap();
-        let dist = self.distance(&q, &point.v, &self.metric);
+        let query = self.query(q);
+        let dist = self.distances_w_memory(&query, &[ep_index], point_map.clone())?[0];
         let mut ep = HashMap::from([(ep_index, dist)]);
-
-        for l_c in (1..=num_layers - 1).rev() {
-            W = self
-                .search_layer(&q, ep, 1, l_c, node_map.clone(), point_map.clone())
-                .expect("Error searching layer");
+        let mut state = SearchState::new(self.budget, self.explain);
+
+        for l_c in (1..num_layers).rev() {
+            W = self.search_layer(
+                &query,
+                ep,
+                1,
+                l_c,
+                node_map.clone(),
+                point_map.clone(),
+                &mut state,
+            )?;
             ep = W;
         }
-
-        let ep_ = self
-            .search_layer(q, ep, self.ef, 0, node_map.clone(), point_map.clone())
-            .expect("Error searching layer");
+        let upper_layers = start.elapsed();
+
+        let ep_ = self.search_layer(
+            &query,
+            ep,
+            self.ef,
+            0,
+            node_map.clone(),
+            point_map.clone(),
+            &mut state,
+        )?;
+
+        // deleted nodes are still traversed, but they can not be in the results
+        let candidates = ep_.keys().copied().collect::<Vec<u32>>();
+        let candidate_nodes = self.get_neighbors_w_memory(0, &candidates, node_map.clone())?;
+        let ep_ = candidates
+            .iter()
+            .zip(candidate_nodes.iter())
+            .filter(|(_, node)| node.visible)
+            .map(|(idx, _)| (*idx, ep_[idx]))
+            .collect::<HashMap<u32, f32>>();
+        let base_layer = start.elapsed();
+
+        // quantized distances are approximate, so the candidates are re-ranked with their points
+        let rescored = if self.quantizer.is_some() && self.rescore {
+            ep_.len()
+        } else {
+            0
+        };
+        let ep_ = if rescored > 0 {
+            let candidates = ep_.keys().copied().collect::<Vec<u32>>();
+            let points = self.get_points_w_memory(&candidates, point_map.clone())?;
+            candidates
+                .into_iter()
+                .zip(points.iter())
+                .map(|(idx, p)| (idx, self.distance(q, &p.vector(), &self.metric)))
+                .collect::<HashMap<u32, f32>>()
+        } else {
+            ep_
+        };
 
         let mut heap = ep_.into_minheap();
         let mut sorted_vec = Vec::new();
         while !heap.is_empty() && sorted_vec.len() < K {
             let item = heap.pop().unwrap().0;
-            sorted_vec.push((item.1, 1.0 - item.0 .0));
+            sorted_vec.push((item.1, item.0 .0));
+        }
+        let rescore = start.elapsed();
+
+        let results = self.results(sorted_vec, point_map, projection)?;
+        let trace = state.layers.take().map(|layers| SearchTrace {
+            entry_point: Hop {
+                id: ep_index,
+                distance: dist,
+            },
+            layers,
+            visited: state.visits,
+            distance_computations: 1 + state.visits + rescored,
+            cache: self.cache_counters.stats().since(&cache_stats),
+            timings: SearchTimings {
+                upper_layers_us: micros(upper_layers),
+                base_layer_us: micros(base_layer - upper_layers),
+                rescore_us: micros(rescore - base_layer),
+                results_us: micros(start.elapsed() - rescore),
+            },
+        });
+        Ok(SearchResults {
+            results,
+            partial: state.exhausted,
+            trace,
+        })
+    }
+
+    /// Finds the true `K` nearest neighbors of `q`, by comparing it to every point that is not deleted.
+    ///
+    /// Points are compared with their full-precision vectors, even if the index is quantized.
+    pub fn exact_search(
+        &self,
+        q: &[f32],
+        K: usize,
+        node_map: Arc<SynchronizedNodes>,
+        point_map: Cache<String, Point>,
+        projection: &Projection,
+    ) -> Result<Vec<Value>, DeserializeError> {
+        let ep = self.entry_point()?;
+        self.check_query(q, ep, point_map.clone())?;
+
+        let datasize = self.db.get_datasize()? as u32;
+        let mut distances = Vec::with_capacity(datasize as usize);
+        for from in (0..datasize).step_by(ITER_BATCH_SIZE) {
+            // cached points are compared from memory, the others are read without caching them,
+            // so that a scan does not evict the points that searches traverse
+            let to = datasize.min(from + ITER_BATCH_SIZE as u32);
+            let mut missing = Vec::new();
+            for idx in from..to {
+                match point_map.get(&format!("p:{}", idx)) {
+                    Some(p) => distances.push((idx, self.distance(q, &p.vector(), &self.metric))),
+                    None => missing.push(idx),
+                }
+            }
+            self.cache_counters
+                .vectors((to - from) as usize - missing.len(), missing.len());
+            for point in self.db.get_points_partial(&missing) {
+                match point {
+                    Ok(p) => distances.push((p.idx, self.distance(q, &p.vector(), &self.metric))),
+                    // points are stored by index, but a point may be missing from an incomplete import
+                    Err(DeserializeError::MissingKey) => {}
+                    Err(e) => return Err(e),
+                }
+            }
         }
+
+        let is_visible = |idx: u32| match node_map.get_or_wait_opt(&format!("0:{}", idx)) {
+            Some(node) => Ok(node.visible),
+            None => match self.db.get_neighbor(0, idx as usize) {
+                Ok(node) => Ok(node.visible),
+                // points are stored before their nodes while they are inserted
+                Err(DeserializeError::MissingKey) => Ok(true),
+                Err(e) => Err(e),
+            },
+        };
+
+        // only the closest points are sorted, and more of them are taken while deleted ones are among them
+        let mut k = K;
+        let sorted_vec = loop {
+            let n = k.min(distances.len());
+            if n < distances.len() {
+                distances.select_nth_unstable_by(n, |a, b| a.1.total_cmp(&b.1));
+            }
+            let mut closest = distances[..n].to_vec();
+            closest.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+
+            let mut sorted_vec = Vec::with_capacity(K);
+            for (idx, dist) in closest {
+                if sorted_vec.len() == K {
+                    break;
+                }
+                if is_visible(idx)? {
+                    sorted_vec.push((idx, dist));
+                }
+            }
+            if sorted_vec.len() == K || n == distances.len() {
+                break sorted_vec;
+            }
+            k *= 2;
+        };
+
+        self.results(sorted_vec, point_map, projection)
+    }
+
+    /// Results of a search from the indices and distances of its neighbors, closest first.
+    fn results(
+        &self,
+        sorted_vec: Vec<(u32, f32)>,
+        point_map: Cache<String, Point>,
+        projection: &Projection,
+    ) -> Result<Vec<Value>, DeserializeError> {
         let indices = sorted_vec.iter().map(|x| x.0).collect::<Vec<u32>>();
-        let metadata = self
-            .db
-            .get_metadatas(indices)
-            .expect("Error getting metadatas");
 
-        let result = sorted_vec
+        let mut result = sorted_vec
             .iter()
-            .zip(metadata.iter())
-            .map(|(x, y)| json!({"id":x.0, "score":x.1, "metadata":y.clone()}))
+            .map(|x| json!({"id":x.0, "score":1.0 - x.1}))
             .collect::<Vec<Value>>();
-        result
+
+        let external_ids = self.db.get_external_ids(&indices)?;
+        for (res, external_id) in result.iter_mut().zip(external_ids) {
+            if let Some(external_id) = external_id {
+                res["external_id"] = json!(external_id);
+            }
+        }
+
+        if projection.needs_metadata() {
+            let metadata = self.db.get_metadatas(indices.clone())?;
+
+            for (res, m) in result.iter_mut().zip(metadata) {
+                res["metadata"] = projection.apply(m);
+            }
+        }
+
+        if projection.include_vector {
+            // points of the results are visited during the search, so they are most likely in cache
+            let points = self.get_points_w_memory(&indices, point_map.clone())?;
+            for (res, p) in result.iter_mut().zip(points) {
+                res["vector"] = projection.encode_vector(&p.vector());
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory_client::MemoryClient;
+    use crate::proto::index_buffer::Precision;
+
+    fn at(degrees: f32) -> Vec<f32> {
+        let radians = degrees.to_radians();
+        vec![radians.cos(), radians.sin()]
+    }
+
+    #[test]
+    fn test_half_precision_cache() {
+        let store = Data::new(MemoryClient::new());
+        let vectors = vec![at(30.0), at(60.0)];
+        store.add_points_batch(&vectors, 0, Precision::F16).unwrap();
+
+        let ind = HNSW::new(3, 128, 0, None, store);
+        let point_map = Cache::new(10);
+        let points = ind.get_points_w_memory(&[0, 1], point_map.clone()).unwrap();
+        let cached = point_map.get(&"p:1".to_string()).unwrap();
+        assert_eq!(cached.precision, Precision::F16 as i32);
+        assert!(cached.v.is_empty());
+        assert_eq!(cached.vector(), points[1].vector());
+        assert!((cached.vector()[0] - vectors[1][0]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_select_neighbors() {
+        let store = Data::new(MemoryClient::new());
+        // the second candidate is closer to the first than to q, the third is not
+        let vectors = vec![at(30.0), at(25.0), at(70.0)];
+        store.add_points_batch(&vectors, 0, Precision::F32).unwrap();
+
+        let mut ind = HNSW::new(3, 128, 0, None, store);
+        let q = at(45.0);
+        let query = ind.query(&q);
+        let candidates = (0..vectors.len() as u32)
+            .map(|idx| (idx, SimSIMD::cosine(&q, &vectors[idx as usize]).unwrap()))
+            .collect::<HashMap<u32, f32>>();
+        let mut select = |keep_pruned_connections| {
+            ind.set_neighbor_selection(false, keep_pruned_connections);
+            let point_map = Cache::new(10);
+            let mut selected = ind
+                .select_neighbors(
+                    &query,
+                    vectors.len() as u32,
+                    candidates.clone(),
+                    1,
+                    Arc::new(SynchronizedNodes::new()),
+                    point_map,
+                )
+                .unwrap()
+                .into_keys()
+                .collect::<Vec<u32>>();
+            selected.sort();
+            selected
+        };
+
+        assert_eq!(select(false), vec![0, 2]);
+        assert_eq!(select(true), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut state = SearchState::new(
+            SearchBudget {
+                max_visits: Some(5),
+                deadline: None,
+            },
+            false,
+        );
+        let mut indices = vec![1, 2, 3];
+        state.visit(&mut indices);
+        assert_eq!((indices.len(), state.is_exhausted()), (3, false));
+        state.visit(&mut indices);
+        assert_eq!((indices, state.is_exhausted()), (vec![1, 2], true));
+
+        let mut state = SearchState::new(
+            SearchBudget {
+                max_visits: None,
+                deadline: Some(Instant::now()),
+            },
+            false,
+        );
+        assert!(state.is_exhausted());
+    }
+
+    /// Encoded nodes of an index built one point at a time, with seeded levels.
+    fn seeded_build(vectors: &[Vec<f32>], seed: u64) -> Vec<(String, Vec<u8>)> {
+        let store = Data::new(MemoryClient::new());
+        store.add_points_batch(vectors, 0, Precision::F32).unwrap();
+        let mut ind = HNSW::new(4, 32, 0, None, store);
+        ind.set_rng_seed(seed);
+
+        let node_map = Arc::new(SynchronizedNodes::new());
+        let (num_layers, epa) = (
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicIsize::new(-1)),
+        );
+        for idx in 0..vectors.len() {
+            ind.insert_w_preset(
+                idx,
+                node_map.clone(),
+                Cache::new(1000),
+                num_layers.clone(),
+                epa.clone(),
+            )
+            .unwrap();
+        }
+
+        let mut nodes = node_map
+            .map
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().encode_to_vec()))
+            .collect::<Vec<(String, Vec<u8>)>>();
+        nodes.sort();
+        nodes
+    }
+
+    #[test]
+    fn test_exact_search() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let vectors = (0..600)
+            .map(|_| (0..4).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect::<Vec<Vec<f32>>>();
+        let q = vec![0.5, -0.25, 0.1, 0.8];
+
+        let store = Data::new(MemoryClient::new());
+        store.add_points_batch(&vectors, 0, Precision::F32).unwrap();
+        store
+            .set_metadata_batch(vec![json!({}); vectors.len()], 0)
+            .unwrap();
+        store.set_datasize(vectors.len()).unwrap();
+        store.set_ep(0).unwrap();
+        store.set_num_layers(1).unwrap();
+        let ind = HNSW::new(16, 128, 10, None, store);
+
+        let mut truth = (0..vectors.len() as u32)
+            .map(|idx| (idx, ind.distance(&q, &vectors[idx as usize], &None)))
+            .collect::<Vec<(u32, f32)>>();
+        truth.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        // the closest points are deleted, so that more candidates than the results are checked
+        let mut nodes = (0..vectors.len())
+            .map(|idx| LayerNode::new(0, idx))
+            .collect::<Vec<LayerNode>>();
+        for &(idx, _) in &truth[..8] {
+            nodes[idx as usize].visible = false;
+        }
+        ind.db.upsert_neighbors(nodes).unwrap();
+
+        // some of the points are cached, the others are read from the store
+        let point_map = Cache::new(1000);
+        for idx in (0..vectors.len()).step_by(3) {
+            point_map.insert(format!("p:{}", idx), Point::new(vectors[idx].clone(), idx));
+        }
+
+        let projection = Projection::new(None, None, None, None);
+        let results = ind
+            .exact_search(
+                &q,
+                5,
+                Arc::new(SynchronizedNodes::new()),
+                point_map,
+                &projection,
+            )
+            .unwrap();
+        let ids = results
+            .iter()
+            .map(|r| r["id"].as_u64().unwrap() as u32)
+            .collect::<Vec<u32>>();
+        let expected = truth[8..13]
+            .iter()
+            .map(|(idx, _)| *idx)
+            .collect::<Vec<u32>>();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn test_missing_node() {
+        // an incomplete index, whose entry point has no node
+        let store = Data::new(MemoryClient::new());
+        store
+            .add_points_batch(&[vec![1.0, 0.0]], 0, Precision::F32)
+            .unwrap();
+        store.set_datasize(1).unwrap();
+        store.set_ep(0).unwrap();
+        store.set_num_layers(1).unwrap();
+        let ind = HNSW::new(16, 128, 10, None, store);
+
+        let projection = Projection::new(None, None, None, None);
+        let res = ind.knn_search(
+            &[1.0, 0.0],
+            1,
+            Arc::new(SynchronizedNodes::new()),
+            Cache::new(10),
+            &projection,
+        );
+        assert!(matches!(res, Err(DeserializeError::MissingKey)));
+    }
+
+    #[test]
+    fn test_seeded_build() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let vectors = (0..300)
+            .map(|_| (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect::<Vec<Vec<f32>>>();
+
+        let nodes = seeded_build(&vectors, 42);
+        assert!(nodes.len() > vectors.len(), "some points are above level 0");
+        assert_eq!(nodes, seeded_build(&vectors, 42));
+        assert_ne!(nodes, seeded_build(&vectors, 43));
     }
 }
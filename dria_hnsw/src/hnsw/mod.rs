@@ -1,4 +1,9 @@
+pub mod binary;
 pub mod index;
+pub mod options;
+pub mod product;
+pub mod quantization;
 pub mod scalar;
 pub mod sync_map;
+pub mod trace;
 pub mod utils;
@@ -0,0 +1,156 @@
+use crate::db::env::Config;
+use crate::errors::errors::ValidationError;
+use crate::proto::index_buffer::Precision;
+use serde::{Deserialize, Serialize};
+
+/// Kind of quantization used to traverse the index.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantizationKind {
+    /// 8-bit scalar quantization, see [`ScalarQuantizer`](crate::hnsw::scalar::ScalarQuantizer).
+    Scalar,
+    /// Product quantization, see [`ProductQuantizer`](crate::hnsw::product::ProductQuantizer).
+    Product,
+    /// 1-bit binary quantization, see [`BinaryQuantizer`](crate::hnsw::binary::BinaryQuantizer).
+    Binary,
+}
+
+/// Index-level options, decided when the index is created and persisted along with it.
+///
+/// Indices created before these options existed use the defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexOptions {
+    /// Quantization used while traversing the index, `None` for full-precision vectors.
+    #[serde(default)]
+    pub quantization: Option<QuantizationKind>,
+    /// Number of vectors the quantizer is trained with, the index is not quantized until it has this many vectors.
+    #[serde(default = "default_train_size")]
+    pub train_size: usize,
+    /// Whether the final candidates of a quantized search are rescored with full-precision vectors by default.
+    #[serde(default = "default_rescore")]
+    pub rescore: bool,
+    /// Number of sub-spaces of product quantization, i.e. bytes per vector; defaults to one per 8 dimensions.
+    #[serde(default)]
+    pub pq_subspaces: Option<usize>,
+    /// Precision of the stored points.
+    #[serde(default)]
+    pub precision: Precision,
+}
+
+fn default_train_size() -> usize {
+    1000
+}
+
+fn default_rescore() -> bool {
+    true
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            quantization: None,
+            train_size: default_train_size(),
+            rescore: default_rescore(),
+            pq_subspaces: None,
+            precision: Precision::F32,
+        }
+    }
+}
+
+impl IndexOptions {
+    /// Options for a new index, read from the environment.
+    ///
+    /// Fails on unknown or invalid values, as the options are persisted for the lifetime of the index.
+    pub fn from_config(cfg: &Config) -> Result<Self, ValidationError> {
+        let quantization = match cfg.quantization.as_deref() {
+            None => None,
+            Some("scalar") => Some(QuantizationKind::Scalar),
+            Some("product") => Some(QuantizationKind::Product),
+            Some("binary") => Some(QuantizationKind::Binary),
+            Some(other) => {
+                return Err(ValidationError(format!(
+                    "QUANTIZATION should be scalar, product or binary, found {:?}",
+                    other
+                )))
+            }
+        };
+
+        let precision = match cfg.storage_type.as_deref() {
+            None | Some("f32") => Precision::F32,
+            Some("f16") => Precision::F16,
+            Some("bf16") => Precision::Bf16,
+            Some(other) => {
+                return Err(ValidationError(format!(
+                    "STORAGE_TYPE should be f32, f16 or bf16, found {:?}",
+                    other
+                )))
+            }
+        };
+
+        let train_size = match cfg.quantization_train_size.as_deref() {
+            None => default_train_size(),
+            Some(value) => parse_positive("QUANTIZATION_TRAIN_SIZE", value)?,
+        };
+
+        let pq_subspaces = match cfg.pq_subspaces.as_deref() {
+            None => None,
+            Some(value) => Some(parse_positive("PQ_SUBSPACES", value)?),
+        };
+
+        Ok(IndexOptions {
+            quantization,
+            train_size,
+            rescore: cfg.rescore,
+            pq_subspaces,
+            precision,
+        })
+    }
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize, ValidationError> {
+    value
+        .parse::<usize>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| {
+            ValidationError(format!(
+                "{} should be a positive integer, found {:?}",
+                name, value
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let mut cfg = Config::new();
+        cfg.quantization = Some("scalar".to_string());
+        cfg.storage_type = Some("f16".to_string());
+        let options = IndexOptions::from_config(&cfg).unwrap();
+        assert_eq!(options.quantization, Some(QuantizationKind::Scalar));
+        assert_eq!(options.precision, Precision::F16);
+
+        cfg.quantization = Some("scalr".to_string());
+        assert!(IndexOptions::from_config(&cfg).is_err());
+        cfg.quantization = None;
+        cfg.storage_type = Some("fp16".to_string());
+        assert!(IndexOptions::from_config(&cfg).is_err());
+        cfg.storage_type = None;
+
+        cfg.quantization_train_size = Some("5000".to_string());
+        cfg.pq_subspaces = Some("16".to_string());
+        let options = IndexOptions::from_config(&cfg).unwrap();
+        assert_eq!(options.train_size, 5000);
+        assert_eq!(options.pq_subspaces, Some(16));
+        for invalid in ["5k", "0", "-1"] {
+            cfg.quantization_train_size = Some(invalid.to_string());
+            assert!(IndexOptions::from_config(&cfg).is_err());
+        }
+        cfg.quantization_train_size = None;
+        cfg.pq_subspaces = Some("sixteen".to_string());
+        assert!(IndexOptions::from_config(&cfg).is_err());
+    }
+}
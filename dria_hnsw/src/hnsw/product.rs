@@ -0,0 +1,232 @@
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Number of centroids per sub-space, so that each sub-space is encoded with a single byte.
+const PQ_CENTROIDS: usize = 256;
+/// Number of k-means iterations while training the codebooks.
+const PQ_ITERATIONS: usize = 20;
+
+/// Product quantizer, which splits vectors into `m` sub-spaces and encodes each sub-space
+/// with the index of its closest centroid in that sub-space's codebook.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductQuantizer {
+    dim: usize,
+    m: usize,
+    k: usize,
+    /// Centroids of each sub-space, flattened as `k` consecutive centroids per sub-space.
+    codebooks: Vec<Vec<f32>>,
+}
+
+/// Distances from a query to every centroid, computed once per query so that the distance
+/// to a code is a sum of `m` table lookups (asymmetric distance computation).
+pub struct DistanceTable {
+    k: usize,
+    /// Dot products of each query sub-vector with each centroid.
+    dots: Vec<f32>,
+    /// Squared norms of each centroid.
+    norms: Vec<f32>,
+    /// Squared norm of the query.
+    q_norm: f32,
+    metric: Option<String>,
+}
+
+impl ProductQuantizer {
+    /// Trains the codebooks with k-means over the given vectors, each sub-space in parallel.
+    pub fn train(vectors: &[Vec<f32>], m: usize) -> Self {
+        let dim = vectors.first().map(|v| v.len()).unwrap_or(0);
+        let m = m.clamp(1, dim.max(1));
+        let k = PQ_CENTROIDS.min(vectors.len()).max(1);
+
+        let mut pq = ProductQuantizer {
+            dim,
+            m,
+            k,
+            codebooks: vec![],
+        };
+
+        pq.codebooks = (0..m)
+            .into_par_iter()
+            .map(|j| {
+                let (start, end) = pq.bounds(j);
+                let sub = vectors
+                    .iter()
+                    .map(|v| &v[start..end])
+                    .collect::<Vec<&[f32]>>();
+                kmeans(&sub, k, j as u64)
+            })
+            .collect();
+
+        pq
+    }
+
+    /// Start & end of the `j`-th sub-space, sub-spaces differ at most by one dimension.
+    fn bounds(&self, j: usize) -> (usize, usize) {
+        (j * self.dim / self.m, (j + 1) * self.dim / self.m)
+    }
+
+    fn centroid(&self, j: usize, c: usize) -> &[f32] {
+        let (start, end) = self.bounds(j);
+        let dsub = end - start;
+        &self.codebooks[j][c * dsub..(c + 1) * dsub]
+    }
+
+    /// Encodes a vector with one byte per sub-space.
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        (0..self.m)
+            .map(|j| {
+                let (start, end) = self.bounds(j);
+                nearest(&self.codebooks[j], &v[start..end], self.k) as u8
+            })
+            .collect()
+    }
+
+    /// Restores an approximate vector from its code, by concatenating its centroids.
+    pub fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .enumerate()
+            .flat_map(|(j, &c)| self.centroid(j, c as usize).to_vec())
+            .collect()
+    }
+
+    /// Computes the distance table of a query, w.r.t. the metric of the index.
+    pub fn distance_table(&self, q: &[f32], metric: &Option<String>) -> DistanceTable {
+        let mut dots = Vec::with_capacity(self.m * self.k);
+        let mut norms = Vec::with_capacity(self.m * self.k);
+        for j in 0..self.m {
+            let (start, end) = self.bounds(j);
+            let q_sub = &q[start..end];
+            for c in 0..self.k {
+                let centroid = self.centroid(j, c);
+                dots.push(dot(q_sub, centroid));
+                norms.push(dot(centroid, centroid));
+            }
+        }
+
+        DistanceTable {
+            k: self.k,
+            dots,
+            norms,
+            q_norm: dot(q, q),
+            metric: metric.clone(),
+        }
+    }
+}
+
+impl DistanceTable {
+    /// Distance from the query to a code, matching the distances of full-precision vectors.
+    pub fn distance(&self, code: &[u8]) -> f32 {
+        let mut ab = 0.0;
+        let mut b2 = 0.0;
+        for (j, &c) in code.iter().enumerate() {
+            let i = j * self.k + c as usize;
+            ab += self.dots[i];
+            b2 += self.norms[i];
+        }
+
+        match self.metric.as_deref() {
+            Some("sqeuclidean") => self.q_norm - 2.0 * ab + b2,
+            Some("inner") => 1.0 - ab,
+            Some("cosine") | None => {
+                if self.q_norm == 0.0 || b2 == 0.0 {
+                    1.0
+                } else {
+                    1.0 - ab / (self.q_norm * b2).sqrt()
+                }
+            }
+            // like `HNSW::distance`, unsupported metrics are rejected before searching
+            _ => f32::MAX,
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn sqeuclidean(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Index of the closest of the `k` flattened centroids to `v`.
+fn nearest(centroids: &[f32], v: &[f32], k: usize) -> usize {
+    let dsub = v.len();
+    (0..k)
+        .map(|c| (c, sqeuclidean(&centroids[c * dsub..(c + 1) * dsub], v)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(c, _)| c)
+        .unwrap_or(0)
+}
+
+/// Lloyd's k-means, initialized with distinct random vectors. Returns the flattened centroids.
+fn kmeans(vectors: &[&[f32]], k: usize, seed: u64) -> Vec<f32> {
+    let dsub = vectors.first().map(|v| v.len()).unwrap_or(0);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut centroids = sample(&mut rng, vectors.len(), k)
+        .into_iter()
+        .flat_map(|i| vectors[i].to_vec())
+        .collect::<Vec<f32>>();
+
+    for _ in 0..PQ_ITERATIONS {
+        let mut sums = vec![0.0; k * dsub];
+        let mut counts = vec![0usize; k];
+        for v in vectors {
+            let c = nearest(&centroids, v, k);
+            counts[c] += 1;
+            for (s, x) in sums[c * dsub..(c + 1) * dsub].iter_mut().zip(v.iter()) {
+                *s += x;
+            }
+        }
+
+        // empty clusters keep their previous centroid
+        for c in 0..k {
+            if counts[c] > 0 {
+                for d in 0..dsub {
+                    centroids[c * dsub + d] = sums[c * dsub + d] / counts[c] as f32;
+                }
+            }
+        }
+    }
+
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_product_quantizer() {
+        let mut rng = rand::thread_rng();
+        let vectors: Vec<Vec<f32>> = (0..500)
+            .map(|_| (0..30).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+
+        let pq = ProductQuantizer::train(&vectors, 8);
+        let q = &vectors[0];
+        for metric in [
+            None,
+            Some("sqeuclidean".to_string()),
+            Some("inner".to_string()),
+        ] {
+            let table = pq.distance_table(q, &metric);
+            for v in vectors.iter().take(20) {
+                let code = pq.encode(v);
+                assert_eq!(code.len(), 8);
+
+                // table lookups must agree with the distance to the decoded vector
+                let decoded = pq.decode(&code);
+                assert_eq!(decoded.len(), v.len());
+                let expected = match metric.as_deref() {
+                    Some("sqeuclidean") => sqeuclidean(q, &decoded),
+                    Some("inner") => 1.0 - dot(q, &decoded),
+                    _ => 1.0 - dot(q, &decoded) / (dot(q, q) * dot(&decoded, &decoded)).sqrt(),
+                };
+                assert!((table.distance(&code) - expected).abs() < 1e-3);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::hnsw::binary::BinaryQuantizer;
+use crate::hnsw::options::{IndexOptions, QuantizationKind};
+use crate::hnsw::product::{DistanceTable, ProductQuantizer};
+use crate::hnsw::scalar::ScalarQuantizer;
+use serde::{Deserialize, Serialize};
+
+/// A trained quantizer, which maps full-precision vectors to compact codes.
+///
+/// Codes are stored as [`PointQuant`](crate::proto::index_buffer::PointQuant) next to the points,
+/// and the index is traversed over them instead of the full-precision vectors.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Quantizer {
+    Scalar(ScalarQuantizer),
+    Product(ProductQuantizer),
+    Binary(BinaryQuantizer),
+}
+
+/// A query prepared for a quantizer, so that its distance to a code is computed without decoding the code.
+pub enum QueryCode {
+    /// Distance table of a product quantized query.
+    Table(DistanceTable),
+    /// Binary code of the query, compared by Hamming distance.
+    Binary(BinaryQuantizer, Vec<u8>),
+}
+
+impl QueryCode {
+    pub fn distance(&self, code: &[u8]) -> f32 {
+        match self {
+            QueryCode::Table(table) => table.distance(code),
+            QueryCode::Binary(bq, q_code) => bq.distance(q_code, code),
+        }
+    }
+}
+
+impl Quantizer {
+    /// Trains a quantizer w.r.t. the index options over the given vectors, `None` if the index is not quantized.
+    pub fn train(options: &IndexOptions, vectors: &[Vec<f32>]) -> Option<Quantizer> {
+        let dim = vectors.first().map(|v| v.len()).unwrap_or(0);
+        let quantizer = match options.quantization? {
+            QuantizationKind::Scalar => {
+                let mut sq = ScalarQuantizer::new(256, 1000, dim);
+                sq.merge(
+                    vectors
+                        .iter()
+                        .map(|v| v.iter().map(|x| *x as f64).collect())
+                        .collect(),
+                );
+                Quantizer::Scalar(sq)
+            }
+            QuantizationKind::Product => {
+                // 8 dimensions per byte by default, e.g. 192 bytes for 1536 dimensions
+                let m = options.pq_subspaces.unwrap_or(dim / 8);
+                Quantizer::Product(ProductQuantizer::train(vectors, m))
+            }
+            QuantizationKind::Binary => Quantizer::Binary(BinaryQuantizer::new(dim)),
+        };
+        Some(quantizer)
+    }
+
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        match self {
+            Quantizer::Scalar(sq) => sq.encode(v),
+            Quantizer::Product(pq) => pq.encode(v),
+            Quantizer::Binary(bq) => bq.encode(v),
+        }
+    }
+
+    /// Restores an approximate vector from its code, which can be compared to a full-precision query.
+    pub fn decode(&self, code: &[u8]) -> Vec<f32> {
+        match self {
+            Quantizer::Scalar(sq) => sq.decode(code),
+            Quantizer::Product(pq) => pq.decode(code),
+            Quantizer::Binary(bq) => bq.decode(code),
+        }
+    }
+
+    /// Prepares a query, if the quantizer compares codes to queries directly instead of decoding them.
+    pub fn prepare(&self, q: &[f32], metric: &Option<String>) -> Option<QueryCode> {
+        match self {
+            Quantizer::Scalar(_) => None,
+            Quantizer::Product(pq) => Some(QueryCode::Table(pq.distance_table(q, metric))),
+            Quantizer::Binary(bq) => Some(QueryCode::Binary(bq.clone(), bq.encode(q))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_scalar_quantizer() {
+        let mut rng = rand::thread_rng();
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|_| (0..32).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+
+        let options = IndexOptions {
+            quantization: Some(QuantizationKind::Scalar),
+            ..Default::default()
+        };
+        let quantizer = Quantizer::train(&options, &vectors).unwrap();
+        for v in vectors.iter() {
+            let code = quantizer.encode(v);
+            assert_eq!(code.len(), v.len());
+
+            // each value is restored within a few quantiles of its original value
+            let decoded = quantizer.decode(&code);
+            for (x, y) in v.iter().zip(decoded.iter()) {
+                assert!((x - y).abs() < 0.1, "{} vs {}", x, y);
+            }
+        }
+    }
+}
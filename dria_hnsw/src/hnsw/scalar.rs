@@ -4,10 +4,12 @@ extern crate serde_json;
 use serde::{Deserialize, Serialize};
 use tdigest::TDigest;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScalarQuantizer {
     levels: usize,
     quantiles: Vec<f64>,
+    /// Only needed while training, the quantiles are enough to quantize afterwards.
+    #[serde(skip)]
     t_digest: TDigest,
     dim: usize,
 }
@@ -62,4 +64,18 @@ impl ScalarQuantizer {
             .map(|value| self.__quantize_scalar(value))
             .collect()
     }
+
+    /// Quantizes a vector to one byte per dimension.
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        v.iter()
+            .map(|value| self.__quantize_scalar(&(*value as f64)).min(255) as u8)
+            .collect()
+    }
+
+    /// Restores an approximate vector from its quantized bytes.
+    pub fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .map(|&val| self.quantiles[(val as usize).min(self.quantiles.len() - 1)] as f32)
+            .collect()
+    }
 }
@@ -0,0 +1,106 @@
+//! Traces of searches over the graph, returned by queries with `explain`.
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How a search went through the graph.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SearchTrace {
+    /// Entry point of the graph, on its top layer.
+    pub entry_point: Hop,
+    /// Layers from the top one down to the base layer.
+    pub layers: Vec<LayerTrace>,
+    /// Number of points compared to the query while traversing the graph.
+    pub visited: usize,
+    /// Number of distances computed, including the entry point and rescoring.
+    pub distance_computations: usize,
+    pub cache: CacheStats,
+    pub timings: SearchTimings,
+}
+
+/// A point reached by a search, with its distance to the query.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Hop {
+    pub id: u32,
+    pub distance: f32,
+}
+
+/// How a search went through a layer.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct LayerTrace {
+    pub level: usize,
+    /// Candidates whose neighbors were visited, in order.
+    pub hops: Vec<Hop>,
+    /// Number of points compared to the query on this layer.
+    pub visited: usize,
+}
+
+/// Vectors and nodes found in the caches of the server, and those read from the store.
+///
+/// Vectors are quantized codes if the index is quantized, except for rescoring.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub vector_hits: usize,
+    pub vector_reads: usize,
+    pub node_hits: usize,
+    pub node_reads: usize,
+}
+
+/// Time spent in each phase of a search, in microseconds.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchTimings {
+    /// Descending from the entry point to the base layer.
+    pub upper_layers_us: u64,
+    /// Searching the base layer, and leaving out deleted points.
+    pub base_layer_us: u64,
+    /// Re-ranking the candidates with full-precision vectors.
+    pub rescore_us: u64,
+    /// Reading the metadata of the results.
+    pub results_us: u64,
+}
+
+pub(crate) fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+/// Counters of [`CacheStats`], shared by the searches of an index.
+#[derive(Debug, Default)]
+pub(crate) struct CacheCounters {
+    vector_hits: AtomicUsize,
+    vector_reads: AtomicUsize,
+    node_hits: AtomicUsize,
+    node_reads: AtomicUsize,
+}
+
+impl CacheCounters {
+    pub fn vectors(&self, hits: usize, reads: usize) {
+        self.vector_hits.fetch_add(hits, Ordering::Relaxed);
+        self.vector_reads.fetch_add(reads, Ordering::Relaxed);
+    }
+
+    pub fn nodes(&self, hits: usize, reads: usize) {
+        self.node_hits.fetch_add(hits, Ordering::Relaxed);
+        self.node_reads.fetch_add(reads, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            vector_hits: self.vector_hits.load(Ordering::Relaxed),
+            vector_reads: self.vector_reads.load(Ordering::Relaxed),
+            node_hits: self.node_hits.load(Ordering::Relaxed),
+            node_reads: self.node_reads.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CacheStats {
+    /// Counts since the given stats were taken.
+    pub fn since(&self, earlier: &CacheStats) -> CacheStats {
+        CacheStats {
+            vector_hits: self.vector_hits - earlier.vector_hits,
+            vector_reads: self.vector_reads - earlier.vector_reads,
+            node_hits: self.node_hits - earlier.node_hits,
+            node_reads: self.node_reads - earlier.node_reads,
+        }
+    }
+}
@@ -7,3 +7,5 @@ pub mod proto;
 pub mod responses;
 pub mod worker;
 pub mod filter;
+pub mod formats;
+pub mod bench;
@@ -2,15 +2,71 @@ use actix_cors::Cors;
 use actix_web::middleware::Logger;
 use actix_web::{web, App, HttpServer};
 use dria_hnsw::db::env::Config;
-use dria_hnsw::db::rocksdb_client::RocksdbClient;
+use dria_hnsw::db::memory_client::MemoryClient;
+use dria_hnsw::db::redis_client::RedisClient;
+use dria_hnsw::db::rocksdb_client::{CatchUp, RocksdbClient};
+use dria_hnsw::db::store::VectorStore;
+use dria_hnsw::hnsw::options::IndexOptions;
 use dria_hnsw::middlewares::cache::{NodeCache, PointCache};
-use dria_hnsw::worker::{fetch, get_health_status, insert_vector, query};
+use dria_hnsw::worker::{
+    delete, fetch, fetch_partial, get_health_status, insert_vector, list_snapshots, query, restore,
+    scroll, snapshot,
+};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub fn config(conf: &mut web::ServiceConfig) {
     conf.service(get_health_status);
     conf.service(query);
     conf.service(fetch);
+    conf.service(fetch_partial);
+    conf.service(scroll);
     conf.service(insert_vector);
+    conf.service(delete);
+    conf.service(snapshot);
+    conf.service(list_snapshots);
+    conf.service(restore);
+}
+
+/// Periodically catches a read-only replica up with its primary, replacing the cached nodes
+/// that the primary rewrote.
+///
+/// Points are never rewritten, so they stay cached unless the index was replaced, i.e. it was
+/// restored from a snapshot or it shrinks.
+fn spawn_catch_up(
+    replica: Arc<RocksdbClient>,
+    node_cache: web::Data<NodeCache>,
+    point_cache: web::Data<PointCache>,
+    contract_id: String,
+    interval: Duration,
+) {
+    std::thread::spawn(move || {
+        let mut datasize = replica.get_datasize().unwrap_or(0);
+        loop {
+            std::thread::sleep(interval);
+            match replica.catch_up() {
+                Ok(CatchUp::Written) => {
+                    if let Err(e) = node_cache.refresh(&contract_id, replica.as_ref()) {
+                        println!("Failed to refresh the cached nodes: {}", e);
+                        node_cache.invalidate(&contract_id);
+                    }
+                    let new_datasize = replica.get_datasize().unwrap_or(0);
+                    if new_datasize < datasize {
+                        point_cache.invalidate(&contract_id);
+                    }
+                    datasize = new_datasize;
+                }
+                // the primary restored a snapshot, whose points may differ at the same indices
+                Ok(CatchUp::Reopened) => {
+                    node_cache.invalidate(&contract_id);
+                    point_cache.invalidate(&contract_id);
+                    datasize = replica.get_datasize().unwrap_or(0);
+                }
+                Ok(CatchUp::Unchanged) => {}
+                Err(e) => println!("Failed to catch up with the primary: {}", e),
+            }
+        }
+    });
 }
 
 #[actix_web::main]
@@ -18,24 +74,66 @@ async fn main() -> std::io::Result<()> {
     let node_cache = web::Data::new(NodeCache::new());
     let point_cache = web::Data::new(PointCache::new());
     let cfg = Config::new();
-
-    let rocksdb_client = RocksdbClient::new(cfg.contract_id.clone());
-
-    if rocksdb_client.is_err() {
-        println!("Rocksdb client failed to initialize");
+    // options of a new index are only read on its first insertion, so they are checked upfront
+    if let Err(e) = IndexOptions::from_config(&cfg) {
         return Err(std::io::Error::new(
             std::io::ErrorKind::Other,
-            "Rocksdb client failed to initialize",
+            e.to_string(),
         ));
     }
-    let rdb = rocksdb_client.unwrap();
-    let rocksdb_client = web::Data::new(rdb);
+
+    let store: web::Data<dyn VectorStore> = match cfg.store.as_str() {
+        "redis" => {
+            let redis_client = RedisClient::new(cfg.contract_id.clone()).map_err(|e| {
+                println!("Redis client failed to initialize");
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Redis client failed to initialize: {}", e),
+                )
+            })?;
+            web::Data::from(Arc::new(redis_client) as Arc<dyn VectorStore>)
+        }
+        "memory" => web::Data::from(Arc::new(MemoryClient::new()) as Arc<dyn VectorStore>),
+        "rocksdb" => {
+            let rocksdb_client = RocksdbClient::new(cfg.contract_id.clone());
+
+            if rocksdb_client.is_err() {
+                println!("Rocksdb client failed to initialize");
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "Rocksdb client failed to initialize",
+                ));
+            }
+            let rdb = Arc::new(rocksdb_client.unwrap());
+
+            if cfg.read_only {
+                spawn_catch_up(
+                    rdb.clone(),
+                    node_cache.clone(),
+                    point_cache.clone(),
+                    cfg.contract_id.clone(),
+                    Duration::from_secs(cfg.catch_up_interval),
+                );
+            }
+
+            web::Data::from(rdb as Arc<dyn VectorStore>)
+        }
+        other => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "STORE should be rocksdb, redis or memory, found {:?}",
+                    other
+                ),
+            ));
+        }
+    };
 
     let factory = move || {
         App::new()
             .app_data(web::JsonConfig::default().limit(152428800))
             .app_data(node_cache.clone())
-            .app_data(rocksdb_client.clone())
+            .app_data(store.clone())
             .app_data(point_cache.clone())
             .configure(config)
             .wrap(Logger::default())
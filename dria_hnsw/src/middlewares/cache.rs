@@ -1,11 +1,17 @@
+use crate::db::store::{VectorStore, ITER_BATCH_SIZE};
+use crate::errors::errors::DeserializeError;
+use crate::hnsw::quantization::Quantizer;
 use crate::hnsw::sync_map::SynchronizedNodes;
-use crate::proto::index_buffer::Point;
+use crate::proto::index_buffer::{Point, PointQuant};
 use mini_moka::sync::Cache;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
 pub struct NodeCache {
     pub caches: Cache<String, Arc<SynchronizedNodes>>,
+    /// Generation of each index when its nodes were last cached, see [`NodeCache::sync`].
+    generations: Cache<String, u64>,
 }
 
 /// If a key within cache is not used (i.e. get or insert) for the given duration (seconds), expire that key.
@@ -20,7 +26,10 @@ impl NodeCache {
             .max_capacity(NODE_CACHE_CAPACITY)
             .build();
 
-        NodeCache { caches: cache }
+        NodeCache {
+            caches: cache,
+            generations: Cache::new(NODE_CACHE_CAPACITY),
+        }
     }
 
     pub fn get_cache(&self, key: String) -> Arc<SynchronizedNodes> {
@@ -39,6 +48,54 @@ impl NodeCache {
         let my_cache = self.caches.clone();
         my_cache.insert(key.to_string(), cache);
     }
+
+    /// Drops the cached nodes of an index if it was written to since they were cached,
+    /// i.e. when another server inserted into or deleted from a shared store.
+    ///
+    /// See [`VectorStore::get_generation`](crate::db::store::VectorStore::get_generation).
+    pub fn sync(&self, key: &str, generation: u64) {
+        let key = key.to_string();
+        if self.generations.get(&key) != Some(generation) {
+            self.add_cache(&key, Arc::new(SynchronizedNodes::new()));
+            self.generations.insert(key, generation);
+        }
+    }
+
+    /// Keeps the cached nodes of an index at a generation, after this server wrote it.
+    pub fn synced(&self, key: &str, generation: u64) {
+        self.generations.insert(key.to_string(), generation);
+    }
+
+    /// Replaces the cached nodes of an index that differ from the ones in the store,
+    /// e.g. after a replica caught up with its primary, and returns how many were replaced.
+    pub fn refresh(&self, key: &str, store: &dyn VectorStore) -> Result<usize, DeserializeError> {
+        let nodes = self.get_cache(key.to_string());
+        let mut layers: HashMap<u32, Vec<u32>> = HashMap::new();
+        for entry in nodes.map.iter() {
+            let node = entry.value();
+            layers.entry(node.level).or_default().push(node.idx);
+        }
+
+        let mut replaced = 0;
+        for (layer, indices) in layers {
+            for chunk in indices.chunks(ITER_BATCH_SIZE) {
+                for node in store.get_neighbors(layer as usize, chunk.to_vec())? {
+                    let key = format!("{}:{}", node.level, node.idx);
+                    if nodes.map.get(&key).map_or(true, |cached| *cached != node) {
+                        nodes.insert_and_notify(&node);
+                        replaced += 1;
+                    }
+                }
+            }
+        }
+        Ok(replaced)
+    }
+
+    /// Drops the cached nodes of an index, e.g. after another server wrote to it.
+    pub fn invalidate(&self, key: &str) {
+        self.add_cache(key, Arc::new(SynchronizedNodes::new()));
+        self.generations.invalidate(&key.to_string());
+    }
 }
 
 /// If a key within cache is not used (i.e. get or insert) for the given duration (seconds), expire that key.
@@ -48,6 +105,8 @@ const POINT_CACHE_CAPACITY: u64 = 5_000;
 
 pub struct PointCache {
     pub caches: Cache<String, Cache<String, Point>>, //Cache<String, Arc<DashMap<String, Point>>>,
+    pub code_caches: Cache<String, Cache<String, PointQuant>>,
+    pub quantizers: Cache<String, Arc<Quantizer>>,
 }
 
 impl PointCache {
@@ -57,7 +116,21 @@ impl PointCache {
             .max_capacity(POINT_CACHE_CAPACITY) // around 106MB for 1536 dim vectors
             .build();
 
-        PointCache { caches: cache }
+        let code_cache = Cache::builder()
+            .time_to_idle(Duration::from_secs(POINT_CACHE_EXPIRE))
+            .max_capacity(POINT_CACHE_CAPACITY)
+            .build();
+
+        let quantizers = Cache::builder()
+            .time_to_idle(Duration::from_secs(POINT_CACHE_EXPIRE))
+            .max_capacity(POINT_CACHE_CAPACITY)
+            .build();
+
+        PointCache {
+            caches: cache,
+            code_caches: code_cache,
+            quantizers,
+        }
     }
 
     pub fn get_cache(&self, key: String) -> Cache<String, Point> {
@@ -81,6 +154,34 @@ impl PointCache {
     pub fn add_cache(&self, key: &str, cache: Cache<String, Point>) {
         self.caches.insert(key.to_string(), cache);
     }
+
+    /// Cache of quantized codes, used instead of the points when the index is quantized.
+    pub fn get_code_cache(&self, key: String) -> Cache<String, PointQuant> {
+        let my_cache = self.code_caches.clone();
+        my_cache.get(&key).unwrap_or_else(|| {
+            let new_cache = Cache::builder()
+                .max_capacity(1_000_000) // around 1536MB for 1536 dim scalar codes
+                .build();
+            my_cache.insert(key.to_string(), new_cache.clone());
+            new_cache
+        })
+    }
+
+    pub fn get_quantizer(&self, key: &str) -> Option<Arc<Quantizer>> {
+        self.quantizers.get(&key.to_string())
+    }
+
+    pub fn set_quantizer(&self, key: &str, quantizer: Arc<Quantizer>) {
+        self.quantizers.insert(key.to_string(), quantizer);
+    }
+
+    /// Drops the cached points, codes and quantizer of an index, e.g. after it was replaced.
+    pub fn invalidate(&self, key: &str) {
+        let key = key.to_string();
+        self.caches.invalidate(&key);
+        self.code_caches.invalidate(&key);
+        self.quantizers.invalidate(&key);
+    }
 }
 
 #[cfg(test)]
@@ -125,4 +226,44 @@ mod tests {
         let ix = cache.get(&0);
         assert_eq!(ix, Some("Value 0".to_string()));
     }
+
+    #[test]
+    fn test_node_cache_refresh() {
+        use crate::db::memory_client::MemoryClient;
+        use crate::proto::index_buffer::LayerNode;
+
+        let store = MemoryClient::new();
+        let cache = NodeCache::new();
+        let nodes = (0..3).map(|idx| LayerNode::new(0, idx)).collect::<Vec<_>>();
+        store.upsert_neighbors(nodes.clone()).unwrap();
+        cache
+            .get_cache("a".to_string())
+            .insert_batch_and_notify(nodes[..2].to_vec());
+
+        // only the cached nodes that were rewritten are replaced, and the others stay uncached
+        let mut node = nodes[1].clone();
+        node.visible = false;
+        store.upsert_neighbor(node.clone()).unwrap();
+        assert_eq!(cache.refresh("a", &store).unwrap(), 1);
+        let cached = cache.get_cache("a".to_string());
+        assert_eq!(cached.map.get("0:1").unwrap().value(), &node);
+        assert_eq!(cached.map.get("0:0").unwrap().value(), &nodes[0]);
+        assert!(cached.map.get("0:2").is_none());
+    }
+
+    #[test]
+    fn test_node_cache_sync() {
+        let cache = NodeCache::new();
+        let nodes = cache.get_cache("a".to_string());
+        cache.sync("a", 1);
+        let synced = cache.get_cache("a".to_string());
+        assert!(!Arc::ptr_eq(&nodes, &synced));
+
+        // nodes written by this server are kept, and dropped once another server writes
+        cache.synced("a", 2);
+        cache.sync("a", 2);
+        assert!(Arc::ptr_eq(&synced, &cache.get_cache("a".to_string())));
+        cache.sync("a", 3);
+        assert!(!Arc::ptr_eq(&synced, &cache.get_cache("a".to_string())));
+    }
 }
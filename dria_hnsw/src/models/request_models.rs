@@ -1,12 +1,15 @@
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
+use crate::db::store::is_valid_snapshot_name;
 use crate::errors::errors::ValidationError;
+use crate::filter::projection::{Projection, VectorEncoding};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InsertModel {
     pub vector: Vec<f32>,
     pub metadata: Value,
+    pub external_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -16,7 +19,109 @@ pub struct InsertBatchModel {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FetchModel {
+    #[serde(default)]
     pub id: Vec<u32>, // TODO: rename this to `ids`
+    pub external_id: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub include_vector: Option<bool>,
+    pub vector_encoding: Option<VectorEncoding>,
+}
+
+impl FetchModel {
+    pub fn projection(&self) -> Projection {
+        Projection::new(
+            self.include.clone(),
+            self.exclude.clone(),
+            self.include_vector,
+            self.vector_encoding,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeleteModel {
+    #[serde(default)]
+    pub id: Vec<u32>,
+    pub external_id: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotModel {
+    /// Defaults to one from the current time.
+    pub name: Option<String>,
+}
+
+impl SnapshotModel {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match &self.name {
+            Some(name) => validate_snapshot_name(name),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RestoreModel {
+    pub name: String,
+}
+
+impl RestoreModel {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_snapshot_name(&self.name)
+    }
+}
+
+fn validate_snapshot_name(name: &str) -> Result<(), ValidationError> {
+    if is_valid_snapshot_name(name) {
+        Ok(())
+    } else {
+        Err(ValidationError(
+            "Snapshot name should be at most 64 alphanumerics, `-` or `_`.".to_string(),
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScrollModel {
+    pub cursor: Option<u32>,
+    pub limit: Option<usize>,
+    pub filter: Option<Map<String, Value>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub include_vector: Option<bool>,
+    pub vector_encoding: Option<VectorEncoding>,
+}
+
+/// Number of records returned by a scroll request if no limit is given.
+pub const SCROLL_DEFAULT_LIMIT: usize = 100;
+/// Maximum number of records that can be returned by a single scroll request.
+pub const SCROLL_MAX_LIMIT: usize = 1000;
+
+impl ScrollModel {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match self.limit {
+            Some(0) => Err(ValidationError("Limit cannot be zero.".to_string())),
+            Some(limit) if limit > SCROLL_MAX_LIMIT => Err(ValidationError(format!(
+                "Limit cannot be more than {}.",
+                SCROLL_MAX_LIMIT
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(SCROLL_DEFAULT_LIMIT)
+    }
+
+    pub fn projection(&self) -> Projection {
+        Projection::new(
+            self.include.clone(),
+            self.exclude.clone(),
+            self.include_vector,
+            self.vector_encoding,
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -25,8 +130,21 @@ pub struct QueryModel {
     pub top_n: usize,
     pub query: Option<String>,
     pub level: Option<usize>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub include_vector: Option<bool>,
+    pub rescore: Option<bool>,
+    pub exact: Option<bool>,
+    /// Size of the candidate list, overrides `level`.
+    pub ef: Option<usize>,
+    pub max_visits: Option<usize>,
+    pub timeout_ms: Option<u64>,
+    pub explain: Option<bool>,
 }
 
+/// Maximum size of the candidate list of a query.
+pub const QUERY_MAX_EF: usize = 1000;
+
 impl QueryModel {
     pub fn new(
         vector: Vec<f32>,
@@ -42,9 +160,52 @@ impl QueryModel {
             top_n,
             query,
             level,
+            include: None,
+            exclude: None,
+            include_vector: None,
+            rescore: None,
+            exact: None,
+            ef: None,
+            max_visits: None,
+            timeout_ms: None,
+            explain: None,
         })
     }
 
+    /// Validates a deserialized query, which is not checked by [`QueryModel::new`].
+    ///
+    /// Besides `top_n` and `level`, this checks the search budget, i.e. `ef`, `max_visits` and `timeout_ms`.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        Self::validate_top_n(self.top_n)?;
+        Self::validate_level(self.level)?;
+        match self.ef {
+            Some(0) => return Err(ValidationError("Ef cannot be zero.".to_string())),
+            Some(ef) if ef > QUERY_MAX_EF => {
+                return Err(ValidationError(format!(
+                    "Ef cannot be more than {}.",
+                    QUERY_MAX_EF
+                )))
+            }
+            _ => {}
+        }
+        if self.max_visits == Some(0) {
+            return Err(ValidationError("Max visits cannot be zero.".to_string()));
+        }
+        if self.timeout_ms == Some(0) {
+            return Err(ValidationError("Timeout cannot be zero.".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn projection(&self) -> Projection {
+        Projection::new(
+            self.include.clone(),
+            self.exclude.clone(),
+            self.include_vector,
+            None,
+        )
+    }
+
     fn validate_top_n(top_n: usize) -> Result<(), ValidationError> {
         if top_n > 20 {
             Err(ValidationError("Top N cannot be more than 20.".to_string()))
@@ -1,3 +1,7 @@
+use crate::db::conversions::{half_to_vector, vector_to_half};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct LayerNode {
     /// Using uint64 as a safe alternative for usize
@@ -9,9 +13,9 @@ pub struct LayerNode {
     /// Whether the node is visible
     #[prost(bool, tag = "3")]
     pub visible: bool,
-    /// Neighbor idx and its distance
-    #[prost(map = "uint32, float", tag = "4")]
-    pub neighbors: ::std::collections::HashMap<u32, f32>,
+    /// Neighbor idx and its distance, ordered so that a node is always encoded the same way
+    #[prost(btree_map = "uint32, float", tag = "4")]
+    pub neighbors: ::std::collections::BTreeMap<u32, f32>,
 }
 impl LayerNode {
     pub fn new(level: usize, idx: usize) -> LayerNode {
@@ -19,7 +23,7 @@ impl LayerNode {
             level: level as u32,
             idx: idx as u32,
             visible: true,
-            neighbors: ::std::collections::HashMap::new(),
+            neighbors: ::std::collections::BTreeMap::new(),
         }
     }
 }
@@ -32,14 +36,69 @@ pub struct Point {
     /// Vector of floats
     #[prost(float, repeated, tag = "2")]
     pub v: ::prost::alloc::vec::Vec<f32>,
+    /// Precision of the stored vector
+    #[prost(enumeration = "Precision", tag = "3")]
+    pub precision: i32,
+    /// Vector of little-endian 16-bit floats
+    #[prost(bytes = "vec", tag = "4")]
+    pub h: ::prost::alloc::vec::Vec<u8>,
 }
 impl Point {
     pub fn new(vec: Vec<f32>, idx: usize) -> Point {
         Point {
             idx: idx as u32,
             v: vec,
+            precision: Precision::F32 as i32,
+            h: vec![],
         }
     }
+
+    /// Creates a point that stores its vector with the given precision.
+    pub fn new_with_precision(vec: Vec<f32>, idx: usize, precision: Precision) -> Point {
+        match precision {
+            Precision::F32 => Point::new(vec, idx),
+            _ => Point {
+                idx: idx as u32,
+                v: vec![],
+                precision: precision as i32,
+                h: vector_to_half(&vec, precision),
+            },
+        }
+    }
+
+    /// Returns the vector as floats, converting it if it is stored in half-precision.
+    pub fn vector(&self) -> Cow<'_, [f32]> {
+        match Precision::from_i32(self.precision) {
+            Some(precision @ (Precision::F16 | Precision::Bf16)) => {
+                Cow::Owned(half_to_vector(&self.h, precision))
+            }
+            _ => Cow::Borrowed(&self.v),
+        }
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    ::prost::Enumeration,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(i32)]
+pub enum Precision {
+    /// Vector is stored as floats in v
+    F32 = 0,
+    /// Vector is stored as half-precision floats in h
+    F16 = 1,
+    /// Vector is stored as brain floats in h
+    Bf16 = 2,
 }
 
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -47,12 +106,12 @@ pub struct PointQuant {
     /// Using uint64 as a safe alternative for usize
     #[prost(uint32, tag = "1")]
     pub idx: u32,
-    /// Vector of ints
-    #[prost(uint32, repeated, tag = "2")]
-    pub v: ::prost::alloc::vec::Vec<u32>,
+    /// Quantized codes
+    #[prost(bytes = "vec", tag = "2")]
+    pub v: ::prost::alloc::vec::Vec<u8>,
 }
 impl PointQuant {
-    pub fn new(vec: Vec<u32>, idx: usize) -> PointQuant {
+    pub fn new(vec: Vec<u8>, idx: usize) -> PointQuant {
         PointQuant {
             idx: idx as u32,
             v: vec,
@@ -1,4 +1,6 @@
+use crate::hnsw::trace::SearchTrace;
 use serde::Serialize;
+use serde_json::Value;
 
 #[derive(Serialize)]
 pub struct CustomResponse<T> {
@@ -6,3 +8,49 @@ pub struct CustomResponse<T> {
     pub(crate) data: T,
     pub(crate) code: u32,
 }
+
+/// Response of a failed request, which is a [`CustomResponse`] with the message of the error as its data.
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub(crate) success: bool,
+    pub(crate) data: String,
+    pub(crate) code: u32,
+    /// Stable code of the error, see `ApiError::code`.
+    pub(crate) error: &'static str,
+    /// What the error is about, such as the ids that were not found.
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub(crate) details: Value,
+}
+
+/// Response of a query, which is a [`CustomResponse`] flagged as partial if the search ran out of its budget.
+#[derive(Serialize)]
+pub struct QueryResponse<T> {
+    pub(crate) success: bool,
+    pub(crate) data: T,
+    pub(crate) code: u32,
+    pub(crate) partial: bool,
+    /// How the search went, only if it is explained.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) trace: Option<SearchTrace>,
+}
+
+/// Response data of a successful insertion.
+#[derive(Serialize)]
+pub struct InsertResponse {
+    /// Ids assigned to the inserted vectors, in the order they were given.
+    pub ids: Vec<u32>,
+    /// Number of vectors in the index after the insertion.
+    pub datasize: usize,
+    pub timings: InsertTimings,
+}
+
+/// Time spent in each phase of an insertion, in milliseconds.
+#[derive(Serialize)]
+pub struct InsertTimings {
+    /// Writing points, metadata and datasize.
+    pub point_write_ms: u64,
+    /// Building the graph in memory.
+    pub graph_build_ms: u64,
+    /// Writing the graph and external ids.
+    pub neighbor_flush_ms: u64,
+}
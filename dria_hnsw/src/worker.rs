@@ -1,27 +1,35 @@
 use crate::db::env::Config;
-use crate::db::rocksdb_client::RocksdbClient;
-use crate::hnsw::index::HNSW;
-use crate::hnsw::sync_map::SynchronizedNodes;
+use crate::db::store::{new_snapshot_name, RecordBatch, VectorStore};
+use crate::errors::errors::{ApiError, DeserializeError};
+use crate::filter::projection::Projection;
+use crate::hnsw::index::{SearchBudget, SearchResults, HNSW};
+use crate::hnsw::options::IndexOptions;
+use crate::hnsw::quantization::Quantizer;
 use crate::middlewares::cache::{NodeCache, PointCache};
-use crate::models::request_models::{FetchModel, InsertBatchModel, QueryModel};
-use crate::proto::index_buffer::{LayerNode, Point};
-use crate::responses::responses::CustomResponse;
+use crate::models::request_models::{
+    DeleteModel, FetchModel, InsertBatchModel, QueryModel, RestoreModel, ScrollModel, SnapshotModel,
+};
+use crate::proto::index_buffer::LayerNode;
+use crate::responses::responses::{CustomResponse, InsertResponse, InsertTimings, QueryResponse};
 use actix_web::web::{Data, Json};
 use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
-use log::error;
-use mini_moka::sync::Cache;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::task;
 
+use crate::filter::metadata::matches_filter;
 use crate::filter::text_based::create_index_from_docs;
 use probly_search::Index;
 
 pub const SINGLE_THREADED_HNSW_BUILD_THRESHOLD: usize = 256;
+/// Maximum number of vectors inserted by a request.
+pub const INSERT_MAX_BATCH_SIZE: usize = 2500;
 
 #[get("/health")]
 pub async fn get_health_status() -> HttpResponse {
@@ -34,22 +42,32 @@ pub async fn get_health_status() -> HttpResponse {
 }
 
 #[post("/query")]
-pub async fn query(req: HttpRequest, payload: Json<QueryModel>) -> HttpResponse {
-    let ind: HNSW;
+pub async fn query(req: HttpRequest, payload: Json<QueryModel>) -> Result<HttpResponse, ApiError> {
+    // the deadline includes the time spent reading options and caches
+    let start = Instant::now();
+    payload.validate()?;
+
+    let mut ind: HNSW;
 
     let cfg = Config::new();
 
-    let rocksdb_client = req
-        .app_data::<web::Data<RocksdbClient>>()
-        .expect("Error getting rocksdb client");
-
-    ind = HNSW::new(
-        16,
-        128,
-        ef_helper(payload.level),
-        None,
-        rocksdb_client.clone(),
-    );
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store");
+
+    // the candidate list can not be shorter than the results
+    let ef = payload
+        .ef
+        .unwrap_or_else(|| ef_helper(payload.level))
+        .max(payload.top_n);
+    ind = HNSW::new(16, 128, ef, None, store.clone());
+    ind.set_budget(SearchBudget {
+        max_visits: payload.max_visits,
+        deadline: payload
+            .timeout_ms
+            .map(|ms| start + Duration::from_millis(ms)),
+    });
+    ind.set_explain(payload.explain.unwrap_or(false));
     let node_cache = req
         .app_data::<web::Data<NodeCache>>()
         .expect("Error getting node cache"); //Arc<SynchronizedNodes> = Arc::new(SynchronizedNodes::new());
@@ -57,87 +75,302 @@ pub async fn query(req: HttpRequest, payload: Json<QueryModel>) -> HttpResponse
         .app_data::<web::Data<PointCache>>()
         .expect("Error getting point cache"); //Arc<DashMap<String, Point>> = Arc::new(DashMap::new());
 
+    let options = store.get_options()?.unwrap_or_default();
+    prepare_quantizer(&mut ind, point_cache, &cfg.contract_id)?;
+    ind.set_rescore(payload.rescore.unwrap_or(options.rescore));
+
+    // other servers may have written to a shared store, changing the neighbors of cached nodes
+    if store.is_shared() {
+        node_cache.sync(&cfg.contract_id, store.get_generation()?);
+    }
+
     let node_map = node_cache.get_cache(cfg.contract_id.clone()); //Arc<SynchronizedNodes> = Arc::new(SynchronizedNodes::new());
     let point_map = point_cache.get_cache(cfg.contract_id.clone());
-    let res = ind.knn_search(&payload.vector, payload.top_n, node_map, point_map);
+    let projection = payload.projection();
+    // text filtering reads from the whole metadata, so projection is applied afterwards
+    let search_projection = match payload.query {
+        Some(_) => Projection::new(None, None, Some(projection.include_vector), None),
+        None => projection.clone(),
+    };
 
-    if payload.query.is_some() {
+    // searches run on a blocking thread, as an exact search compares the query to every point
+    let payload = payload.into_inner();
+    let (vector, top_n) = (payload.vector, payload.top_n);
+    let exact = payload.exact.unwrap_or(false);
+    let res = task::spawn_blocking(move || {
+        if exact {
+            ind.exact_search(&vector, top_n, node_map, point_map, &search_projection)
+                .map(|results| SearchResults {
+                    results,
+                    partial: false,
+                    trace: None,
+                })
+        } else {
+            ind.knn_search(&vector, top_n, node_map, point_map, &search_projection)
+        }
+    })
+    .await
+    .expect("Error getting result")?;
+
+    if let Some(query) = payload.query {
         let mut index = Index::<usize>::new(1);
-        let results =
-            create_index_from_docs(&mut index, &payload.query.clone().unwrap(), res.clone());
-        let response = CustomResponse {
+        let mut results = create_index_from_docs(&mut index, &query, res.results.clone());
+        for result in results.iter_mut() {
+            // each text result holds the original hit as its metadata
+            projection.apply_to_hit(&mut result["metadata"]);
+        }
+        let response = QueryResponse {
             success: true,
             data: json!(results),
             code: 200,
+            partial: res.partial,
+            trace: res.trace,
         };
-        return HttpResponse::Ok().json(response);
+        return Ok(HttpResponse::Ok().json(response));
     }
 
-    let response = CustomResponse {
+    let response = QueryResponse {
         success: true,
-        data: json!(res),
+        data: json!(res.results),
         code: 200,
+        partial: res.partial,
+        trace: res.trace,
     };
-    HttpResponse::Ok().json(response)
+    Ok(HttpResponse::Ok().json(response))
 }
 
 #[post("/fetch")]
-pub async fn fetch(req: HttpRequest, payload: Json<FetchModel>) -> HttpResponse {
-    let ind: HNSW;
+pub async fn fetch(req: HttpRequest, payload: Json<FetchModel>) -> Result<HttpResponse, ApiError> {
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store");
 
-    let rocksdb_client = req
-        .app_data::<web::Data<RocksdbClient>>()
-        .expect("Error getting rocksdb client");
+    let projection = payload.projection();
+    let ids = resolve_ids(store.get_ref(), &payload.id, &payload.external_id)?;
+    let (indices, mut missing) = split_missing(ids);
 
-    ind = HNSW::new(16, 128, 0, None, rocksdb_client.clone());
+    // metadata is read one by one, so that every missing record is reported
+    let mut res = Vec::with_capacity(indices.len());
+    for (idx, metadata) in indices.iter().zip(store.get_metadatas_partial(&indices)) {
+        match metadata {
+            Ok(m) => res.push(projection.apply(m)),
+            Err(DeserializeError::MissingKey) => missing.add(*idx),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    missing.into_result()?;
 
-    let res = ind.db.get_metadatas(payload.id.clone());
+    if projection.include_vector {
+        let points = store.get_points(&indices)?;
+        let res = indices
+            .iter()
+            .zip(res)
+            .zip(points)
+            .map(|((id, m), p)| {
+                json!({"id": id, "metadata": m, "vector": projection.encode_vector(&p.vector())})
+            })
+            .collect::<Vec<Value>>();
 
-    if res.is_err() {
         let response = CustomResponse {
-            success: false,
-            data: "Error fetching metadata".to_string(),
-            code: 500,
+            success: true,
+            data: json!(res),
+            code: 200,
         };
-        return HttpResponse::InternalServerError().json(response);
+        return Ok(HttpResponse::Ok().json(response));
     }
 
-    let res = res.unwrap();
+    let response = CustomResponse {
+        success: true,
+        data: json!(res),
+        code: 200,
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[post("/fetch_partial")]
+pub async fn fetch_partial(
+    req: HttpRequest,
+    payload: Json<FetchModel>,
+) -> Result<HttpResponse, ApiError> {
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store");
+
+    let projection = payload.projection();
+    let ids = resolve_ids(store.get_ref(), &payload.id, &payload.external_id)?;
+
+    let indices = ids.iter().filter_map(|(idx, _)| *idx).collect::<Vec<u32>>();
+    let mut metadatas = store.get_metadatas_partial(&indices).into_iter();
+    let mut points = if projection.include_vector {
+        Some(store.get_points_partial(&indices).into_iter())
+    } else {
+        None
+    };
+
+    // each id gets its own result, so that a missing key does not fail the entire request
+    let res = ids
+        .into_iter()
+        .map(|(idx, external_id)| {
+            let mut result = json!({"success": true});
+            if let Some(external_id) = external_id {
+                result["external_id"] = json!(external_id);
+            }
+            let Some(idx) = idx else {
+                result["success"] = json!(false);
+                result["error"] = json!(DeserializeError::MissingKey.to_string());
+                return result;
+            };
+
+            result["id"] = json!(idx);
+            match metadatas.next().unwrap() {
+                Ok(m) => result["metadata"] = projection.apply(m),
+                Err(e) => {
+                    result["success"] = json!(false);
+                    result["error"] = json!(e.to_string());
+                }
+            }
+            match points.as_mut().map(|p| p.next().unwrap()) {
+                Some(Ok(p)) => result["vector"] = projection.encode_vector(&p.vector()),
+                Some(Err(e)) => {
+                    result["success"] = json!(false);
+                    result["error"] = json!(e.to_string());
+                }
+                None => {}
+            }
+            result
+        })
+        .collect::<Vec<Value>>();
 
     let response = CustomResponse {
         success: true,
         data: json!(res),
         code: 200,
     };
-    HttpResponse::Ok().json(response)
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[post("/delete")]
+pub async fn delete(
+    req: HttpRequest,
+    payload: Json<DeleteModel>,
+) -> Result<HttpResponse, ApiError> {
+    let cfg = Config::new();
+
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store");
+
+    if store.is_read_only() {
+        return Err(ApiError::ReadOnly);
+    }
+    let store = store.clone();
+    let node_cache = req
+        .app_data::<web::Data<NodeCache>>()
+        .expect("Error getting node cache")
+        .clone();
+
+    let payload = payload.into_inner();
+    // the lock is held by inserts for their whole graph build, so it is waited for off the async workers
+    let result = task::spawn_blocking(move || {
+        let ind = HNSW::new(16, 128, 0, None, store.clone());
+
+        let ids = resolve_ids(ind.db.get_ref(), &payload.id, &payload.external_id)?;
+        let (indices, mut missing) = split_missing(ids);
+
+        // deletes are not interleaved with inserts, whose graph flush would otherwise bring the nodes back
+        let _lock = store.lock_writes()?;
+        if store.is_shared() {
+            node_cache.sync(&cfg.contract_id, store.get_generation()?);
+        }
+        let node_map = node_cache.get_cache(cfg.contract_id.clone());
+        // nothing is deleted if a record is missing, in which case all the missing ones are reported
+        let res = if missing.is_empty() {
+            ind.mark_deleted(&indices, node_map).and_then(|()| {
+                node_cache.synced(&cfg.contract_id, store.bump_generation()?);
+                Ok(())
+            })
+        } else {
+            Err(DeserializeError::MissingKey)
+        };
+        match res {
+            Ok(()) => Ok(indices),
+            Err(DeserializeError::MissingKey) => {
+                for &idx in &indices {
+                    if let Err(DeserializeError::MissingKey) = ind.db.get_neighbor(0, idx as usize)
+                    {
+                        missing.add(idx);
+                    }
+                }
+                missing.into_result()?;
+                Err(DeserializeError::MissingKey.into())
+            }
+            Err(e) => Err(ApiError::from(e)),
+        }
+    })
+    .await;
+
+    let indices = result.expect("Error getting result")?;
+    Ok(HttpResponse::Ok().json(CustomResponse {
+        success: true,
+        data: json!(indices),
+        code: 200,
+    }))
+}
+
+#[post("/scroll")]
+pub async fn scroll(
+    req: HttpRequest,
+    payload: Json<ScrollModel>,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store")
+        .clone();
+
+    let payload = payload.into_inner();
+    let result = task::spawn_blocking(move || scroll_worker(payload, store)).await;
+
+    let res = result.expect("Error getting result")?;
+    Ok(HttpResponse::Ok().json(CustomResponse {
+        success: true,
+        data: res,
+        code: 200,
+    }))
 }
 
 #[post("/insert_vector")]
-pub async fn insert_vector(req: HttpRequest, payload: Json<InsertBatchModel>) -> HttpResponse {
+pub async fn insert_vector(
+    req: HttpRequest,
+    payload: Json<InsertBatchModel>,
+) -> Result<HttpResponse, ApiError> {
     let cfg = Config::new();
-    let cid = cfg.contract_id.clone();
 
-    let rocksdb_client = req
-        .app_data::<web::Data<RocksdbClient>>()
-        .expect("Error getting rocksdb client");
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store");
+
+    if store.is_read_only() {
+        return Err(ApiError::ReadOnly);
+    }
 
-    let rocksdb_client = rocksdb_client.clone();
+    let store = store.clone();
 
     let mut vectors = Vec::new();
     let mut metadata_batch = Vec::new();
+    let mut external_ids = Vec::new();
     for d in payload.data.iter() {
         vectors.push(d.vector.clone());
         metadata_batch.push(d.metadata.clone());
+        external_ids.push(d.external_id.clone());
     }
 
-    if vectors.len() > 2500 {
-        let response = CustomResponse {
-            success: false,
-            data: "Batch size should be smaller than 2500.".to_string(),
-            code: 401,
-        };
-        // TODO: fix status code
-        return HttpResponse::InternalServerError().json(response);
+    if vectors.len() > INSERT_MAX_BATCH_SIZE {
+        return Err(ApiError::BatchTooLarge {
+            size: vectors.len(),
+            max: INSERT_MAX_BATCH_SIZE,
+        });
     }
 
     let node_cache = req
@@ -147,149 +380,474 @@ pub async fn insert_vector(req: HttpRequest, payload: Json<InsertBatchModel>) ->
         .app_data::<web::Data<PointCache>>()
         .expect("Error getting point cache"); //Arc<DashMap<String, Point>> = Arc::new(DashMap::new());
 
-    let node_map = node_cache.get_cache(cid.clone()); //Arc<SynchronizedNodes> = Arc::new(SynchronizedNodes::new());
-    let point_map = point_cache.get_cache(cid.clone()); //Arc<DashMap<String, Point>> = Arc::new(DashMap::new());
-    let cid_clone = cid.clone();
+    let node_cache = node_cache.clone();
+    let point_cache = point_cache.clone();
     let result = task::spawn_blocking(move || {
         train_worker(
+            &cfg,
             vectors,
             metadata_batch,
-            node_map,
-            point_map,
-            rocksdb_client.clone(),
+            external_ids,
+            &node_cache,
+            point_cache,
+            store.clone(),
             10_000,
         )
     })
     .await;
 
-    let node_map = node_cache.get_cache(cid_clone); //Arc<SynchronizedNodes> = Arc::new(SynchronizedNodes::new());
-    node_map.reset();
+    let res = result.expect("Error getting result")?;
+    Ok(HttpResponse::Ok().json(CustomResponse {
+        success: true,
+        data: res,
+        code: 200,
+    }))
+}
 
-    let (res, code) = result.expect("Error getting result");
+#[post("/snapshot")]
+pub async fn snapshot(
+    req: HttpRequest,
+    payload: Json<SnapshotModel>,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
 
-    if code != 200 {
-        return HttpResponse::InternalServerError().json(CustomResponse {
-            success: false,
-            data: res,
-            code: code as u32,
-        });
-    }
-    return HttpResponse::Ok().json(CustomResponse {
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store")
+        .clone();
+
+    let name = payload.into_inner().name.unwrap_or_else(new_snapshot_name);
+    let snapshot_name = name.clone();
+    let result = task::spawn_blocking(move || store.create_snapshot(&snapshot_name)).await;
+
+    result
+        .expect("Error getting result")
+        .map_err(|e| snapshot_error(e, &name))?;
+    Ok(HttpResponse::Ok().json(CustomResponse {
         success: true,
-        data: "Values are successfully added to index.".to_string(),
+        data: name,
         code: 200,
-    });
+    }))
+}
+
+#[get("/snapshots")]
+pub async fn list_snapshots(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store");
+
+    let names = store.list_snapshots().map_err(|e| match e {
+        DeserializeError::Unsupported => ApiError::SnapshotsUnsupported,
+        e => e.into(),
+    })?;
+    Ok(HttpResponse::Ok().json(CustomResponse {
+        success: true,
+        data: names,
+        code: 200,
+    }))
+}
+
+#[post("/restore")]
+pub async fn restore(
+    req: HttpRequest,
+    payload: Json<RestoreModel>,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
+    let cfg = Config::new();
+    let store = req
+        .app_data::<web::Data<dyn VectorStore>>()
+        .expect("Error getting store")
+        .clone();
+    let node_cache = req
+        .app_data::<web::Data<NodeCache>>()
+        .expect("Error getting node cache")
+        .clone();
+    let point_cache = req
+        .app_data::<web::Data<PointCache>>()
+        .expect("Error getting point cache")
+        .clone();
+
+    let name = payload.into_inner().name;
+    let snapshot_name = name.clone();
+    // inserts wait for the restore, and start over from the restored index rather than the cached nodes
+    let result = task::spawn_blocking(move || {
+        let _lock = store.lock_writes()?;
+        let restored = store.restore_snapshot(&snapshot_name);
+        node_cache.invalidate(&cfg.contract_id);
+        point_cache.invalidate(&cfg.contract_id);
+        restored
+    })
+    .await;
+
+    result
+        .expect("Error getting result")
+        .map_err(|e| snapshot_error(e, &name))?;
+    Ok(HttpResponse::Ok().json(CustomResponse {
+        success: true,
+        data: name,
+        code: 200,
+    }))
+}
+
+/// Error of a failed operation on the snapshot with the given name.
+fn snapshot_error(e: DeserializeError, name: &str) -> ApiError {
+    match e {
+        DeserializeError::Unsupported => ApiError::SnapshotsUnsupported,
+        DeserializeError::AlreadyExists => ApiError::SnapshotExists(name.to_string()),
+        DeserializeError::MissingKey => ApiError::SnapshotNotFound(name.to_string()),
+        e => e.into(),
+    }
+}
+
+/// Pairs of an internal index and the external id it was requested with.
+type ResolvedIds = Vec<(Option<u32>, Option<String>)>;
+
+/// Resolves the requested ids to internal indices, internal ids first and then the external ids.
+///
+/// Each entry also has the external id it was requested with, if any. Unknown external ids resolve to `None`.
+fn resolve_ids(
+    store: &dyn VectorStore,
+    ids: &[u32],
+    external_ids: &Option<Vec<String>>,
+) -> Result<ResolvedIds, DeserializeError> {
+    let mut resolved = ids
+        .iter()
+        .map(|idx| (Some(*idx), None))
+        .collect::<ResolvedIds>();
+
+    if let Some(external_ids) = external_ids {
+        let indices = store.get_indices_by_external_ids(external_ids)?;
+        resolved.extend(
+            indices
+                .into_iter()
+                .zip(external_ids.iter().cloned().map(Some)),
+        );
+    }
+
+    Ok(resolved)
+}
+
+/// Records that were requested but do not exist.
+#[derive(Default)]
+struct MissingRecords {
+    ids: Vec<u32>,
+    external_ids: Vec<String>,
+}
+
+impl MissingRecords {
+    fn add(&mut self, idx: u32) {
+        self.ids.push(idx);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.external_ids.is_empty()
+    }
+
+    /// [`ApiError::RecordsNotFound`] if any record is missing.
+    fn into_result(self) -> Result<(), ApiError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::RecordsNotFound {
+                ids: self.ids,
+                external_ids: self.external_ids,
+            })
+        }
+    }
+}
+
+/// Splits resolved ids into the internal indices and the external ids that are not known.
+fn split_missing(ids: ResolvedIds) -> (Vec<u32>, MissingRecords) {
+    let mut indices = Vec::with_capacity(ids.len());
+    let mut missing = MissingRecords::default();
+    for (idx, external_id) in ids {
+        match (idx, external_id) {
+            (Some(idx), _) => indices.push(idx),
+            (None, Some(external_id)) => missing.external_ids.push(external_id),
+            (None, None) => {}
+        }
+    }
+    (indices, missing)
 }
 
-fn ef_helper(ef: Option<usize>) -> usize {
+fn scroll_worker(
+    payload: ScrollModel,
+    store: Data<dyn VectorStore>,
+) -> Result<Value, DeserializeError> {
+    let limit = payload.limit();
+    let projection = payload.projection();
+
+    // read one more than the limit to see if there are any records left
+    let mut page = Vec::with_capacity(limit + 1);
+    for item in store.iter_metadatas(payload.cursor) {
+        let (idx, metadata) = item?;
+        if let Some(filter) = &payload.filter {
+            if !matches_filter(filter, &metadata) {
+                continue;
+            }
+        }
+
+        page.push((idx, metadata));
+        if page.len() > limit {
+            break;
+        }
+    }
+
+    let next_cursor = if page.len() > limit {
+        page.pop();
+        page.last().map(|(idx, _)| *idx)
+    } else {
+        None
+    };
+
+    let indices = page.iter().map(|(idx, _)| *idx).collect::<Vec<u32>>();
+    let external_ids = store.get_external_ids(&indices)?;
+
+    let mut records = page
+        .into_iter()
+        .zip(external_ids)
+        .map(|((idx, metadata), external_id)| {
+            let mut record = json!({"id": idx});
+            if let Some(external_id) = external_id {
+                record["external_id"] = json!(external_id);
+            }
+            if projection.needs_metadata() {
+                record["metadata"] = projection.apply(metadata);
+            }
+            record
+        })
+        .collect::<Vec<Value>>();
+
+    if projection.include_vector {
+        let points = store.get_points(&indices)?;
+        for (record, point) in records.iter_mut().zip(points) {
+            record["vector"] = projection.encode_vector(&point.vector());
+        }
+    }
+
+    Ok(json!({"records": records, "next_cursor": next_cursor}))
+}
+
+/// Sets the quantizer of the index, if it has been trained, so that the index is traversed over quantized codes.
+pub fn prepare_quantizer(
+    ind: &mut HNSW,
+    point_cache: &PointCache,
+    cid: &str,
+) -> Result<(), DeserializeError> {
+    let quantizer = match point_cache.get_quantizer(cid) {
+        Some(quantizer) => Some(quantizer),
+        None => ind.db.get_quantizer()?.map(|quantizer| {
+            let quantizer = Arc::new(quantizer);
+            point_cache.set_quantizer(cid, quantizer.clone());
+            quantizer
+        }),
+    };
+
+    if let Some(quantizer) = quantizer {
+        ind.set_quantizer(quantizer, point_cache.get_code_cache(cid.to_string()));
+    }
+    Ok(())
+}
+
+/// Trains a quantizer over the first points of the index, and stores the codes of all points up to `datasize`.
+pub fn train_quantizer(
+    store: &dyn VectorStore,
+    options: &IndexOptions,
+    datasize: usize,
+    batch_size: usize,
+) -> Result<Quantizer, DeserializeError> {
+    let sample = (0..options.train_size as u32).collect::<Vec<u32>>();
+    let vectors = store
+        .get_points(&sample)?
+        .into_iter()
+        .map(|p| p.vector().to_vec())
+        .collect::<Vec<Vec<f32>>>();
+    let quantizer = Quantizer::train(options, &vectors).ok_or(DeserializeError::InvalidForm)?;
+
+    for start in (0..datasize).step_by(batch_size) {
+        let indices = (start as u32..datasize.min(start + batch_size) as u32).collect::<Vec<u32>>();
+        let codes = store
+            .get_points(&indices)?
+            .iter()
+            .map(|p| quantizer.encode(&p.vector()))
+            .collect::<Vec<Vec<u8>>>();
+        store.add_codes_batch(codes, start)?;
+    }
+
+    store.set_quantizer(&quantizer)?;
+    Ok(quantizer)
+}
+
+pub fn ef_helper(ef: Option<usize>) -> usize {
     let level = ef.clone().unwrap_or(1);
     20 + (level * 30)
 }
 
-fn train_worker(
+/// Inserts a batch of vectors, building the graph with the settings of the given config.
+#[allow(clippy::too_many_arguments)]
+pub fn train_worker(
+    cfg: &Config,
     vectors: Vec<Vec<f32>>,
     metadata_batch: Vec<Value>,
-    node_map: Arc<SynchronizedNodes>,
-    point_map: Cache<String, Point>,
-    rocksdb_client: Data<RocksdbClient>,
+    external_ids: Vec<Option<String>>,
+    node_cache: &NodeCache,
+    point_cache: Data<PointCache>,
+    store: Data<dyn VectorStore>,
     batch_size: usize,
-) -> (String, u16) {
-    let ind = HNSW::new(16, 128, ef_helper(Some(1)), None, rocksdb_client.clone());
+) -> Result<InsertResponse, ApiError> {
+    let cid = cfg.contract_id.clone();
+    let point_map = point_cache.get_cache(cid.clone());
+    let mut ind = HNSW::new(16, 128, ef_helper(Some(1)), None, store.clone());
+    ind.set_neighbor_selection(cfg.extend_candidates, cfg.keep_pruned_connections);
+    if let Some(seed) = cfg.rng_seed {
+        ind.set_rng_seed(seed);
+    }
+
+    // inserts of an index are serialized, as each one builds on the graph and datasize left by the previous one
+    let _lock = store.lock_writes()?;
+    // other servers of a shared store may have written since the nodes were cached, which the graph flush would revert
+    if store.is_shared() {
+        node_cache.sync(&cid, store.get_generation()?);
+    }
+    let node_map = node_cache.get_cache(cid.clone());
 
     let mut ds = 0;
-    let nl = ind.db.get_num_layers();
     let num_layers = Arc::new(AtomicUsize::new(0));
 
-    if nl.is_err() {
-        error!("{}", nl.err().unwrap());
-        ind.db.set_datasize(0).expect("Error setting datasize");
-    } else {
-        let nl_value = nl.expect("").clone();
-        ds = ind.db.get_datasize().expect("Error getting datasize");
+    match ind.db.get_num_layers() {
+        Ok(nl_value) => {
+            ds = ind.db.get_datasize()?;
+            num_layers.store(nl_value, Ordering::SeqCst);
+        }
+        // a new index has no layers yet, and its options are decided once, when it is created
+        Err(DeserializeError::MissingKey) => {
+            ind.db.set_datasize(0)?;
+            let options =
+                IndexOptions::from_config(cfg).map_err(|e| ApiError::Internal(e.to_string()))?;
+            ind.db.set_options(&options)?;
+        }
+        Err(e) => return Err(e.into()),
+    }
 
-        let res = num_layers.fetch_update(Ordering::SeqCst, Ordering::Relaxed, |x| Some(nl_value));
+    let options = ind.db.get_options()?.unwrap_or_default();
+    prepare_quantizer(&mut ind, &point_cache, &cid)?;
+    // vectors of another dimension would be linked with meaningless distances, or fail to be quantized
+    ind.check_vectors(&vectors, point_map.clone())?;
 
-        if res.is_err() {
-            error!("{}", res.err().unwrap());
-            return ("Error setting num layers, atomic".to_string(), 500);
+    // upsert by external ids, where the new record replaces the existing one
+    let mut upserted: HashMap<String, u32> = HashMap::new();
+    let mut replaced_in_batch = Vec::new();
+    for (i, external_id) in external_ids.into_iter().enumerate() {
+        if let Some(external_id) = external_id {
+            // if an external id is repeated within the batch, the last one wins
+            if let Some(prev) = upserted.insert(external_id, (ds + i) as u32) {
+                replaced_in_batch.push(prev);
+            }
         }
     }
+    let upserted = upserted.into_iter().collect::<Vec<(String, u32)>>();
+    let keys = upserted
+        .iter()
+        .map(|(external_id, _)| external_id.clone())
+        .collect::<Vec<String>>();
+    let mut replaced = ind
+        .db
+        .get_indices_by_external_ids(&keys)?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<u32>>();
+    // replaced nodes stay hidden in the node map, so that the graph flush does not make them visible again
+    let tombstones = ind.hide_nodes(&replaced, node_map.clone())?;
+    replaced.extend_from_slice(&replaced_in_batch);
 
-    let r1 = ind.db.add_points_batch(&vectors, ds);
-    let r2 = ind.db.set_metadata_batch(metadata_batch, ds);
-    let r3 = ind.db.set_datasize(ds + vectors.len());
+    // the new records and the deletion of the replaced ones are written at once, before the graph
+    let point_write = Instant::now();
+    ind.db.add_records(RecordBatch {
+        start_idx: ds,
+        vectors: &vectors,
+        precision: options.precision,
+        metadata: metadata_batch,
+        external_ids: upserted,
+        replaced,
+        tombstones,
+    })?;
+    node_cache.synced(&cid, ind.db.bump_generation()?);
 
-    if r1.is_err() || r2.is_err() || r3.is_err() {
-        error!("Error adding points as batch");
-        return ("Error adding points as batch".to_string(), 500);
+    if options.quantization.is_some() {
+        if let Some(quantizer) = point_cache.get_quantizer(&cid) {
+            let codes = vectors
+                .iter()
+                .map(|v| quantizer.encode(v))
+                .collect::<Vec<Vec<u8>>>();
+            ind.db.add_codes_batch(codes, ds)?;
+        } else if ds + vectors.len() >= options.train_size {
+            // the whole index is quantized once there are enough points to train with
+            let quantizer = Arc::new(train_quantizer(
+                ind.db.get_ref(),
+                &options,
+                ds + vectors.len(),
+                batch_size,
+            )?);
+            point_cache.set_quantizer(&cid, quantizer.clone());
+            ind.set_quantizer(quantizer, point_cache.get_code_cache(cid.clone()));
+        }
     }
+    let point_write_ms = point_write.elapsed().as_millis() as u64;
 
     let epa = Arc::new(AtomicIsize::new(-1));
-
-    let ep = ind.db.get_ep();
-
-    if ep.is_ok() {
-        let ep_value = ep.expect("").clone();
-        let res = epa.fetch_update(Ordering::SeqCst, Ordering::Relaxed, |x| {
-            // Your update logic here
-            Some(ep_value as isize)
-        });
-        if res.is_err() {
-            error!("{}", res.err().unwrap());
-            return ("Error setting ep, atomic".to_string(), 500);
-        }
+    match ind.db.get_ep() {
+        Ok(ep_value) => epa.store(ep_value as isize, Ordering::SeqCst),
+        Err(DeserializeError::MissingKey) => {}
+        Err(e) => return Err(e.into()),
     }
+
+    let graph_build = Instant::now();
     let pool = rayon::ThreadPoolBuilder::new()
         .thread_name(|idx| format!("hnsw-build-{idx}"))
         .num_threads(8)
         .build()
         .expect("Error building threadpool");
 
-    if ds < SINGLE_THREADED_HNSW_BUILD_THRESHOLD {
-        let iter_ind = vectors.len().min(SINGLE_THREADED_HNSW_BUILD_THRESHOLD - ds);
-        for i in 0..iter_ind {
-            ind.insert_w_preset(
-                ds + i,
-                node_map.clone(),
-                point_map.clone(),
-                num_layers.clone(),
-                epa.clone(),
-            )
-            .expect("Error inserting");
-        }
-
-        if SINGLE_THREADED_HNSW_BUILD_THRESHOLD < (vectors.len() + ds) {
-            pool.install(|| {
-                (iter_ind..vectors.len())
-                    .into_par_iter()
-                    .try_for_each(|item| {
-                        ind.insert_w_preset(
-                            ds + item,
-                            node_map.clone(),
-                            point_map.clone(),
-                            num_layers.clone(),
-                            epa.clone(),
-                        )
-                    })
-            })
-            .expect("Error inserting");
-        }
+    // the first points are inserted one at a time, as the graph is too small to insert them concurrently;
+    // all of them are in a serial build, whose graph does not depend on the scheduling of threads
+    let iter_ind = if cfg.serial_build {
+        vectors.len()
     } else {
-        pool.install(|| {
-            (0..vectors.len()).into_par_iter().try_for_each(|item| {
+        vectors
+            .len()
+            .min(SINGLE_THREADED_HNSW_BUILD_THRESHOLD.saturating_sub(ds))
+    };
+    (0..iter_ind).try_for_each(|i| {
+        ind.insert_w_preset(
+            ds + i,
+            node_map.clone(),
+            point_map.clone(),
+            num_layers.clone(),
+            epa.clone(),
+        )
+    })?;
+    pool.install(|| {
+        (iter_ind..vectors.len())
+            .into_par_iter()
+            .try_for_each(|item| {
                 ind.insert_w_preset(
-                    item + ds,
+                    ds + item,
                     node_map.clone(),
                     point_map.clone(),
                     num_layers.clone(),
                     epa.clone(),
                 )
             })
-        })
-        .expect("Error inserting");
-    }
+    })?;
+
+    // records replaced within the batch only have nodes once the graph is built
+    ind.hide_nodes(&replaced_in_batch, node_map.clone())?;
+
+    let graph_build_ms = graph_build.elapsed().as_millis() as u64;
 
     //replicate neighbors
+    let neighbor_flush = Instant::now();
     let values: Vec<LayerNode> = node_map
         .clone()
         .map
@@ -300,47 +858,111 @@ fn train_worker(
     let ep_value = epa.clone().load(Ordering::Relaxed);
     let num_layers = num_layers.clone().load(Ordering::Relaxed);
 
-    let batch_size: usize = batch_size;
-
     for chunk in values.chunks(batch_size) {
-        let r1 = ind.db.upsert_neighbors(chunk.to_vec());
-        if r1.is_err() {
-            error!("{}", r1.err().unwrap());
-            return ("Error writing batch to blockchain".to_string(), 500);
-        }
+        ind.db.upsert_neighbors(chunk.to_vec())?;
     }
-    let r2 = ind.db.set_ep(ep_value as usize, false);
-    let r3 = ind.db.set_num_layers(num_layers, false);
+    ind.db.set_ep(ep_value as usize)?;
+    ind.db.set_num_layers(num_layers)?;
+    node_cache.synced(&cid, ind.db.bump_generation()?);
 
-    if r2.is_err() || r3.is_err() {
-        error!("Error writing to blockchain");
-        return ("Error writing batch to blockchain".to_string(), 500);
-    }
-    return ("Values are successfully added to index.".to_string(), 200);
+    // the node map is shared by the inserts of an index, so it is trimmed while none of them builds on it
+    node_map.reset();
+
+    Ok(InsertResponse {
+        ids: (ds..ds + vectors.len()).map(|idx| idx as u32).collect(),
+        datasize: ds + vectors.len(),
+        timings: InsertTimings {
+            point_write_ms,
+            graph_build_ms,
+            neighbor_flush_ms: neighbor_flush.elapsed().as_millis() as u64,
+        },
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::memory_client::MemoryClient;
     use actix_web::{http::header::ContentType, test, App};
     use rand::{self, Rng};
-    use simple_home_dir::home_dir;
-    use std::env;
 
-    fn prepare_env() -> () {
-        const CONTRACT_ID: &str = "WbcY2a-KfDpk7fsgumUtLC2bu4NQcVzNlXWi13fPMlU";
+    /// An in-memory index of random vectors, along with the caches it is served with.
+    fn prepare_store(
+        size: usize,
+        dim: usize,
+    ) -> (Data<dyn VectorStore>, Data<NodeCache>, Data<PointCache>) {
+        let store: Data<dyn VectorStore> =
+            Data::from(Arc::new(MemoryClient::new()) as Arc<dyn VectorStore>);
+        let node_cache = web::Data::new(NodeCache::new());
+        let point_cache = web::Data::new(PointCache::new());
+
+        let mut rng = rand::thread_rng();
+        let vectors = (0..size)
+            .map(|_| (0..dim).map(|_| rng.gen()).collect())
+            .collect::<Vec<Vec<f32>>>();
+        let metadata = (0..size).map(|i| json!({ "i": i })).collect();
+        let cfg = Config::new();
+        let cid = cfg.contract_id.clone();
+        train_worker(
+            &cfg,
+            vectors,
+            metadata,
+            vec![None; size],
+            &node_cache,
+            point_cache.clone(),
+            store.clone(),
+            10_000,
+        )
+        .unwrap();
+        node_cache.get_cache(cid).reset();
 
-        env::set_var("CONTRACT_ID", CONTRACT_ID);
-        let mut rocksdb_path = home_dir().unwrap().to_str().unwrap().to_owned();
-        rocksdb_path.push_str("/.dria/data/");
-        rocksdb_path.push_str(CONTRACT_ID);
-        env::set_var("ROCKSDB_PATH", rocksdb_path);
+        (store, node_cache, point_cache)
     }
 
-    fn prepare_rocksdb() -> Data<RocksdbClient> {
-        let cfg = Config::new();
-        let rocksdb_client = RocksdbClient::new(cfg.contract_id.clone()).unwrap();
-        web::Data::new(rocksdb_client)
+    #[actix_web::test]
+    async fn test_seeded_train_worker() {
+        let mut cfg = Config::new();
+        cfg.rng_seed = Some(7);
+        cfg.serial_build = true;
+
+        let mut rng = rand::thread_rng();
+        let vectors = (0..300)
+            .map(|_| (0..8).map(|_| rng.gen()).collect())
+            .collect::<Vec<Vec<f32>>>();
+
+        // two servers building the same batches write the same index
+        let build = || {
+            let store: Data<dyn VectorStore> =
+                Data::from(Arc::new(MemoryClient::new()) as Arc<dyn VectorStore>);
+            let point_cache = web::Data::new(PointCache::new());
+            for batch in vectors.chunks(150) {
+                train_worker(
+                    &cfg,
+                    batch.to_vec(),
+                    vec![json!({}); batch.len()],
+                    vec![None; batch.len()],
+                    &NodeCache::new(),
+                    point_cache.clone(),
+                    store.clone(),
+                    10_000,
+                )
+                .unwrap();
+            }
+            store
+        };
+        let (a, b) = (build(), build());
+
+        assert_eq!(a.get_ep().unwrap(), b.get_ep().unwrap());
+        let num_layers = a.get_num_layers().unwrap();
+        assert_eq!(num_layers, b.get_num_layers().unwrap());
+        for layer in 0..num_layers {
+            for idx in 0..vectors.len() {
+                assert_eq!(
+                    a.get_neighbor(layer, idx).ok(),
+                    b.get_neighbor(layer, idx).ok()
+                );
+            }
+        }
     }
 
     #[actix_web::test]
@@ -360,13 +982,10 @@ mod tests {
 
     #[actix_web::test]
     async fn test_fetch_and_query() {
-        prepare_env();
-        let rocksdb_client = prepare_rocksdb();
-        let node_cache = web::Data::new(NodeCache::new());
-        let point_cache = web::Data::new(PointCache::new());
+        let (store, node_cache, point_cache) = prepare_store(100, 768);
         let app = test::init_service(
             App::new()
-                .app_data(rocksdb_client)
+                .app_data(store)
                 .app_data(node_cache)
                 .app_data(point_cache)
                 .configure(|conf| {
@@ -395,4 +1014,436 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn test_insert_vector() {
+        let store: Data<dyn VectorStore> =
+            Data::from(Arc::new(MemoryClient::new()) as Arc<dyn VectorStore>);
+        let app = test::init_service(
+            App::new()
+                .app_data(store)
+                .app_data(web::Data::new(NodeCache::new()))
+                .app_data(web::Data::new(PointCache::new()))
+                .configure(|conf| {
+                    conf.service(insert_vector);
+                }),
+        )
+        .await;
+
+        // ids of a batch continue from the previous one
+        for (size, ids, datasize) in [(3, json!([0, 1, 2]), 3), (2, json!([3, 4]), 5)] {
+            let data = (0..size)
+                .map(|i| json!({ "vector": vec![i as f32 + 1.0, 1.0], "metadata": {} }))
+                .collect::<Vec<Value>>();
+            let req = test::TestRequest::post()
+                .uri("/insert_vector")
+                .set_json(json!({ "data": data }))
+                .to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["data"]["ids"], ids);
+            assert_eq!(resp["data"]["datasize"], datasize);
+            assert!(resp["data"]["timings"]["graph_build_ms"].is_u64());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_upsert() {
+        let (store, node_cache, point_cache) = prepare_store(10, 4);
+        let app = test::init_service(
+            App::new()
+                .app_data(store.clone())
+                .app_data(node_cache)
+                .app_data(point_cache)
+                .configure(|conf| {
+                    conf.service(insert_vector)
+                        .service(fetch)
+                        .service(delete)
+                        .service(query);
+                }),
+        )
+        .await;
+
+        // the last record of a repeated external id wins within a batch, and across batches
+        let batches = [
+            json!([
+                { "vector": [1.0, 0.0, 0.0, 0.0], "metadata": { "v": 1 }, "external_id": "a" },
+                { "vector": [0.0, 1.0, 0.0, 0.0], "metadata": { "v": 2 }, "external_id": "b" },
+                { "vector": [0.0, 0.0, 1.0, 0.0], "metadata": { "v": 3 }, "external_id": "a" },
+            ]),
+            json!([
+                { "vector": [0.0, 0.0, 0.0, 1.0], "metadata": { "v": 4 }, "external_id": "b" },
+            ]),
+        ];
+        for data in batches {
+            let req = test::TestRequest::post()
+                .uri("/insert_vector")
+                .set_json(json!({ "data": data }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+        }
+        for idx in [10, 11] {
+            assert!(!store.get_neighbor(0, idx).unwrap().visible);
+        }
+        assert_eq!(
+            store
+                .get_indices_by_external_ids(&["a".into(), "b".into()])
+                .unwrap(),
+            vec![Some(12), Some(13)]
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/fetch")
+            .set_json(json!({ "external_id": ["a", "b"] }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"], json!([{ "v": 3 }, { "v": 4 }]));
+
+        let req = test::TestRequest::post()
+            .uri("/fetch")
+            .set_json(json!({ "id": [11] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        // replaced records are not in the results, which carry the external ids
+        for (vector, id, external_id) in [
+            ([0.0, 0.0, 1.0, 0.0], 12, "a"),
+            ([0.0, 0.0, 0.0, 1.0], 13, "b"),
+        ] {
+            for exact in [false, true] {
+                let req = test::TestRequest::post()
+                    .uri("/query")
+                    .set_json(json!({ "vector": vector, "top_n": 20, "exact": exact }))
+                    .to_request();
+                let resp: Value = test::call_and_read_body_json(&app, req).await;
+                let results = resp["data"].as_array().unwrap();
+                assert_eq!(results.len(), 12);
+                assert!(results
+                    .iter()
+                    .all(|r| r["id"] != json!(10) && r["id"] != json!(11)));
+                let result = results.iter().find(|r| r["id"] == json!(id)).unwrap();
+                assert_eq!(result["external_id"], json!(external_id));
+            }
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/delete")
+            .set_json(json!({ "external_id": ["a"] }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"], json!([12]));
+        assert!(!store.get_neighbor(0, 12).unwrap().visible);
+
+        let req = test::TestRequest::post()
+            .uri("/fetch")
+            .set_json(json!({ "external_id": ["a", "b"] }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["details"], json!({ "ids": [], "external_ids": ["a"] }));
+    }
+
+    #[actix_web::test]
+    async fn test_exact_query() {
+        let (store, node_cache, point_cache) = prepare_store(50, 8);
+        let cid = Config::new().contract_id;
+        let vector = store.get_points(&[3]).unwrap()[0].vector().to_vec();
+        HNSW::new(16, 128, 0, None, store.clone())
+            .mark_deleted(&[7], node_cache.get_cache(cid))
+            .unwrap();
+        let deleted = store.get_points(&[7]).unwrap()[0].vector().to_vec();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(store)
+                .app_data(node_cache)
+                .app_data(point_cache)
+                .configure(|conf| {
+                    conf.service(query);
+                }),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_json(json!({ "vector": vector, "top_n": 5, "exact": true }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let results = resp["data"].as_array().unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0]["id"], json!(3));
+        assert_eq!(results[0]["metadata"], json!({ "i": 3 }));
+        let scores = results
+            .iter()
+            .map(|r| r["score"].as_f64().unwrap())
+            .collect::<Vec<f64>>();
+        assert!(scores.windows(2).all(|w| w[0] >= w[1]));
+
+        // deleted points are not in the results
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_json(json!({ "vector": deleted, "top_n": 5, "exact": true }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let results = resp["data"].as_array().unwrap();
+        assert!(results.iter().all(|r| r["id"] != json!(7)));
+    }
+
+    #[actix_web::test]
+    async fn test_query_budget() {
+        let (store, node_cache, point_cache) = prepare_store(300, 8);
+        let app = test::init_service(
+            App::new()
+                .app_data(store)
+                .app_data(node_cache)
+                .app_data(point_cache)
+                .configure(|conf| {
+                    conf.service(query);
+                }),
+        )
+        .await;
+
+        let vector = vec![0.5; 8];
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_json(json!({ "vector": vector, "top_n": 10, "ef": 200 }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["partial"], json!(false));
+        assert_eq!(resp["data"].as_array().unwrap().len(), 10);
+
+        // the search stops after comparing a few points, with the best of them
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_json(json!({ "vector": vector, "top_n": 10, "max_visits": 5 }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["partial"], json!(true));
+        assert!(resp["data"].as_array().unwrap().len() <= 6);
+
+        for body in [
+            json!({ "vector": vector, "top_n": 10, "ef": 0 }),
+            json!({ "vector": vector, "top_n": 10, "ef": 100_000 }),
+            json!({ "vector": vector, "top_n": 10, "timeout_ms": 0 }),
+            json!({ "vector": vector, "top_n": 5000 }),
+            json!({ "vector": vector, "top_n": 10, "level": 9 }),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/query")
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_client_error());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_explain_query() {
+        let (store, node_cache, point_cache) = prepare_store(100, 8);
+        let app = test::init_service(
+            App::new()
+                .app_data(store)
+                .app_data(node_cache)
+                .app_data(point_cache)
+                .configure(|conf| {
+                    conf.service(query);
+                }),
+        )
+        .await;
+
+        let vector = vec![0.5; 8];
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_json(json!({ "vector": vector, "top_n": 5, "explain": true }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let trace = &resp["trace"];
+        let layers = trace["layers"].as_array().unwrap();
+        assert_eq!(layers.last().unwrap()["level"], json!(0));
+        let visited = trace["visited"].as_u64().unwrap();
+        assert!(visited > 0);
+        assert_eq!(
+            layers
+                .iter()
+                .map(|layer| layer["visited"].as_u64().unwrap())
+                .sum::<u64>(),
+            visited
+        );
+        assert_eq!(
+            trace["distance_computations"].as_u64().unwrap(),
+            visited + 1
+        );
+        // each distance is to a vector from either the cache or the store
+        let cache = &trace["cache"];
+        assert!(
+            cache["vector_hits"].as_u64().unwrap() + cache["vector_reads"].as_u64().unwrap()
+                > visited
+        );
+        assert!(trace["timings"]["base_layer_us"].is_u64());
+
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_json(json!({ "vector": vector, "top_n": 5 }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert!(resp.get("trace").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_query_errors() {
+        let empty: Data<dyn VectorStore> =
+            Data::from(Arc::new(MemoryClient::new()) as Arc<dyn VectorStore>);
+        let (store, node_cache, point_cache) = prepare_store(20, 8);
+        for (store, vector, status) in [(empty, vec![0.5; 8], 404), (store, vec![0.5; 4], 400)] {
+            let app = test::init_service(
+                App::new()
+                    .app_data(store)
+                    .app_data(node_cache.clone())
+                    .app_data(point_cache.clone())
+                    .configure(|conf| {
+                        conf.service(query);
+                    }),
+            )
+            .await;
+
+            for exact in [false, true] {
+                let req = test::TestRequest::post()
+                    .uri("/query")
+                    .set_json(json!({ "vector": vector, "top_n": 5, "exact": exact }))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status().as_u16(), status);
+                let resp: Value = test::read_body_json(resp).await;
+                assert_eq!(resp["code"], status);
+                let code = if status == 404 {
+                    "empty_index"
+                } else {
+                    "dimension_mismatch"
+                };
+                assert_eq!(resp["error"], code);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_insert_dimension_mismatch() {
+        let empty: Data<dyn VectorStore> =
+            Data::from(Arc::new(MemoryClient::new()) as Arc<dyn VectorStore>);
+        let (store, node_cache, point_cache) = prepare_store(5, 4);
+        // a new index takes the dimension of the first vector
+        for (store, data) in [
+            (
+                empty,
+                json!([{ "vector": [1.0, 2.0], "metadata": {} }, { "vector": [1.0, 2.0, 3.0], "metadata": {} }]),
+            ),
+            (
+                store,
+                json!([{ "vector": [1.0, 2.0, 3.0], "metadata": {} }]),
+            ),
+        ] {
+            let datasize = store.get_datasize().unwrap_or(0);
+            let app = test::init_service(
+                App::new()
+                    .app_data(store.clone())
+                    .app_data(node_cache.clone())
+                    .app_data(point_cache.clone())
+                    .configure(|conf| {
+                        conf.service(insert_vector);
+                    }),
+            )
+            .await;
+
+            let req = test::TestRequest::post()
+                .uri("/insert_vector")
+                .set_json(json!({ "data": data }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), 400);
+            let resp: Value = test::read_body_json(resp).await;
+            assert_eq!(resp["error"], "dimension_mismatch");
+            assert_eq!(store.get_datasize().unwrap_or(0), datasize);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_error_codes() {
+        let (store, node_cache, point_cache) = prepare_store(5, 4);
+        let app = test::init_service(
+            App::new()
+                .app_data(store)
+                .app_data(node_cache)
+                .app_data(point_cache)
+                .configure(|conf| {
+                    conf.service(fetch).service(delete).service(insert_vector);
+                }),
+        )
+        .await;
+
+        for uri in ["/fetch", "/delete"] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(json!({ "id": [1, 7], "external_id": ["a"] }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), 404);
+            let resp: Value = test::read_body_json(resp).await;
+            assert_eq!(resp["error"], "records_not_found");
+            assert_eq!(
+                resp["details"],
+                json!({ "ids": [7], "external_ids": ["a"] })
+            );
+        }
+
+        let data =
+            vec![json!({ "vector": vec![0.5; 4], "metadata": {} }); INSERT_MAX_BATCH_SIZE + 1];
+        let req = test::TestRequest::post()
+            .uri("/insert_vector")
+            .set_json(json!({ "data": data }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 413);
+        let resp: Value = test::read_body_json(resp).await;
+        assert_eq!(resp["error"], "batch_too_large");
+        assert_eq!(resp["details"]["max"], INSERT_MAX_BATCH_SIZE);
+
+        let req = test::TestRequest::post()
+            .uri("/fetch")
+            .set_json(json!({ "id": [1] }))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert!(resp.get("error").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_scroll() {
+        let (store, _, _) = prepare_store(5, 4);
+        let app = test::init_service(App::new().app_data(store).configure(|conf| {
+            conf.service(scroll);
+        }))
+        .await;
+
+        // pages are in the order of ids, and the last one has no cursor
+        let mut cursor = Value::Null;
+        for ids in [json!([0, 1]), json!([2, 3]), json!([4])] {
+            let req = test::TestRequest::post()
+                .uri("/scroll")
+                .set_json(json!({ "limit": 2, "cursor": cursor }))
+                .to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["success"], json!(true));
+            let records = resp["data"]["records"].as_array().unwrap();
+            let page = records.iter().map(|r| r["id"].clone()).collect::<Vec<_>>();
+            assert_eq!(json!(page), ids);
+            cursor = resp["data"]["next_cursor"].clone();
+        }
+        assert!(cursor.is_null());
+
+        // invalid limit
+        let req = test::TestRequest::post()
+            .uri("/scroll")
+            .set_json(json!({ "limit": 0 }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_client_error());
+    }
 }